@@ -21,8 +21,13 @@ extern crate lazy_static;
 /// it is unused.
 pub const HEAP_START: usize = 0o_000_001_000_000_0000;
 
-/// Size of the heap
-pub const HEAP_SIZE: usize = 100 * 1024;
+/// Size of the heap. The six virtual consoles alone take about 165 KiB
+/// of it: 100 lines of scrollback each (a `VecDeque` rounds that up to
+/// 128 lines of 160 bytes), plus a copy of the live screen and, for the
+/// ones in the background, a screen of their own. 1 MiB leaves room for
+/// everything else. It is mapped page by page in `memory::init()`, well
+/// inside the 1 GiB before the kernel stacks.
+pub const HEAP_SIZE: usize = 1024 * 1024;
 
 // Create a static reference to the heap after
 // compile-time using lazy_static
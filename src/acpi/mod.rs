@@ -147,13 +147,12 @@ pub fn get_ioapic_addr(rsdt: &'static sdt::RSDT) -> Option<u32> {
 
 
 
-/// Print MADT table
-/// debug function, should be removed for end product
+/// Log the MADT table, at the debug level
 pub unsafe fn print_madt(madt: &'static sdt::MADT) {
-    println!("sdt::MADT data:");
-    println!("Length: 0x{:x}", madt.header.length);
-    println!("LCA: 0x{:x}", madt.local_ctrl);
-    println!("Flags: 0x{:x}", madt.flags);
+    debug!("sdt::MADT data:");
+    debug!("Length: 0x{:x}", madt.header.length);
+    debug!("LCA: 0x{:x}", madt.local_ctrl);
+    debug!("Flags: 0x{:x}", madt.flags);
 
     // Get first byte address
     let start: *const u8 = &madt.first_intctr;
@@ -172,8 +171,8 @@ pub unsafe fn print_madt(madt: &'static sdt::MADT) {
 
         if cur_head.entry_type == 0b00 {
             let lapic = &*(cur_addr as *const apic::LAPICEntry);
-            println!("LAPIC PID: {:x}, ID {:x}, FLAGS {:x}",
-                     lapic.acpi_proc_id, lapic.apic_id, lapic.flags);
+            debug!("LAPIC PID: {:x}, ID {:x}, FLAGS {:x}",
+                   lapic.acpi_proc_id, lapic.apic_id, lapic.flags);
         }
 
         i += cur_head.record_length as u32;
@@ -185,19 +184,19 @@ pub fn test_rsdt(rsdt: &'static sdt::RSDT) {
 
 
     if let Some(madt) = unsafe { sdt::load_madt(rsdt) } {
-        println!("Loaded MADT");
+        debug!("Loaded MADT");
         unsafe { print_madt(&madt); }
 
         if let Some(ioapic) = unsafe { apic::load_ioapic_entry(&madt) } {
-            println!("Loaded I/O APIC!");
-            println!("ID: {:x}, Address: {:x}, Reserved {:x}, GSIB: {:x}",
-                    ioapic.id, ioapic.address, ioapic.reserved, ioapic.gsib);
+            debug!("Loaded I/O APIC!");
+            debug!("ID: {:x}, Address: {:x}, Reserved {:x}, GSIB: {:x}",
+                   ioapic.id, ioapic.address, ioapic.reserved, ioapic.gsib);
 
         } else {
-            println!("Not loaded ioapic D:");
+            warn!("Not loaded ioapic D:");
         }
     } else {
-        println!("Not loaded madt!");
+        warn!("Not loaded madt!");
     }
 
 }
@@ -123,7 +123,7 @@ pub unsafe fn load_acpisdt_header(address: usize) -> &'static ACPISDTHeader {
 unsafe fn sum_bytes(start: usize, len: usize) -> u8 {
     let mut sum: u32 = 0;
 
-    println!("length: {}", len);
+    trace!("checksum over {} bytes at {:#x}", len, start);
     for i in start..(start + len) {
         let current: u32 = *(i as *const u32) & 0xFF;
         sum = (sum + current) & 0xFF;
@@ -0,0 +1,50 @@
+;;; This file contains the kernel thread context switch, and the
+;;; trampoline every new thread starts in.
+
+extern rust_thread_start
+global switch_context
+global thread_trampoline
+
+section .text
+bits 64
+
+;;; Switch from the running thread to another one.
+;;;
+;;; rdi: where to save the stack pointer of the running thread
+;;; rsi: the saved stack pointer of the thread to switch to
+;;;
+;;; Only the callee-saved registers need saving, as everything else has
+;;; already been saved by the caller (according to the System V ABI).
+;;; Interrupts must be disabled!
+switch_context:
+        push rbp
+        push rbx
+        push r12
+        push r13
+        push r14
+        push r15
+
+        ;; Swap stacks
+        mov [rdi], rsp
+        mov rsp, rsi
+
+        pop r15
+        pop r14
+        pop r13
+        pop r12
+        pop rbx
+        pop rbp
+
+        ;; Return into the other thread
+        ret
+
+;;; Every new thread "returns" here from its first `switch_context`.
+;;; The scheduler has placed the entry function in r12 and its argument
+;;; in r13.
+thread_trampoline:
+        mov rdi, r12
+        mov rsi, r13
+        call rust_thread_start
+
+        ;; rust_thread_start never returns, but just in case:
+        hlt
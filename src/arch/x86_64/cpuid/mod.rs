@@ -389,6 +389,66 @@ pub struct CPUModel {
 }
 
 
+/// Struct for the architectural performance monitoring unit (PMU)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PerfMonitoring {
+    /// Version of architectural performance monitoring
+    pub version:        u8,
+
+    /// General-purpose counters per logical processor
+    pub counters:       u8,
+
+    /// Width of the general-purpose counters, in bits
+    pub counter_width:  u8,
+
+    /// Fixed-function counters (version 2 and up)
+    pub fixed_counters: u8,
+
+    /// Width of the fixed-function counters, in bits
+    pub fixed_width:    u8,
+
+    /// Architectural events that can be counted, one bit each.
+    /// Inverted from the CPUID bit vector, where 1 means unavailable.
+    pub events:         u32
+}
+
+impl PerfMonitoring {
+    /// Decode the registers of CPUID option `BASIC_PEMO`. Returns
+    /// `None` if there is no architectural PMU (version 0).
+    pub fn decode(a: u32, b: u32, d: u32) -> Option<PerfMonitoring> {
+        let version = (a & MASK_BYTE) as u8;
+        if version == 0 {
+            return None;
+        }
+
+        // Only the first few bits of EBX say anything
+        let event_bits = (a >> 24) & MASK_BYTE;
+        let valid = if event_bits >= 32 { !0 } else { (1 << event_bits) - 1 };
+
+        let (fixed_counters, fixed_width) = if version >= 2 {
+            ((d & 0x1f) as u8, ((d >> 5) & MASK_BYTE) as u8)
+        } else {
+            (0, 0)
+        };
+
+        Some(PerfMonitoring {
+            version: version,
+            counters: ((a >> 8) & MASK_BYTE) as u8,
+            counter_width: ((a >> 16) & MASK_BYTE) as u8,
+            fixed_counters: fixed_counters,
+            fixed_width: fixed_width,
+            events: !b & valid
+        })
+    }
+
+    /// Returns TRUE if architectural event number `event` (the bit in
+    /// EBX) can be counted
+    pub fn has_event(&self, event: u8) -> bool {
+        event < 32 && self.events & (1 << event) != 0
+    }
+}
+
+
 /// Struct/Class for using CPUID
 pub struct CPUID {
     /// Highest option available in call to Basic CPUID
@@ -600,6 +660,15 @@ impl CPUID {
 
     }
 
+    /// Get the architectural performance monitoring unit (PMU), if
+    /// there is one
+    pub fn perf_monitoring(&self) -> Option<PerfMonitoring> {
+        match self.get(BASIC_PEMO, self.basic_limit) {
+            Some((a,b,_,d)) => PerfMonitoring::decode(a, b, d),
+            None => None
+        }
+    }
+
     /// Call CPUID using supplied option, only
     /// if option does not exceed highest option
     /// available.
@@ -639,3 +708,21 @@ unsafe fn call_cpuid(option: u32, sub_option: u32) -> (u32, u32, u32, u32) {
     );
     (eax, ebx, ecx, edx)
 }
+
+
+#[test]
+/// Leaf 0xA of a Skylake: version 4, four 48-bit counters, three 48-bit
+/// fixed counters and all seven architectural events
+fn cpuid_perf_monitoring() {
+    let pmu = PerfMonitoring::decode(0x0730_0404, 0, 0x603).unwrap();
+    assert_eq!((pmu.version, pmu.counters, pmu.counter_width), (4, 4, 48));
+    assert_eq!((pmu.fixed_counters, pmu.fixed_width), (3, 48));
+    assert!(pmu.has_event(6) && !pmu.has_event(7));
+
+    // Version 1 without fixed counters, and LLC misses (bit 4) missing
+    let pmu = PerfMonitoring::decode(0x0728_0201, 1 << 4, 0x603).unwrap();
+    assert_eq!(pmu.fixed_counters, 0);
+    assert!(pmu.has_event(3) && !pmu.has_event(4));
+
+    assert_eq!(PerfMonitoring::decode(0, 0, 0), None);
+}
@@ -0,0 +1,171 @@
+//! The Global Descriptor Table (GDT) and the Task State Segment (TSS).
+//!
+//! The GDT set up in `boot.asm` only knows about kernel segments. This
+//! module replaces it with one that also has user mode (ring 3)
+//! segments and a TSS, which tells the CPU which stack to switch to
+//! when an interrupt arrives while running user code.
+//!
+//! The segment order is dictated by `SYSCALL`/`SYSRET`, which compute
+//! the selectors from a base value in the `STAR` MSR:
+//!
+//! | Selector | Segment     |
+//! |----------|-------------|
+//! | 0x00     | Null        |
+//! | 0x08     | Kernel code |
+//! | 0x10     | Kernel data |
+//! | 0x18     | User data   |
+//! | 0x20     | User code   |
+//! | 0x28     | TSS (takes two entries) |
+
+use core::mem::size_of;
+
+/// Selector for the kernel code segment. Same as in `boot.asm`.
+pub const KERNEL_CODE: u16 = 0x08;
+
+/// Selector for the kernel data segment. Same as in `boot.asm`.
+pub const KERNEL_DATA: u16 = 0x10;
+
+/// Selector for the user data segment, with requested privilege level 3.
+pub const USER_DATA: u16 = 0x18 | 3;
+
+/// Selector for the user code segment, with requested privilege level 3.
+pub const USER_CODE: u16 = 0x20 | 3;
+
+/// Selector for the TSS.
+const TSS_SELECTOR: u16 = 0x28;
+
+/// Number of 8-byte entries in the GDT.
+const GDT_NUM_ENTRIES: usize = 7;
+
+// Descriptor bits (see the Intel manual, vol. 3, section 3.4.5):
+
+/// Data segment is writable (ignored in long mode, but QEMU cares)
+const DESC_WRITABLE: u64 = 1 << 41;
+/// Segment is a code segment
+const DESC_EXECUTABLE: u64 = 1 << 43;
+/// Segment is a code or data segment, as opposed to a system segment
+const DESC_USER_SEGMENT: u64 = 1 << 44;
+/// Descriptor privilege level 3
+const DESC_DPL_USER: u64 = 3 << 45;
+/// Segment is present
+const DESC_PRESENT: u64 = 1 << 47;
+/// Code segment is a 64-bit segment
+const DESC_LONG_MODE: u64 = 1 << 53;
+/// System segment type: available 64-bit TSS
+const DESC_TYPE_TSS: u64 = 0b1001 << 40;
+
+const KERNEL_CODE_DESC: u64 = DESC_USER_SEGMENT | DESC_PRESENT
+    | DESC_EXECUTABLE | DESC_WRITABLE | DESC_LONG_MODE;
+const KERNEL_DATA_DESC: u64 = DESC_USER_SEGMENT | DESC_PRESENT
+    | DESC_WRITABLE;
+const USER_CODE_DESC: u64 = KERNEL_CODE_DESC | DESC_DPL_USER;
+const USER_DATA_DESC: u64 = KERNEL_DATA_DESC | DESC_DPL_USER;
+
+/// The 64-bit Task State Segment. In long mode, it no longer holds any
+/// task state, only stack pointers.
+#[repr(C, packed)]
+pub struct TaskStateSegment {
+    reserved_1: u32,
+    /// Stack pointers to load on a privilege level change, indexed by
+    /// the new privilege level.
+    pub privilege_stack_table: [u64; 3],
+    reserved_2: u64,
+    /// Stack pointers for the interrupt stack table.
+    pub interrupt_stack_table: [u64; 7],
+    reserved_3: u64,
+    reserved_4: u16,
+    /// Offset of the I/O permission bitmap from the start of the TSS.
+    pub iomap_base: u16,
+}
+
+/// The TSS. It is exported for the `SYSCALL` entry stub, which loads
+/// the kernel stack from `privilege_stack_table[0]` (offset 4).
+#[no_mangle]
+pub static mut tss: TaskStateSegment = TaskStateSegment {
+    reserved_1: 0,
+    privilege_stack_table: [0; 3],
+    reserved_2: 0,
+    interrupt_stack_table: [0; 7],
+    reserved_3: 0,
+    reserved_4: 0,
+    iomap_base: 0,
+};
+
+/// The GDT itself.
+static mut GDT: [u64; GDT_NUM_ENTRIES] = [0; GDT_NUM_ENTRIES];
+
+/// The contents of the `GDTR` register: where the GDT is, and its limit.
+#[repr(C, packed)]
+struct GdtPointer {
+    limit: u16,
+    base: u64,
+}
+
+/// Build the two GDT entries describing the TSS at `base`.
+fn tss_descriptor(base: u64) -> (u64, u64) {
+    let limit = (size_of::<TaskStateSegment>() - 1) as u64;
+
+    let low = DESC_PRESENT | DESC_TYPE_TSS
+        | (limit & 0xffff)
+        | ((base & 0xff_ffff) << 16)
+        | (((limit >> 16) & 0xf) << 48)
+        | (((base >> 24) & 0xff) << 56);
+    let high = base >> 32;
+
+    (low, high)
+}
+
+/// Install the new GDT and load the TSS.
+///
+/// # Safety
+/// Replaces the contents of `GDTR` and `TR`. Call once, early.
+pub unsafe fn init() {
+    assert_has_not_been_called!("gdt::init must be called only once");
+
+    tss.iomap_base = size_of::<TaskStateSegment>() as u16;
+
+    let (tss_low, tss_high) = tss_descriptor(&tss as *const _ as u64);
+
+    GDT = [0,
+           KERNEL_CODE_DESC,
+           KERNEL_DATA_DESC,
+           USER_DATA_DESC,
+           USER_CODE_DESC,
+           tss_low,
+           tss_high];
+
+    let gdtr = GdtPointer {
+        limit: (size_of::<[u64; GDT_NUM_ENTRIES]>() - 1) as u16,
+        base: &GDT as *const _ as u64,
+    };
+
+    asm!("lgdt [$0]"
+         :
+         : "r"(&gdtr as *const GdtPointer)
+         : "memory"
+         : "intel", "volatile");
+
+    // The kernel selectors are the same as in the old GDT, so there is
+    // no need for a far jump, but the hidden parts of the data segment
+    // registers should be refreshed.
+    asm!("mov ds, ax\n\t\
+          mov es, ax\n\t\
+          mov ss, ax"
+         :
+         : "{ax}"(KERNEL_DATA)
+         :
+         : "intel", "volatile");
+
+    asm!("ltr ax"
+         :
+         : "{ax}"(TSS_SELECTOR)
+         :
+         : "intel", "volatile");
+}
+
+/// Set the stack the CPU switches to when entering the kernel from user
+/// mode, be it through an interrupt or a system call. The scheduler
+/// calls this on every context switch.
+pub fn set_kernel_stack(stack_top: usize) {
+    unsafe { tss.privilege_stack_table[0] = stack_top as u64; }
+}
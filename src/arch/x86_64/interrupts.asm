@@ -2,9 +2,12 @@
 
 extern rust_interrupt_handler
 extern rust_exception_handler
+extern rust_debug_handler
 global general_interrupt_handler
 global null_interrupt_handler
 global general_exception_handler
+global debug_exception_1
+global debug_exception_3
 
 
 section .text
@@ -57,8 +60,12 @@ isr_%1
 
         push_all
 
-        ;; Place the interrupt number as the first argument.
+        ;; Place the interrupt number as the first argument, and where
+        ;; the interrupt came from (RIP and RBP) as the others. For
+        ;; exceptions with an error code, that is in RIP's place.
         mov edi, %1
+        mov rsi, [rsp + 14*8]
+        mov rdx, rbp
         call %2
 
         pop_all
@@ -83,6 +90,32 @@ isr_%1
 %assign i i+1                   ; i++
 %endrep
 
+;;; Define a handler for the debug (1) and breakpoint (3) exceptions,
+;;; used by the GDB stub. All registers are saved, RBP too, and a
+;;; pointer to them is the second argument, so that the handler can
+;;; show and change them.
+%macro def_debug_handler 1
+debug_exception_%1:
+        cli
+
+        push rbp
+        push_all
+
+        mov edi, %1
+        mov rsi, rsp
+        call rust_debug_handler
+
+        pop_all
+        pop rbp
+
+        ;; RFLAGS is restored from the stack, and with it the interrupt
+        ;; flag and the trap flag
+        iretq
+%endmacro
+
+def_debug_handler 1
+def_debug_handler 3
+
 ;;; General (do-nothing) handlers:
 general_exception_handler:
         cli
@@ -1,2 +1,3 @@
 /// Dummy mod for enabling x86_64 specific submodules
 pub mod cpuid;
+pub mod gdt;
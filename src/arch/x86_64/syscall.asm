@@ -0,0 +1,95 @@
+;;; This file contains the entry point for the SYSCALL instruction, and
+;;; the code to drop into user mode.
+
+extern rust_syscall_handler
+extern tss
+global syscall_entry
+global jump_to_user_mode
+
+;;; Offset of the ring 0 stack pointer in the TSS
+%define TSS_RSP0 4
+
+;;; Segment selectors, see gdt.rs
+%define USER_DATA 0x18 | 3
+%define USER_CODE 0x20 | 3
+
+;;; Interrupt enable flag in RFLAGS
+%define RFLAGS_IF 0x200
+
+section .text
+bits 64
+
+;;; SYSCALL lands here, still on the user stack. The CPU has put the
+;;; user return address in rcx and the user RFLAGS in r11, and cleared
+;;; the interrupt flag (see SFMASK).
+;;;
+;;; Calling convention (like Linux): system call number in rax,
+;;; arguments in rdi, rsi and rdx. The result is returned in rax. All
+;;; other registers except rcx and r11 are preserved.
+syscall_entry:
+        ;; Switch to the kernel stack of the running thread
+        mov [user_rsp], rsp
+        mov rsp, [tss + TSS_RSP0]
+
+        push qword [user_rsp]
+        push rcx
+        push r11
+        push rdi
+        push rsi
+        push rdx
+        push r8
+        push r9
+        push r10
+        ;; Keep the stack 16-byte aligned for the call below
+        sub rsp, 8
+
+        ;; Everything is saved, the thread may be preempted from here on
+        sti
+
+        ;; rust_syscall_handler(number, arg1, arg2, arg3)
+        mov rcx, rdx
+        mov rdx, rsi
+        mov rsi, rdi
+        mov rdi, rax
+        call rust_syscall_handler
+
+        cli
+
+        add rsp, 8
+        pop r10
+        pop r9
+        pop r8
+        pop rdx
+        pop rsi
+        pop rdi
+        pop r11
+        pop rcx
+        pop rsp
+
+        o64 sysret
+
+;;; Start running user code, never to return.
+;;;
+;;; rdi: user instruction pointer
+;;; rsi: user stack pointer
+jump_to_user_mode:
+        cli
+
+        mov ax, USER_DATA
+        mov ds, ax
+        mov es, ax
+
+        ;; Build an interrupt return frame
+        push USER_DATA          ; ss
+        push rsi                ; rsp
+        push RFLAGS_IF          ; rflags
+        push USER_CODE          ; cs
+        push rdi                ; rip
+
+        iretq
+
+section .bss
+;;; Scratch space for the user stack pointer. Only used with interrupts
+;;; disabled.
+user_rsp:
+        resq 1
@@ -0,0 +1,441 @@
+//! # GDB stub
+//!
+//! Lets GDB debug the kernel over the serial port, with the GDB remote
+//! serial protocol. Unlike QEMU's own stub, this works on any machine
+//! with a serial line, and sees memory the way the kernel does, through
+//! the page tables of whatever is running.
+//!
+//! `init()` takes over the debug (1) and breakpoint (3) exceptions.
+//! Whenever one happens, the kernel stops, tells GDB and does what it
+//! says until it continues. Start the kernel with `gdb` on the command
+//! line to stop right after boot, or run `gdb` in the shell to stop
+//! there; then, with QEMU (see `make debug-stub` and `make gdb-stub`):
+//!
+//! ```
+//! (gdb) target remote :4321
+//! ```
+//!
+//! Supported: reading and writing registers and memory, software
+//! breakpoints (`break`) and single-stepping (`stepi`).
+//!
+//! # Limitations
+//! There is no way to interrupt the kernel from GDB (Ctrl-C); set a
+//! breakpoint instead. Only the registers of the thread that stopped
+//! can be seen. Breakpoints in the serial port or page table code the
+//! stub itself uses hang the kernel. Kernel log messages go to the
+//! serial port too, and GDB will complain about them.
+
+use core::fmt::Write;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
+use x86::controlregs::{cr0, cr0_write};
+
+use io::serial;
+use irq;
+use memory;
+
+mod packet;
+
+use self::packet::{Reply, PACKET_SIZE};
+
+/// The registers saved by `debug_exception_1` and `debug_exception_3`
+/// in `interrupts.asm`, in the order they are on the stack, followed by
+/// what the CPU pushed.
+#[repr(C)]
+pub struct TrapFrame {
+    rsi: u64,
+    rdi: u64,
+    r15: u64,
+    r14: u64,
+    r13: u64,
+    r12: u64,
+    r11: u64,
+    r10: u64,
+    r9: u64,
+    r8: u64,
+    rdx: u64,
+    rcx: u64,
+    rbx: u64,
+    rax: u64,
+    rbp: u64,
+    rip: u64,
+    cs: u64,
+    rflags: u64,
+    rsp: u64,
+    ss: u64,
+}
+
+extern {
+    fn debug_exception_1();
+    fn debug_exception_3();
+}
+
+/// Registers GDB knows for amd64 without a target description. The
+/// first 17 are 64 bits, the others (RFLAGS and segments) 32.
+const NUM_REGISTERS: usize = 24;
+const NUM_REGISTERS_64: usize = 17;
+/// The registers up to here (RFLAGS) can be changed
+const LAST_WRITABLE_REGISTER: usize = 17;
+
+const MAX_BREAKPOINTS: usize = 32;
+
+/// The breakpoint instruction
+const INT3: u8 = 0xCC;
+
+/// Trap flag: a debug exception after every instruction
+const RFLAGS_TF: u64 = 1 << 8;
+
+/// Write protect: the kernel can't write read-only pages either
+const CR0_WP: usize = 1 << 16;
+
+// Error replies
+const E_REQUEST: &'static str = "E01";
+/// Unmapped memory (EFAULT)
+const E_FAULT: &'static str = "E0e";
+const E_NO_ROOM: &'static str = "E1c";
+
+#[derive(Clone, Copy)]
+struct Breakpoint {
+    address: usize,
+    /// The byte that `INT3` replaced
+    saved: u8,
+}
+
+struct Stub {
+    breakpoints: [Option<Breakpoint>; MAX_BREAKPOINTS],
+    /// False once GDB has turned acks off with `QStartNoAckMode`
+    ack: bool,
+}
+
+/// Only one CPU, with interrupts off, ever has it.
+static STUB: Mutex<Stub> = Mutex::new(Stub {
+    breakpoints: [None; MAX_BREAKPOINTS],
+    ack: true,
+});
+
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+
+/// Take over the debug and breakpoint exceptions, if there is a serial
+/// port to talk to GDB on. Must be called after `irq::install()` and
+/// `io::install_io()`.
+pub fn init() -> bool {
+    if !serial::present() {
+        return false;
+    }
+    unsafe {
+        irq::idt::set_gate(1, debug_exception_1, irq::idt::SELECT_TARGET_PRIV_1,
+                           irq::DEFAULT_FLAGS);
+        irq::idt::set_gate(3, debug_exception_3, irq::idt::SELECT_TARGET_PRIV_1,
+                           irq::DEFAULT_FLAGS);
+    }
+    INSTALLED.store(true, Ordering::SeqCst);
+    true
+}
+
+/// True if `init()` installed the stub.
+pub fn installed() -> bool {
+    INSTALLED.load(Ordering::SeqCst)
+}
+
+/// Stop here and wait for GDB, if the stub is installed.
+pub fn breakpoint() {
+    if installed() {
+        unsafe { asm!("int3" :::: "volatile") };
+    }
+}
+
+/// Debug or breakpoint exception `vector`, with the registers of the
+/// code that stopped in `frame`: let GDB have a look, and return when
+/// it says to go on.
+pub fn handle_exception(vector: usize, frame: &mut TrapFrame) {
+    let mut stub = STUB.lock();
+
+    // After a breakpoint of ours, RIP is past the INT3 that replaced
+    // the first byte of the instruction; go back to run it for real
+    if vector == 3 && stub.find(frame.rip as usize - 1).is_some() {
+        frame.rip -= 1;
+    }
+    frame.rflags &= !RFLAGS_TF;
+
+    let ack = stub.ack;
+    packet::send(b"S05", ack);
+    stub.serve(frame);
+}
+
+impl Stub {
+    /// Answer GDB's requests until it continues.
+    fn serve(&mut self, frame: &mut TrapFrame) {
+        let mut buffer = [0; PACKET_SIZE];
+        let mut reply = Reply::new();
+
+        loop {
+            let len = packet::receive(&mut buffer, self.ack);
+            let request = &buffer[..len];
+            reply.clear();
+
+            let ok = match request.first() {
+                // Why did we stop? SIGTRAP
+                Some(&b'?') => write!(reply, "S05").is_ok(),
+                Some(&b'g') => {
+                    for n in 0..NUM_REGISTERS {
+                        push_register(&mut reply, frame, n);
+                    }
+                    true
+                },
+                Some(&b'G') => write_registers(frame, &request[1..], &mut reply),
+                Some(&b'p') => match packet::parse_hex(&request[1..]) {
+                    Some(n) if n < NUM_REGISTERS => {
+                        push_register(&mut reply, frame, n);
+                        true
+                    },
+                    _ => false,
+                },
+                Some(&b'P') => write_register(frame, &request[1..], &mut reply),
+                Some(&b'm') => read_memory(&request[1..], &mut reply),
+                Some(&b'M') => write_memory(&request[1..], &mut reply),
+                Some(&b'Z') => self.set_breakpoint(&request[1..], true, &mut reply),
+                Some(&b'z') => self.set_breakpoint(&request[1..], false, &mut reply),
+                Some(&b'c') | Some(&b's') => {
+                    // Optionally from a new address
+                    if request.len() > 1 {
+                        if let Some(address) = packet::parse_hex(&request[1..]) {
+                            frame.rip = address as u64;
+                        }
+                    }
+                    if request[0] == b's' {
+                        frame.rflags |= RFLAGS_TF;
+                    }
+                    // The reply comes when we stop again
+                    return;
+                },
+                Some(&b'D') => {
+                    packet::send(b"OK", self.ack);
+                    return;
+                },
+                // We can't be killed; go on instead
+                Some(&b'k') => return,
+                // There is only the thread that stopped
+                Some(&b'H') => write!(reply, "OK").is_ok(),
+                Some(&b'q') => {
+                    if request.starts_with(b"qSupported") {
+                        let _ = write!(reply, "PacketSize={:x};QStartNoAckMode+", PACKET_SIZE);
+                    } else if request == b"qAttached" {
+                        let _ = write!(reply, "1");
+                    }
+                    true
+                },
+                Some(&b'Q') if request == b"QStartNoAckMode" => {
+                    packet::send(b"OK", self.ack);
+                    self.ack = false;
+                    continue;
+                },
+                // Anything else isn't supported, which is an empty reply
+                _ => true,
+            };
+
+            if !ok && reply.as_bytes().is_empty() {
+                let _ = write!(reply, "{}", E_REQUEST);
+            }
+            packet::send(reply.as_bytes(), self.ack);
+        }
+    }
+
+    /// The breakpoint at `address`, as an index into `breakpoints`
+    fn find(&self, address: usize) -> Option<usize> {
+        self.breakpoints.iter().position(|b| match *b {
+            Some(ref b) => b.address == address,
+            None => false,
+        })
+    }
+
+    /// `Z0,address,kind` and `z0,address,kind`: set or remove a software
+    /// breakpoint. Other kinds of breakpoints aren't supported.
+    fn set_breakpoint(&mut self, request: &[u8], set: bool, reply: &mut Reply) -> bool {
+        let mut parts = request.split(|&c| c == b',');
+        if parts.next() != Some(&b"0"[..]) {
+            // Not supported
+            return true;
+        }
+        let address = match parts.next().and_then(packet::parse_hex) {
+            Some(address) => address,
+            None => return false,
+        };
+
+        match (set, self.find(address)) {
+            // Already there, or already gone
+            (true, Some(_)) | (false, None) => {},
+            (true, None) => {
+                let slot = match self.breakpoints.iter().position(|b| b.is_none()) {
+                    Some(slot) => slot,
+                    None => return write!(reply, "{}", E_NO_ROOM).is_ok(),
+                };
+                if !memory::kernel_readable(address, 1) {
+                    return write!(reply, "{}", E_FAULT).is_ok();
+                }
+                let saved = unsafe { ptr::read_volatile(address as *const u8) };
+                unsafe { poke(address, &[INT3]) };
+                self.breakpoints[slot] = Some(Breakpoint { address: address, saved: saved });
+            },
+            (false, Some(slot)) => {
+                let saved = self.breakpoints[slot].take().unwrap().saved;
+                unsafe { poke(address, &[saved]) };
+            },
+        }
+        write!(reply, "OK").is_ok()
+    }
+}
+
+/// `G`: every register, as for `g`.
+fn write_registers(frame: &mut TrapFrame, hex: &[u8], reply: &mut Reply) -> bool {
+    let mut rest = hex;
+    for n in 0..NUM_REGISTERS {
+        let size = register_size(n) * 2;
+        if rest.len() < size {
+            break;
+        }
+        match decode_register(&rest[..size]) {
+            Some(value) => set_register(frame, n, value),
+            None => return false,
+        }
+        rest = &rest[size..];
+    }
+    write!(reply, "OK").is_ok()
+}
+
+/// `Pn=value`
+fn write_register(frame: &mut TrapFrame, request: &[u8], reply: &mut Reply) -> bool {
+    let mut parts = request.splitn(2, |&c| c == b'=');
+    let n = match parts.next().and_then(packet::parse_hex) {
+        Some(n) if n < NUM_REGISTERS => n,
+        _ => return false,
+    };
+    match parts.next().and_then(decode_register) {
+        Some(value) => {
+            set_register(frame, n, value);
+            write!(reply, "OK").is_ok()
+        },
+        None => false,
+    }
+}
+
+/// Size of register `n`, in bytes
+fn register_size(n: usize) -> usize {
+    if n < NUM_REGISTERS_64 { 8 } else { 4 }
+}
+
+/// Register `n` in GDB's numbering, if we have it
+fn register(frame: &mut TrapFrame, n: usize) -> Option<&mut u64> {
+    Some(match n {
+        0 => &mut frame.rax,
+        1 => &mut frame.rbx,
+        2 => &mut frame.rcx,
+        3 => &mut frame.rdx,
+        4 => &mut frame.rsi,
+        5 => &mut frame.rdi,
+        6 => &mut frame.rbp,
+        7 => &mut frame.rsp,
+        8 => &mut frame.r8,
+        9 => &mut frame.r9,
+        10 => &mut frame.r10,
+        11 => &mut frame.r11,
+        12 => &mut frame.r12,
+        13 => &mut frame.r13,
+        14 => &mut frame.r14,
+        15 => &mut frame.r15,
+        16 => &mut frame.rip,
+        17 => &mut frame.rflags,
+        18 => &mut frame.cs,
+        19 => &mut frame.ss,
+        // DS, ES, FS and GS aren't used in long mode
+        _ => return None,
+    })
+}
+
+/// Add register `n` to `reply`, in target byte order
+fn push_register(reply: &mut Reply, frame: &mut TrapFrame, n: usize) {
+    let value = register(frame, n).map_or(0, |r| *r);
+    for i in 0..register_size(n) {
+        reply.push_hex((value >> (8 * i)) as u8);
+    }
+}
+
+/// A register value in target byte order, as sent by GDB
+fn decode_register(hex: &[u8]) -> Option<u64> {
+    let mut bytes = [0; 8];
+    packet::decode_hex(hex, &mut bytes).map(|len| {
+        bytes[..len].iter().rev().fold(0, |value, &b| value << 8 | b as u64)
+    })
+}
+
+/// Change register `n`, unless it's one that GDB had better leave alone
+fn set_register(frame: &mut TrapFrame, n: usize, value: u64) {
+    if n > LAST_WRITABLE_REGISTER {
+        return;
+    }
+    if let Some(register) = register(frame, n) {
+        if register_size(n) == 4 {
+            *register = (*register & !0xFFFF_FFFF) | (value & 0xFFFF_FFFF);
+        } else {
+            *register = value;
+        }
+    }
+}
+
+/// `address,length`, as in the `m` and `M` requests
+fn parse_range(range: &[u8]) -> Option<(usize, usize)> {
+    let mut parts = range.splitn(2, |&c| c == b',');
+    match (parts.next().and_then(packet::parse_hex),
+           parts.next().and_then(packet::parse_hex)) {
+        (Some(address), Some(length)) => Some((address, length)),
+        _ => None,
+    }
+}
+
+/// `maddress,length`: read memory, as much as fits in a reply.
+fn read_memory(request: &[u8], reply: &mut Reply) -> bool {
+    let (address, length) = match parse_range(request) {
+        Some(range) => range,
+        None => return false,
+    };
+    let length = if length > reply.room() / 2 { reply.room() / 2 } else { length };
+
+    if !memory::kernel_readable(address, length) {
+        return write!(reply, "{}", E_FAULT).is_ok();
+    }
+    for i in 0..length {
+        reply.push_hex(unsafe { ptr::read_volatile((address + i) as *const u8) });
+    }
+    true
+}
+
+/// `Maddress,length:bytes`: write memory.
+fn write_memory(request: &[u8], reply: &mut Reply) -> bool {
+    let mut parts = request.splitn(2, |&c| c == b':');
+    let (address, length) = match parts.next().and_then(parse_range) {
+        Some(range) => range,
+        None => return false,
+    };
+    let mut bytes = [0; PACKET_SIZE / 2];
+    match parts.next().and_then(|hex| packet::decode_hex(hex, &mut bytes)) {
+        Some(len) if len == length => {},
+        _ => return false,
+    }
+
+    if !memory::kernel_readable(address, length) {
+        return write!(reply, "{}", E_FAULT).is_ok();
+    }
+    unsafe { poke(address, &bytes[..length]) };
+    write!(reply, "OK").is_ok()
+}
+
+/// Write `bytes` at `address`, even if it is read-only, like the
+/// kernel's code.
+unsafe fn poke(address: usize, bytes: &[u8]) {
+    let old_cr0 = cr0();
+    cr0_write(old_cr0 & !CR0_WP);
+    for (i, &b) in bytes.iter().enumerate() {
+        ptr::write_volatile((address + i) as *mut u8, b);
+    }
+    cr0_write(old_cr0);
+}
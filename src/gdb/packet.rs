@@ -0,0 +1,179 @@
+//! Packets of the GDB remote serial protocol, like `$m10a2f0,4#f9`:
+//! the data between `$` and `#`, then the sum of its bytes modulo 256,
+//! in hex. The other side answers `+` if the packet arrived intact and
+//! `-` if it should be sent again, unless acks have been turned off.
+
+use core::fmt;
+
+use io::serial;
+
+/// Largest packet we take, not counting `$`, `#` and the checksum.
+/// Told to GDB in the reply to `qSupported`.
+pub const PACKET_SIZE: usize = 512;
+
+fn read() -> u8 {
+    // The stub is only installed if there is a UART
+    serial::read_byte().unwrap_or(0)
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum: u8, &b| sum.wrapping_add(b))
+}
+
+fn hex_digit(c: u8) -> Option<u8> {
+    match c {
+        b'0'...b'9' => Some(c - b'0'),
+        b'a'...b'f' => Some(c - b'a' + 10),
+        b'A'...b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
+
+const HEX: &'static [u8; 16] = b"0123456789abcdef";
+
+/// The number written in hex in `hex`, which may not be empty.
+pub fn parse_hex(hex: &[u8]) -> Option<usize> {
+    if hex.is_empty() || hex.len() > 16 {
+        return None;
+    }
+    hex.iter().fold(Some(0), |value, &c| {
+        value.and_then(|value| hex_digit(c).map(|digit| value << 4 | digit as usize))
+    })
+}
+
+/// Decode the bytes written in hex in `hex` into `bytes`, returning how
+/// many there were.
+pub fn decode_hex(hex: &[u8], bytes: &mut [u8]) -> Option<usize> {
+    if hex.len() % 2 != 0 || hex.len() / 2 > bytes.len() {
+        return None;
+    }
+    for (i, pair) in hex.chunks(2).enumerate() {
+        match (hex_digit(pair[0]), hex_digit(pair[1])) {
+            (Some(high), Some(low)) => bytes[i] = high << 4 | low,
+            _ => return None,
+        }
+    }
+    Some(hex.len() / 2)
+}
+
+/// Wait for a packet, and put its data in `buffer`. Returns the length
+/// of the data.
+pub fn receive(buffer: &mut [u8; PACKET_SIZE], ack: bool) -> usize {
+    loop {
+        // Anything between packets is noise
+        while read() != b'$' {}
+
+        let mut len = 0;
+        let mut fits = true;
+        loop {
+            let c = read();
+            if c == b'#' {
+                break;
+            }
+            if len < PACKET_SIZE {
+                buffer[len] = c;
+                len += 1;
+            } else {
+                fits = false;
+            }
+        }
+        let sum = match (hex_digit(read()), hex_digit(read())) {
+            (Some(high), Some(low)) => Some(high << 4 | low),
+            _ => None,
+        };
+
+        if fits && sum == Some(checksum(&buffer[..len])) {
+            if ack {
+                serial::write_byte(b'+');
+            }
+            return len;
+        }
+        if ack {
+            serial::write_byte(b'-');
+        }
+    }
+}
+
+/// Send a packet with `data`, again until GDB has it.
+pub fn send(data: &[u8], ack: bool) {
+    loop {
+        serial::write_byte(b'$');
+        for &b in data {
+            serial::write_byte(b);
+        }
+        let sum = checksum(data);
+        serial::write_byte(b'#');
+        serial::write_byte(HEX[(sum >> 4) as usize]);
+        serial::write_byte(HEX[(sum & 0xF) as usize]);
+
+        if !ack || read() != b'-' {
+            return;
+        }
+    }
+}
+
+/// The data of a reply, filled in with `write!` and `push_hex`.
+pub struct Reply {
+    data: [u8; PACKET_SIZE],
+    len: usize,
+}
+
+impl Reply {
+    pub fn new() -> Reply {
+        Reply { data: [0; PACKET_SIZE], len: 0 }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data[..self.len]
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// Room left, in bytes of data
+    pub fn room(&self) -> usize {
+        PACKET_SIZE - self.len
+    }
+
+    /// Add `byte`, as two hex digits.
+    pub fn push_hex(&mut self, byte: u8) {
+        if self.room() >= 2 {
+            self.data[self.len] = HEX[(byte >> 4) as usize];
+            self.data[self.len + 1] = HEX[(byte & 0xF) as usize];
+            self.len += 2;
+        }
+    }
+}
+
+impl fmt::Write for Reply {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if s.len() > self.room() {
+            return Err(fmt::Error);
+        }
+        self.data[self.len..self.len + s.len()].clone_from_slice(s.as_bytes());
+        self.len += s.len();
+        Ok(())
+    }
+}
+
+
+#[test]
+/// Hex numbers and byte strings, in both cases, and what isn't hex
+fn gdb_hex() {
+    assert_eq!(parse_hex(b"10a2F0"), Some(0x10a2f0));
+    assert_eq!(parse_hex(b""), None);
+    assert_eq!(parse_hex(b"12g"), None);
+
+    let mut bytes = [0; 4];
+    assert_eq!(decode_hex(b"cc90", &mut bytes), Some(2));
+    assert_eq!(&bytes[..2], &[0xCC, 0x90]);
+    assert_eq!(decode_hex(b"c", &mut bytes), None);
+    assert_eq!(decode_hex(b"0102030405", &mut bytes), None);
+}
+
+#[test]
+/// The checksum of `$OK#9a`
+fn gdb_checksum() {
+    assert_eq!(checksum(b"OK"), 0x9a);
+}
@@ -0,0 +1,168 @@
+//! # Input events
+//!
+//! Input drivers (keyboard, mouse, serial port) `publish()` what they
+//! get as timestamped `InputEvent`s. Every thread wanting input
+//! `subscribe()`s to the kinds of events it cares about, and gets its
+//! own queue of them:
+//!
+//! ```
+//! let input = input::subscribe(input::CHAR | input::KEY);
+//! loop {
+//!     match input.read().event {
+//!         Event::Char(c) => print!("{}", c),
+//!         Event::Key(key) if key.pressed && key.key() == KeyCode::Up => ...,
+//!         _ => {},
+//!     }
+//! }
+//! ```
+//!
+//! A key press gives a `Key` event, followed by a `Char` event if the
+//! key types a character in the current keyboard layout.
+
+use alloc::arc::Arc;
+use collections::btree_map::BTreeMap;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin;
+
+use io::kbd::KeyEvent;
+use io::mouse::MouseEvent;
+use irq;
+use pipe::Buffer;
+use sync::WaitQueue;
+use timers;
+
+/// Number of events a subscriber can fall behind before losing some.
+const QUEUE_SIZE: usize = 128;
+
+/// Something that happened on an input device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// A key was pressed, repeated or released
+    Key(KeyEvent),
+    /// A character was typed on the keyboard
+    Char(char),
+    Mouse(MouseEvent),
+    /// A byte arrived on the serial port
+    Serial(u8),
+}
+
+/// An `Event`, and when it happened.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InputEvent {
+    /// Timer ticks since boot
+    pub timestamp: usize,
+    pub event: Event,
+}
+
+bitflags! {
+    flags Kinds: u8 {
+        const KEY    = 1 << 0,
+        const CHAR   = 1 << 1,
+        const MOUSE  = 1 << 2,
+        const SERIAL = 1 << 3,
+    }
+}
+
+impl Event {
+    /// Which kind of event this is.
+    pub fn kind(&self) -> Kinds {
+        match *self {
+            Event::Key(_) => KEY,
+            Event::Char(_) => CHAR,
+            Event::Mouse(_) => MOUSE,
+            Event::Serial(_) => SERIAL,
+        }
+    }
+}
+
+/// The events for one subscriber.
+struct Queue {
+    kinds: Kinds,
+    buffer: Buffer<InputEvent>,
+    waiters: WaitQueue,
+}
+
+/// Every subscriber's queue, by subscriber number. Only locked with
+/// interrupts disabled, as drivers publish from interrupt handlers.
+static QUEUES: spin::Mutex<Option<BTreeMap<usize, Arc<Queue>>>> = spin::Mutex::new(None);
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Hand `event` to everybody subscribed to its kind. Safe to call from
+/// interrupt handlers.
+pub fn publish(event: Event) {
+    let event = InputEvent { timestamp: timers::get_ticks(), event: event };
+    let kind = event.event.kind();
+
+    irq::without_interrupts(|| {
+        if let Some(ref queues) = *QUEUES.lock() {
+            for queue in queues.values() {
+                if queue.kinds.contains(kind) {
+                    // Drop the event if the subscriber is too far behind
+                    let _ = queue.buffer.try_push(event);
+                    queue.waiters.wake_all();
+                }
+            }
+        }
+    });
+}
+
+/// Start receiving events of the given `kinds`.
+pub fn subscribe(kinds: Kinds) -> Subscriber {
+    let queue = Arc::new(Queue {
+        kinds: kinds,
+        buffer: Buffer::new(QUEUE_SIZE),
+        waiters: WaitQueue::new(),
+    });
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+
+    irq::without_interrupts(|| {
+        let mut queues = QUEUES.lock();
+        if queues.is_none() {
+            *queues = Some(BTreeMap::new());
+        }
+        queues.as_mut().unwrap().insert(id, queue.clone());
+    });
+
+    Subscriber { id: id, queue: queue }
+}
+
+/// A subscription to input events, returned by `subscribe()`. Only one
+/// thread at a time may read from it. Dropping it unsubscribes.
+pub struct Subscriber {
+    id: usize,
+    queue: Arc<Queue>,
+}
+
+impl Subscriber {
+    /// The next event, waiting for one if needed.
+    pub fn read(&self) -> InputEvent {
+        loop {
+            let buffer = &self.queue.buffer;
+            self.queue.waiters.wait_until(|| !buffer.is_empty());
+            if let Ok(event) = buffer.try_pop() {
+                return event;
+            }
+        }
+    }
+
+    /// The next event, if there is one.
+    pub fn try_read(&self) -> Option<InputEvent> {
+        self.queue.buffer.try_pop().ok()
+    }
+
+    /// Throw away the events not read yet.
+    pub fn clear(&self) {
+        self.queue.buffer.clear();
+    }
+}
+
+impl Drop for Subscriber {
+    fn drop(&mut self) {
+        let queue = irq::without_interrupts(|| {
+            QUEUES.lock().as_mut().and_then(|queues| queues.remove(&self.id))
+        });
+        // Freed here, outside of the lock
+        drop(queue);
+    }
+}
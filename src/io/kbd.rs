@@ -1,79 +1,304 @@
-use core::intrinsics::{volatile_load, volatile_store};
+//! PS/2 keyboard: scancode set 1 decoding.
+//!
+//! The keyboard sends one or more bytes (_scancodes_) for every key
+//! press and release. `Decoder` turns them into `KeyEvent`s, naming the
+//! physical key and keeping track of the modifier keys. Turning events
+//! into characters is up to the `keymap` module.
+//!
+//! A scancode is the key number, with bit 7 set on release. Keys added
+//! after the original XT keyboard (arrows, right Ctrl, ...) are prefixed
+//! by 0xE0, and Pause sends its own six-byte sequence starting with 0xE1.
 
+use input::{self, Event};
 use io;
-use pipe;
-use pipe::Buffer;
-
-
-enum Keycode {
-    NULL_KEY = 0,
-    Q_PRESSED = 0x10,   Q_RELEASED = 0x90,
-    W_PRESSED = 0x11,   W_RELEASED = 0x91,
-    E_PRESSED = 0x12,   E_RELEASED = 0x92,
-    R_PRESSED = 0x13,   R_RELEASED = 0x93,
-    T_PRESSED = 0x14,   T_RELEASED = 0x94,
-    Y_PRESSED = 0x15,   Y_RELEASED = 0x95,
-    U_PRESSED = 0x16,   U_RELEASED = 0x96,
-    I_PRESSED = 0x17,   I_RELEASED = 0x97,
-    O_PRESSED = 0x18,   O_RELEASED = 0x98,
-    P_PRESSED = 0x19,   P_RELEASED = 0x99,
-    A_PRESSED = 0x1E,   A_RELEASED = 0x9E,
-    S_PRESSED = 0x1F,   S_RELEASED = 0x9F,
-    D_PRESSED = 0x20,   D_RELEASED = 0xA0,
-    F_PRESSED = 0x21,   F_RELEASED = 0xA1,
-    G_PRESSED = 0x22,   G_RELEASED = 0xA2,
-    H_PRESSED = 0x23,   H_RELEASED = 0xA3,
-    J_PRESSED = 0x24,   J_RELEASED = 0xA4,
-    K_PRESSED = 0x25,   K_RELEASED = 0xA5,
-    L_PRESSED = 0x26,   L_RELEASED = 0xA6,
-    Z_PRESSED = 0x2C,   Z_RELEASED = 0xAC,
-    X_PRESSED = 0x2D,   X_RELEASED = 0xAD,
-    C_PRESSED = 0x2E,   C_RELEASED = 0xAE,
-    V_PRESSED = 0x2F,   V_RELEASED = 0xAF,
-    B_PRESSED = 0x30,   B_RELEASED = 0xB0,
-    N_PRESSED = 0x31,   N_RELEASED = 0xB1,
-    M_PRESSED = 0x32,   M_RELEASED = 0xB2,
-
-    ZERO_PRESSED    = 0xB,
-    ONE_PRESSED     = 0x2,
-    TWO_PRESSED     = 0x3,
-    THREE_PRESSED   = 0x4,
-    FOUR_PRESSED    = 0x5,
-    FIVE_PRESSED    = 0x6,
-    SIX_PRESSED     = 0x7,
-    SEVEN_PRESSED   = 0x8,
-    EIGHT_PRESSED   = 0x9,
-    NINE_PRESSED    = 0xA,
-
-    SHIFT_PRESSED = 0x2A, SHIFT_RELEASED = 0xAA,
-
-    POINT_PRESSED   = 0x34, POINT_RELEASED  = 0xB4,
-    SLASH_PRESSED   = 0x35, SLASH_RELEASED  = 0xB5,
-    DASH_PRESSED    = 0x0C, DASH_RELEASED   = 0x8C,
-
-    BACKSPACE_PRESSED = 0xE,BACKSPACE_RELEASED = 0x8E,
-    SPACE_PRESSED = 0x39,   SPACE_RELEASED = 0xB9,
-    ENTER_PRESSED = 0x1C,   ENTER_RELEASED = 0x9C,
-
-    UP_RELEASED = 0xC8,     DOWN_RELEASED = 0xD0,
-    LEFT_RELEASED = 0xCB,   RIGHT_RELEASED = 0xCD,
-
-    AA_PRESSED = 0x1A,   AA_RELEASED = 0x9A,
-    AE_PRESSED = 0x28,   AE_RELEASED = 0xA8,
-    OE_PRESSED = 0x27,   OE_RELEASED = 0xA7,
-}
-
-static mut shift: bool = false;
-
-
-/// Keyboard handler. It is _very_ depressing to resort to global variables, if a better solution
-/// exists, feel free to implement it!
-pub unsafe fn getkbd(arg: usize) {
-    let flag: u8;
+use io::keymap;
+use io::ps2;
+
+/// Prefix of extended scancodes
+const EXTENDED: u8 = 0xE0;
+/// Prefix of the Pause key sequence
+const PAUSE: u8 = 0xE1;
+/// Bit set in the scancodes of released keys
+const RELEASED: u8 = 0x80;
+
+/// A physical key, named after what it says on a US keyboard.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyCode {
+    Escape,
+    Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9, Key0,
+    Minus, Equals, Backspace, Tab,
+    Q, W, E, R, T, Y, U, I, O, P,
+    LeftBracket, RightBracket, Enter,
+    A, S, D, F, G, H, J, K, L,
+    Semicolon, Quote, Backtick, Backslash,
+    Z, X, C, V, B, N, M,
+    Comma, Period, Slash, Space,
+    /// The extra key next to left Shift on non-US keyboards
+    NonUsBackslash,
+
+    LeftShift, RightShift, LeftCtrl, RightCtrl, LeftAlt, RightAlt,
+    LeftGui, RightGui, Menu,
+    CapsLock, NumLock, ScrollLock,
+
+    F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12,
+
+    Keypad0, Keypad1, Keypad2, Keypad3, Keypad4,
+    Keypad5, Keypad6, Keypad7, Keypad8, Keypad9,
+    KeypadPeriod, KeypadPlus, KeypadMinus, KeypadMultiply,
+    KeypadDivide, KeypadEnter,
+
+    Up, Down, Left, Right,
+    Home, End, PageUp, PageDown, Insert, Delete,
+    PrintScreen, Pause,
+}
+
+bitflags! {
+    flags Modifiers: u8 {
+        const SHIFT     = 1 << 0,
+        const CTRL      = 1 << 1,
+        const ALT       = 1 << 2,
+        /// Right Alt, used for a third character on many layouts
+        const ALT_GR    = 1 << 3,
+        const CAPS_LOCK = 1 << 4,
+        const NUM_LOCK  = 1 << 5,
+        const SCROLL_LOCK = 1 << 6,
+    }
+}
+
+/// A key being pressed (or repeated while held down) or released.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub code: KeyCode,
+    pub pressed: bool,
+    /// The modifiers in effect, this event included
+    pub modifiers: Modifiers,
+}
+
+impl KeyEvent {
+    /// The key as it acts: without Num Lock (or with Shift), the keypad
+    /// keys are the arrows, Home, End and so on.
+    pub fn key(&self) -> KeyCode {
+        use self::KeyCode::*;
+
+        if self.modifiers.contains(NUM_LOCK) && !self.modifiers.contains(SHIFT) {
+            return self.code;
+        }
+        match self.code {
+            Keypad0 => Insert, Keypad1 => End, Keypad2 => Down, Keypad3 => PageDown,
+            Keypad4 => Left, Keypad6 => Right,
+            Keypad7 => Home, Keypad8 => Up, Keypad9 => PageUp,
+            KeypadPeriod => Delete,
+            code => code,
+        }
+    }
+}
+
+/// What the decoder expects next.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    Start,
+    /// Got 0xE0
+    Extended,
+    /// Got 0xE1 and this many more bytes of the Pause sequence
+    Pause(u8),
+}
+
+/// Turns scancodes into `KeyEvent`s.
+pub struct Decoder {
+    state: State,
+    left_shift: bool,
+    right_shift: bool,
+    left_ctrl: bool,
+    right_ctrl: bool,
+    alt: bool,
+    alt_gr: bool,
+    caps_lock: bool,
+    num_lock: bool,
+    scroll_lock: bool,
+    /// The lock keys toggle on the first press only, not when the key
+    /// repeats
+    caps_held: bool,
+    num_held: bool,
+    scroll_held: bool,
+}
+
+impl Decoder {
+    /// A decoder with no keys held and all locks off.
+    pub const fn new() -> Decoder {
+        Decoder {
+            state: State::Start,
+            left_shift: false,
+            right_shift: false,
+            left_ctrl: false,
+            right_ctrl: false,
+            alt: false,
+            alt_gr: false,
+            caps_lock: false,
+            num_lock: false,
+            scroll_lock: false,
+            caps_held: false,
+            num_held: false,
+            scroll_held: false,
+        }
+    }
+
+    /// The modifiers in effect.
+    pub fn modifiers(&self) -> Modifiers {
+        let mut modifiers = Modifiers::empty();
+        if self.left_shift || self.right_shift { modifiers.insert(SHIFT); }
+        if self.left_ctrl || self.right_ctrl { modifiers.insert(CTRL); }
+        if self.alt { modifiers.insert(ALT); }
+        if self.alt_gr { modifiers.insert(ALT_GR); }
+        if self.caps_lock { modifiers.insert(CAPS_LOCK); }
+        if self.num_lock { modifiers.insert(NUM_LOCK); }
+        if self.scroll_lock { modifiers.insert(SCROLL_LOCK); }
+        modifiers
+    }
+
+    /// Feed the next byte from the keyboard. Returns an event once a
+    /// whole scancode has arrived.
+    pub fn feed(&mut self, byte: u8) -> Option<KeyEvent> {
+        let (code, pressed) = match self.state {
+            State::Start => match byte {
+                EXTENDED => {
+                    self.state = State::Extended;
+                    return None;
+                },
+                PAUSE => {
+                    self.state = State::Pause(0);
+                    return None;
+                },
+                _ => match key(byte & !RELEASED) {
+                    Some(code) => (code, byte & RELEASED == 0),
+                    None => return None,
+                },
+            },
+            State::Extended => {
+                self.state = State::Start;
+                match extended_key(byte & !RELEASED) {
+                    Some(code) => (code, byte & RELEASED == 0),
+                    // Includes the fake Shifts around Print Screen
+                    None => return None,
+                }
+            },
+            // Pause sends E1 1D 45 on press and E1 9D C5 on release
+            State::Pause(0) => {
+                self.state = State::Pause(1);
+                return None;
+            },
+            State::Pause(_) => {
+                self.state = State::Start;
+                (KeyCode::Pause, byte & RELEASED == 0)
+            },
+        };
+
+        self.update_modifiers(code, pressed);
+        Some(KeyEvent { code: code, pressed: pressed, modifiers: self.modifiers() })
+    }
+
+    /// Keep track of modifier keys.
+    fn update_modifiers(&mut self, code: KeyCode, pressed: bool) {
+        match code {
+            KeyCode::LeftShift => self.left_shift = pressed,
+            KeyCode::RightShift => self.right_shift = pressed,
+            KeyCode::LeftCtrl => self.left_ctrl = pressed,
+            KeyCode::RightCtrl => self.right_ctrl = pressed,
+            KeyCode::LeftAlt => self.alt = pressed,
+            KeyCode::RightAlt => self.alt_gr = pressed,
+            KeyCode::CapsLock => {
+                if pressed && !self.caps_held {
+                    self.caps_lock = !self.caps_lock;
+                }
+                self.caps_held = pressed;
+            },
+            KeyCode::NumLock => {
+                if pressed && !self.num_held {
+                    self.num_lock = !self.num_lock;
+                }
+                self.num_held = pressed;
+            },
+            KeyCode::ScrollLock => {
+                if pressed && !self.scroll_held {
+                    self.scroll_lock = !self.scroll_lock;
+                }
+                self.scroll_held = pressed;
+            },
+            _ => {},
+        }
+    }
+}
+
+/// The key with (unprefixed) scancode `scancode`.
+fn key(scancode: u8) -> Option<KeyCode> {
+    use self::KeyCode::*;
+
+    Some(match scancode {
+        0x01 => Escape,
+        0x02 => Key1, 0x03 => Key2, 0x04 => Key3, 0x05 => Key4, 0x06 => Key5,
+        0x07 => Key6, 0x08 => Key7, 0x09 => Key8, 0x0A => Key9, 0x0B => Key0,
+        0x0C => Minus, 0x0D => Equals, 0x0E => Backspace, 0x0F => Tab,
+        0x10 => Q, 0x11 => W, 0x12 => E, 0x13 => R, 0x14 => T,
+        0x15 => Y, 0x16 => U, 0x17 => I, 0x18 => O, 0x19 => P,
+        0x1A => LeftBracket, 0x1B => RightBracket, 0x1C => Enter,
+        0x1D => LeftCtrl,
+        0x1E => A, 0x1F => S, 0x20 => D, 0x21 => F, 0x22 => G,
+        0x23 => H, 0x24 => J, 0x25 => K, 0x26 => L,
+        0x27 => Semicolon, 0x28 => Quote, 0x29 => Backtick,
+        0x2A => LeftShift, 0x2B => Backslash,
+        0x2C => Z, 0x2D => X, 0x2E => C, 0x2F => V, 0x30 => B,
+        0x31 => N, 0x32 => M,
+        0x33 => Comma, 0x34 => Period, 0x35 => Slash, 0x36 => RightShift,
+        0x37 => KeypadMultiply, 0x38 => LeftAlt, 0x39 => Space,
+        0x3A => CapsLock,
+        0x3B => F1, 0x3C => F2, 0x3D => F3, 0x3E => F4, 0x3F => F5,
+        0x40 => F6, 0x41 => F7, 0x42 => F8, 0x43 => F9, 0x44 => F10,
+        0x45 => NumLock, 0x46 => ScrollLock,
+        0x47 => Keypad7, 0x48 => Keypad8, 0x49 => Keypad9, 0x4A => KeypadMinus,
+        0x4B => Keypad4, 0x4C => Keypad5, 0x4D => Keypad6, 0x4E => KeypadPlus,
+        0x4F => Keypad1, 0x50 => Keypad2, 0x51 => Keypad3,
+        0x52 => Keypad0, 0x53 => KeypadPeriod,
+        0x56 => NonUsBackslash,
+        0x57 => F11, 0x58 => F12,
+        _ => return None,
+    })
+}
+
+/// The key with scancode `scancode` after the 0xE0 prefix.
+fn extended_key(scancode: u8) -> Option<KeyCode> {
+    use self::KeyCode::*;
+
+    Some(match scancode {
+        0x1C => KeypadEnter,
+        0x1D => RightCtrl,
+        0x35 => KeypadDivide,
+        0x37 => PrintScreen,
+        0x38 => RightAlt,
+        0x47 => Home, 0x48 => Up, 0x49 => PageUp,
+        0x4B => Left, 0x4D => Right,
+        0x4F => End, 0x50 => Down, 0x51 => PageDown,
+        0x52 => Insert, 0x53 => Delete,
+        0x5B => LeftGui, 0x5C => RightGui, 0x5D => Menu,
+        _ => return None,
+    })
+}
+
+/// True for the keys with an LED.
+fn is_lock(code: KeyCode) -> bool {
+    code == KeyCode::CapsLock || code == KeyCode::NumLock || code == KeyCode::ScrollLock
+}
+
+static mut DECODER: Decoder = Decoder::new();
+static mut TRANSLATOR: keymap::Translator = keymap::Translator::new();
+
+
+/// Keyboard handler: decode the scancode, and publish the key event and
+/// the characters it types.
+pub unsafe fn getkbd(_arg: usize) {
+    let status: u8;
     let data: u8;
 
     asm!("in al, 0x64"
-          : "={al}"(flag)
+          : "={al}"(status)
           :
           : "{al}"
           : "intel" );
@@ -84,107 +309,88 @@ pub unsafe fn getkbd(arg: usize) {
           : "{al}"
           : "intel" );
 
+    // Mouse data, or a reply to a command: not a key
+    if status & ps2::STATUS_AUX_DATA != 0 || ps2::keyboard_response(data) {
+        io::send_LAPIC_EOI();
+        return;
+    }
 
-    if data == Keycode::SHIFT_PRESSED as u8 {
-        shift = true;
-    } else if data == Keycode::SHIFT_RELEASED as u8 {
-        shift = false;
-    } else if let Some(ref mut buf) = io::kbd_buffer {
-        let mut read_char = data_to_ascii(data);
-        if read_char != 0x00 {
-            // Set lower/uppercase
-            if !shift {
-                if 0x41 <= read_char && read_char <= 0x5A {
-                    read_char += 0x20;
-                } else if read_char == 0xC5 { // Å
-                    read_char = 0xE5;
-                } else if read_char == 0xC4 { // Ä
-                    read_char = 0xE4;
-                } else if read_char == 0xD6 { // Ö
-                    read_char = 0xF6;
-                }
-            }
-            // Write to buffer
-            if !buf.is_full() {
-                buf.write(read_char);
-            }
-            // Set buffer not empty
-            io::kbd_buffer_empty = false;
+    if let Some(event) = DECODER.feed(data) {
+        if event.pressed && is_lock(event.code) {
+            ps2::set_leds(event.modifiers);
         }
-        //println!("Value is {}", buf.read() as char);
-    }
 
-    //println!("Flag: {:x}, data: {:x}, {:x}", flag, data, data_to_ascii(data) );
+        input::publish(Event::Key(event));
+        TRANSLATOR.translate(&event, |c| input::publish(Event::Char(c)));
+    }
 
     io::send_LAPIC_EOI()
 }
 
 
-/// Converts a given keycode to an ASCII character
-/// TODO: Implement Dvorak version
-/// Special characters have been encoded to characters out of the ASCII bound (i e using bit 7 or
-/// values > 127)
-///
-/// Currently included are:
-///
-/// + UP Released (pressed value is undefined): 0x82
-/// + DOWN Released (pressed value is undefined): 0x83
-fn data_to_ascii(data: u8) -> u8 {
-    match data {
-        data if data == Keycode::Q_PRESSED  as u8 => b'Q',
-        data if data == Keycode::W_PRESSED  as u8 => b'W',
-        data if data == Keycode::E_PRESSED  as u8 => b'E',
-        data if data == Keycode::R_PRESSED  as u8 => b'R',
-        data if data == Keycode::T_PRESSED  as u8 => b'T',
-        data if data == Keycode::Z_PRESSED  as u8 => b'Z',
-        data if data == Keycode::U_PRESSED  as u8 => b'U',
-        data if data == Keycode::I_PRESSED  as u8 => b'I',
-        data if data == Keycode::O_PRESSED  as u8 => b'O',
-        data if data == Keycode::P_PRESSED  as u8 => b'P',
-        data if data == Keycode::A_PRESSED  as u8 => b'A',
-        data if data == Keycode::S_PRESSED  as u8 => b'S',
-        data if data == Keycode::D_PRESSED  as u8 => b'D',
-        data if data == Keycode::F_PRESSED  as u8 => b'F',
-        data if data == Keycode::G_PRESSED  as u8 => b'G',
-        data if data == Keycode::H_PRESSED  as u8 => b'H',
-        data if data == Keycode::J_PRESSED  as u8 => b'J',
-        data if data == Keycode::K_PRESSED  as u8 => b'K',
-        data if data == Keycode::L_PRESSED  as u8 => b'L',
-        data if data == Keycode::Y_PRESSED  as u8 => b'Y',
-        data if data == Keycode::X_PRESSED  as u8 => b'X',
-        data if data == Keycode::C_PRESSED  as u8 => b'C',
-        data if data == Keycode::V_PRESSED  as u8 => b'V',
-        data if data == Keycode::B_PRESSED  as u8 => b'B',
-        data if data == Keycode::N_PRESSED  as u8 => b'N',
-        data if data == Keycode::M_PRESSED  as u8 => b'M',
-
-        data if data == Keycode::AA_PRESSED  as u8 => 0xC5,
-        data if data == Keycode::AE_PRESSED  as u8 => 0xC4,
-        data if data == Keycode::OE_PRESSED  as u8 => 0xD6,
-
-        data if data == Keycode::ZERO_PRESSED   as u8 => b'0',
-        data if data == Keycode::ONE_PRESSED    as u8 => b'1',
-        data if data == Keycode::TWO_PRESSED    as u8 => b'2',
-        data if data == Keycode::THREE_PRESSED  as u8 => b'3',
-        data if data == Keycode::FOUR_PRESSED   as u8 => b'4',
-        data if data == Keycode::FIVE_PRESSED   as u8 => b'5',
-        data if data == Keycode::SIX_PRESSED    as u8 => b'6',
-        data if data == Keycode::SEVEN_PRESSED  as u8 => b'7',
-        data if data == Keycode::EIGHT_PRESSED  as u8 => b'8',
-        data if data == Keycode::NINE_PRESSED   as u8 => b'9',
-
-        data if data == Keycode::ENTER_PRESSED  as u8 => b'\n',
-        data if data == Keycode::SPACE_PRESSED  as u8 => b' ',
-        data if data == Keycode::DASH_PRESSED   as u8 => b'-',
-        data if data == Keycode::SLASH_PRESSED  as u8 => b'-',
-
-        data if data == Keycode::BACKSPACE_PRESSED as u8 => 0x08,
-
-
-        //data if data == Keycode::LEFT_RELEASED  as u8 => 0x80,
-        //data if data == Keycode::RIGHT_RELEASED as u8 => 0x81,
-        data if data == Keycode::UP_RELEASED    as u8 => 0x82,
-        data if data == Keycode::DOWN_RELEASED  as u8 => 0x83,
-        _ => 0x00,
+#[test]
+/// Plain keys, pressed and released
+fn decode_plain_key() {
+    let mut decoder = Decoder::new();
+    let event = decoder.feed(0x1E).unwrap();
+    assert_eq!(event.code, KeyCode::A);
+    assert!(event.pressed);
+    assert!(!decoder.feed(0x9E).unwrap().pressed);
+}
+
+#[test]
+/// Extended keys are told apart from the keypad keys sharing their codes
+fn decode_extended_key() {
+    let mut decoder = Decoder::new();
+    assert_eq!(decoder.feed(0xE0), None);
+    assert_eq!(decoder.feed(0x48).unwrap().code, KeyCode::Up);
+    assert_eq!(decoder.feed(0x48).unwrap().code, KeyCode::Keypad8);
+    assert_eq!(decoder.feed(0xE0), None);
+    let event = decoder.feed(0xC8).unwrap();
+    assert_eq!(event.code, KeyCode::Up);
+    assert!(!event.pressed);
+}
+
+#[test]
+/// Modifiers stay on while held, and either Shift will do
+fn decode_modifiers() {
+    let mut decoder = Decoder::new();
+    decoder.feed(0x2A);
+    decoder.feed(0x36);
+    decoder.feed(0xAA);
+    assert!(decoder.feed(0x1E).unwrap().modifiers.contains(SHIFT));
+    decoder.feed(0xB6);
+    decoder.feed(0xE0);
+    decoder.feed(0x38);
+    let modifiers = decoder.feed(0x1E).unwrap().modifiers;
+    assert_eq!(modifiers, ALT_GR);
+}
+
+#[test]
+/// Caps Lock toggles on press, not while repeating
+fn decode_caps_lock() {
+    let mut decoder = Decoder::new();
+    decoder.feed(0x3A);
+    decoder.feed(0x3A);
+    decoder.feed(0xBA);
+    assert!(decoder.modifiers().contains(CAPS_LOCK));
+    decoder.feed(0x3A);
+    decoder.feed(0xBA);
+    assert!(!decoder.modifiers().contains(CAPS_LOCK));
+}
+
+#[test]
+/// The Pause sequence gives one press and one release
+fn decode_pause() {
+    let mut decoder = Decoder::new();
+    for &byte in &[0xE1, 0x1D] {
+        assert_eq!(decoder.feed(byte), None);
+    }
+    let press = decoder.feed(0x45).unwrap();
+    assert_eq!(press.code, KeyCode::Pause);
+    assert!(press.pressed);
+    for &byte in &[0xE1, 0x9D] {
+        assert_eq!(decoder.feed(byte), None);
     }
+    assert!(!decoder.feed(0xC5).unwrap().pressed);
 }
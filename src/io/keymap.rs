@@ -0,0 +1,445 @@
+//! Keyboard layouts: turning key events into typed characters.
+//!
+//! Layouts cover Latin-1. Ctrl+letter types the matching control
+//! character (Ctrl+C is '\x03'), Enter '\n' and Tab '\t'. Other keys
+//! without a character, like the arrows or Backspace, type nothing:
+//! look at their `KeyEvent`s instead.
+//!
+//! The layout can be changed at any time with `set_keymap()`. Layouts:
+//!
+//! + `us`: US QWERTY
+//! + `sv`: Swedish, with symbols on AltGr and dead keys for accents
+//! + `dvorak`: US Dvorak
+//!
+//! A dead key types nothing by itself, but puts its accent on the next
+//! character (´ then e gives é). If the two don't combine, both are
+//! typed; a space after a dead key types just the accent.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use io::kbd::{KeyCode, KeyEvent, Modifiers, SHIFT, CTRL, ALT_GR, CAPS_LOCK, NUM_LOCK};
+
+/// An accent typed with a dead key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Accent {
+    Acute,
+    Grave,
+    Diaeresis,
+    Circumflex,
+    Tilde,
+}
+
+/// What a key types at one shift level.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Sym {
+    Nothing,
+    Char(u8),
+    Dead(Accent),
+}
+
+/// What a key types without Shift, with Shift, and with AltGr.
+#[derive(Clone, Copy)]
+struct Keys {
+    normal: Sym,
+    shifted: Sym,
+    alt_gr: Sym,
+}
+
+/// A keyboard layout.
+pub struct Keymap {
+    /// Short name, used by `set_keymap()`
+    pub name: &'static str,
+    pub description: &'static str,
+    /// What each key types
+    lookup: fn(KeyCode) -> Option<Keys>,
+}
+
+/// Every layout there is.
+pub static KEYMAPS: [Keymap; 3] = [
+    Keymap { name: "us", description: "US QWERTY", lookup: us },
+    Keymap { name: "sv", description: "Swedish", lookup: swedish },
+    Keymap { name: "dvorak", description: "US Dvorak", lookup: dvorak },
+];
+
+/// Index in `KEYMAPS` of the layout in use.
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+
+/// The layout in use.
+pub fn current() -> &'static Keymap {
+    &KEYMAPS[CURRENT.load(Ordering::Relaxed)]
+}
+
+/// Switch to the layout called `name`. Returns `false` if there is no
+/// such layout.
+pub fn set_keymap(name: &str) -> bool {
+    match KEYMAPS.iter().position(|k| k.name == name) {
+        Some(index) => {
+            CURRENT.store(index, Ordering::Relaxed);
+            true
+        },
+        None => false,
+    }
+}
+
+/// Turns key events into input using the current layout, remembering
+/// dead keys in between.
+pub struct Translator {
+    dead: Option<Accent>,
+}
+
+impl Translator {
+    pub const fn new() -> Translator {
+        Translator { dead: None }
+    }
+
+    /// Call `emit` with the characters typed by `event`, if any. Only
+    /// key presses type anything.
+    pub fn translate<F>(&mut self, event: &KeyEvent, mut emit: F)
+        where F: FnMut(char)
+    {
+        // The layouts are in Latin-1, which is the first 256 code points
+        self.translate_latin1(current(), event, |byte| emit(byte as char));
+    }
+
+    fn translate_latin1<F>(&mut self, keymap: &Keymap, event: &KeyEvent,
+                           mut emit: F)
+        where F: FnMut(u8)
+    {
+        if !event.pressed || is_modifier(event.code) {
+            return;
+        }
+
+        if let Some(byte) = special(event.code, event.modifiers) {
+            self.flush(&mut emit);
+            emit(byte);
+            return;
+        }
+
+        let keys = match (keymap.lookup)(event.code) {
+            Some(keys) => keys,
+            None => return,
+        };
+
+        if event.modifiers.contains(CTRL) {
+            // Ctrl+A is 1, ..., Ctrl+Z is 26
+            if let Sym::Char(c) = keys.normal {
+                if let b'a'...b'z' = c {
+                    self.dead = None;
+                    emit(c - b'a' + 1);
+                }
+            }
+            return;
+        }
+
+        match sym(keys, event.modifiers) {
+            Sym::Nothing => {},
+            Sym::Dead(accent) => match self.dead.take() {
+                // The same dead key twice types the accent
+                Some(dead) if dead == accent => emit(accent_char(accent)),
+                Some(dead) => {
+                    emit(accent_char(dead));
+                    self.dead = Some(accent);
+                },
+                None => self.dead = Some(accent),
+            },
+            Sym::Char(c) => match self.dead.take() {
+                Some(accent) if c == b' ' => emit(accent_char(accent)),
+                Some(accent) => match compose(accent, c) {
+                    Some(composed) => emit(composed),
+                    None => {
+                        emit(accent_char(accent));
+                        emit(c);
+                    },
+                },
+                None => emit(c),
+            },
+        }
+    }
+
+    /// Type the accent of a pending dead key, if any.
+    fn flush<F>(&mut self, emit: &mut F) where F: FnMut(u8) {
+        if let Some(accent) = self.dead.take() {
+            emit(accent_char(accent));
+        }
+    }
+}
+
+/// True for keys that only change what other keys type.
+fn is_modifier(code: KeyCode) -> bool {
+    use io::kbd::KeyCode::*;
+
+    match code {
+        LeftShift | RightShift | LeftCtrl | RightCtrl | LeftAlt | RightAlt |
+        LeftGui | RightGui | CapsLock | NumLock | ScrollLock => true,
+        _ => false,
+    }
+}
+
+/// What `keys` types with `modifiers`.
+fn sym(keys: Keys, modifiers: Modifiers) -> Sym {
+    if modifiers.contains(ALT_GR) {
+        return keys.alt_gr;
+    }
+
+    // Caps Lock works like Shift, but only for letters
+    let mut shift = modifiers.contains(SHIFT);
+    if modifiers.contains(CAPS_LOCK) && is_letter(keys) {
+        shift = !shift;
+    }
+
+    if shift { keys.shifted } else { keys.normal }
+}
+
+/// True if `keys` types a lower case letter, and its upper case with
+/// Shift.
+fn is_letter(keys: Keys) -> bool {
+    match (keys.normal, keys.shifted) {
+        (Sym::Char(normal), Sym::Char(shifted)) => match normal {
+            b'a'...b'z' | 0xE0...0xF6 | 0xF8...0xFE => shifted == normal - 0x20,
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Characters typed by keys that type the same thing in every layout.
+fn special(code: KeyCode, modifiers: Modifiers) -> Option<u8> {
+    use io::kbd::KeyCode::*;
+
+    // Without Num Lock (or with Shift), the keypad is for moving around
+    if !modifiers.contains(NUM_LOCK) || modifiers.contains(SHIFT) {
+        match code {
+            Keypad0 | Keypad1 | Keypad2 | Keypad3 | Keypad4 | Keypad5 |
+            Keypad6 | Keypad7 | Keypad8 | Keypad9 | KeypadPeriod => return None,
+            _ => {},
+        }
+    }
+
+    Some(match code {
+        Tab => b'\t',
+        Enter | KeypadEnter => b'\n',
+
+        Keypad0 => b'0', Keypad1 => b'1', Keypad2 => b'2', Keypad3 => b'3',
+        Keypad4 => b'4', Keypad5 => b'5', Keypad6 => b'6', Keypad7 => b'7',
+        Keypad8 => b'8', Keypad9 => b'9',
+        KeypadPeriod => b'.',
+        KeypadPlus => b'+', KeypadMinus => b'-',
+        KeypadMultiply => b'*', KeypadDivide => b'/',
+
+        _ => return None,
+    })
+}
+
+/// The character typed for `accent` on its own.
+fn accent_char(accent: Accent) -> u8 {
+    match accent {
+        Accent::Acute => 0xB4,
+        Accent::Grave => b'`',
+        Accent::Diaeresis => 0xA8,
+        Accent::Circumflex => b'^',
+        Accent::Tilde => b'~',
+    }
+}
+
+/// `c` with `accent`, if Latin-1 has it.
+fn compose(accent: Accent, c: u8) -> Option<u8> {
+    use self::Accent::*;
+
+    Some(match (accent, c) {
+        (Acute, b'a') => 0xE1, (Acute, b'e') => 0xE9, (Acute, b'i') => 0xED,
+        (Acute, b'o') => 0xF3, (Acute, b'u') => 0xFA, (Acute, b'y') => 0xFD,
+        (Acute, b'A') => 0xC1, (Acute, b'E') => 0xC9, (Acute, b'I') => 0xCD,
+        (Acute, b'O') => 0xD3, (Acute, b'U') => 0xDA, (Acute, b'Y') => 0xDD,
+
+        (Grave, b'a') => 0xE0, (Grave, b'e') => 0xE8, (Grave, b'i') => 0xEC,
+        (Grave, b'o') => 0xF2, (Grave, b'u') => 0xF9,
+        (Grave, b'A') => 0xC0, (Grave, b'E') => 0xC8, (Grave, b'I') => 0xCC,
+        (Grave, b'O') => 0xD2, (Grave, b'U') => 0xD9,
+
+        (Diaeresis, b'a') => 0xE4, (Diaeresis, b'e') => 0xEB, (Diaeresis, b'i') => 0xEF,
+        (Diaeresis, b'o') => 0xF6, (Diaeresis, b'u') => 0xFC, (Diaeresis, b'y') => 0xFF,
+        (Diaeresis, b'A') => 0xC4, (Diaeresis, b'E') => 0xCB, (Diaeresis, b'I') => 0xCF,
+        (Diaeresis, b'O') => 0xD6, (Diaeresis, b'U') => 0xDC,
+
+        (Circumflex, b'a') => 0xE2, (Circumflex, b'e') => 0xEA, (Circumflex, b'i') => 0xEE,
+        (Circumflex, b'o') => 0xF4, (Circumflex, b'u') => 0xFB,
+        (Circumflex, b'A') => 0xC2, (Circumflex, b'E') => 0xCA, (Circumflex, b'I') => 0xCE,
+        (Circumflex, b'O') => 0xD4, (Circumflex, b'U') => 0xDB,
+
+        (Tilde, b'a') => 0xE3, (Tilde, b'n') => 0xF1, (Tilde, b'o') => 0xF5,
+        (Tilde, b'A') => 0xC3, (Tilde, b'N') => 0xD1, (Tilde, b'O') => 0xD5,
+
+        _ => return None,
+    })
+}
+
+/// A key typing `normal`, and `shifted` with Shift.
+fn key(normal: u8, shifted: u8) -> Keys {
+    Keys { normal: Sym::Char(normal), shifted: Sym::Char(shifted), alt_gr: Sym::Nothing }
+}
+
+/// A key typing `normal`, `shifted` with Shift and `alt_gr` with AltGr.
+fn key3(normal: u8, shifted: u8, alt_gr: u8) -> Keys {
+    Keys { normal: Sym::Char(normal), shifted: Sym::Char(shifted), alt_gr: Sym::Char(alt_gr) }
+}
+
+/// A key typing the (Latin-1) lower case letter `c`.
+fn letter(c: u8) -> Keys {
+    key(c, c - 0x20)
+}
+
+/// The keys from 1 to 0 and space, the same in the US layouts.
+fn us_common(code: KeyCode) -> Option<Keys> {
+    use io::kbd::KeyCode::*;
+
+    Some(match code {
+        Key1 => key(b'1', b'!'), Key2 => key(b'2', b'@'), Key3 => key(b'3', b'#'),
+        Key4 => key(b'4', b'$'), Key5 => key(b'5', b'%'), Key6 => key(b'6', b'^'),
+        Key7 => key(b'7', b'&'), Key8 => key(b'8', b'*'), Key9 => key(b'9', b'('),
+        Key0 => key(b'0', b')'),
+        Backtick => key(b'`', b'~'),
+        Backslash | NonUsBackslash => key(b'\\', b'|'),
+        Space => key(b' ', b' '),
+        _ => return None,
+    })
+}
+
+/// US QWERTY.
+fn us(code: KeyCode) -> Option<Keys> {
+    use io::kbd::KeyCode::*;
+
+    Some(match code {
+        Q => letter(b'q'), W => letter(b'w'), E => letter(b'e'), R => letter(b'r'),
+        T => letter(b't'), Y => letter(b'y'), U => letter(b'u'), I => letter(b'i'),
+        O => letter(b'o'), P => letter(b'p'), A => letter(b'a'), S => letter(b's'),
+        D => letter(b'd'), F => letter(b'f'), G => letter(b'g'), H => letter(b'h'),
+        J => letter(b'j'), K => letter(b'k'), L => letter(b'l'), Z => letter(b'z'),
+        X => letter(b'x'), C => letter(b'c'), V => letter(b'v'), B => letter(b'b'),
+        N => letter(b'n'), M => letter(b'm'),
+
+        Minus => key(b'-', b'_'),
+        Equals => key(b'=', b'+'),
+        LeftBracket => key(b'[', b'{'),
+        RightBracket => key(b']', b'}'),
+        Semicolon => key(b';', b':'),
+        Quote => key(b'\'', b'"'),
+        Comma => key(b',', b'<'),
+        Period => key(b'.', b'>'),
+        Slash => key(b'/', b'?'),
+
+        code => return us_common(code),
+    })
+}
+
+/// US Dvorak.
+fn dvorak(code: KeyCode) -> Option<Keys> {
+    use io::kbd::KeyCode::*;
+
+    Some(match code {
+        Minus => key(b'[', b'{'),
+        Equals => key(b']', b'}'),
+
+        Q => key(b'\'', b'"'), W => key(b',', b'<'), E => key(b'.', b'>'),
+        R => letter(b'p'), T => letter(b'y'), Y => letter(b'f'), U => letter(b'g'),
+        I => letter(b'c'), O => letter(b'r'), P => letter(b'l'),
+        LeftBracket => key(b'/', b'?'),
+        RightBracket => key(b'=', b'+'),
+
+        A => letter(b'a'), S => letter(b'o'), D => letter(b'e'), F => letter(b'u'),
+        G => letter(b'i'), H => letter(b'd'), J => letter(b'h'), K => letter(b't'),
+        L => letter(b'n'), Semicolon => letter(b's'),
+        Quote => key(b'-', b'_'),
+
+        Z => key(b';', b':'), X => letter(b'q'), C => letter(b'j'), V => letter(b'k'),
+        B => letter(b'x'), N => letter(b'b'), M => letter(b'm'),
+        Comma => letter(b'w'), Period => letter(b'v'), Slash => letter(b'z'),
+
+        code => return us_common(code),
+    })
+}
+
+/// Swedish.
+fn swedish(code: KeyCode) -> Option<Keys> {
+    use io::kbd::KeyCode::*;
+
+    Some(match code {
+        Backtick => key(0xA7, 0xBD),                // § ½
+        Key1 => key(b'1', b'!'),
+        Key2 => key3(b'2', b'"', b'@'),
+        Key3 => key3(b'3', b'#', 0xA3),             // £
+        Key4 => key3(b'4', 0xA4, b'$'),             // ¤
+        Key5 => key(b'5', b'%'),
+        Key6 => key(b'6', b'&'),
+        Key7 => key3(b'7', b'/', b'{'),
+        Key8 => key3(b'8', b'(', b'['),
+        Key9 => key3(b'9', b')', b']'),
+        Key0 => key3(b'0', b'=', b'}'),
+        Minus => key3(b'+', b'?', b'\\'),
+        Equals => Keys {
+            normal: Sym::Dead(Accent::Acute),
+            shifted: Sym::Dead(Accent::Grave),
+            alt_gr: Sym::Nothing,
+        },
+
+        Q => letter(b'q'), W => letter(b'w'), E => letter(b'e'), R => letter(b'r'),
+        T => letter(b't'), Y => letter(b'y'), U => letter(b'u'), I => letter(b'i'),
+        O => letter(b'o'), P => letter(b'p'),
+        LeftBracket => letter(0xE5),                // å
+        RightBracket => Keys {
+            normal: Sym::Dead(Accent::Diaeresis),
+            shifted: Sym::Dead(Accent::Circumflex),
+            alt_gr: Sym::Dead(Accent::Tilde),
+        },
+
+        A => letter(b'a'), S => letter(b's'), D => letter(b'd'), F => letter(b'f'),
+        G => letter(b'g'), H => letter(b'h'), J => letter(b'j'), K => letter(b'k'),
+        L => letter(b'l'),
+        Semicolon => letter(0xF6),                  // ö
+        Quote => letter(0xE4),                      // ä
+        Backslash => key(b'\'', b'*'),
+
+        NonUsBackslash => key3(b'<', b'>', b'|'),
+        Z => letter(b'z'), X => letter(b'x'), C => letter(b'c'), V => letter(b'v'),
+        B => letter(b'b'), N => letter(b'n'),
+        M => key3(b'm', b'M', 0xB5),                // µ
+        Comma => key(b',', b';'),
+        Period => key(b'.', b':'),
+        Slash => key(b'-', b'_'),
+        Space => key(b' ', b' '),
+
+        _ => return None,
+    })
+}
+
+
+#[cfg(test)]
+fn press(code: KeyCode, modifiers: Modifiers) -> KeyEvent {
+    KeyEvent { code: code, pressed: true, modifiers: modifiers }
+}
+
+#[test]
+/// Dead keys combine with the next letter, or type their accent
+fn keymap_dead_keys() {
+    let swedish = KEYMAPS.iter().find(|k| k.name == "sv").unwrap();
+    let mut translator = Translator::new();
+    let mut typed = [0; 4];
+    let mut count = 0;
+    {
+        let mut emit = |c| { typed[count] = c; count += 1; };
+        for &code in &[KeyCode::Equals, KeyCode::E, KeyCode::Equals, KeyCode::X] {
+            translator.translate_latin1(swedish, &press(code, Modifiers::empty()),
+                                        &mut emit);
+        }
+    }
+    // é, ´ and x
+    assert_eq!(&typed[..count], &[0xE9, 0xB4, b'x']);
+}
+
+#[test]
+/// Caps Lock only affects letters
+fn keymap_caps_lock() {
+    let keys = letter(0xE5);
+    assert_eq!(sym(keys, CAPS_LOCK), Sym::Char(0xC5));
+    assert_eq!(sym(keys, CAPS_LOCK | SHIFT), Sym::Char(0xE5));
+    assert_eq!(sym(key(b'1', b'!'), CAPS_LOCK), Sym::Char(b'1'));
+}
@@ -11,11 +11,10 @@ use irq;
 
 
 pub mod kbd;
-
-// TODO: Put the following into a scheduler
-use pipe::Buffer;
-pub static mut kbd_buffer: Option<Buffer> = None;
-pub static mut kbd_buffer_empty: bool = true;
+pub mod keymap;
+pub mod ps2;
+pub mod mouse;
+pub mod serial;
 
 
 static mut LAPIC_BASE: usize = 0;
@@ -28,9 +27,26 @@ const IOWIN_RESERVED_HI : u32 = 0x00FF_FFFF;
 // Conventional registry offsets, write to IOREGSEL
 const KBD_IOWIN_LO : u32 = 0x12;
 const KBD_IOWIN_HI : u32 = 0x13;
+const MOUSE_IOWIN_LO : u32 = 0x28;
+const MOUSE_IOWIN_HI : u32 = 0x29;
+const SERIAL_IOWIN_LO : u32 = 0x18;
+const SERIAL_IOWIN_HI : u32 = 0x19;
 
 
+/// Local APIC ID, in the top byte
+const LAPIC_ID : u16 = 0x0020;
 const LAPIC_EOI : u16 = 0x00B0;
+/// Local vector table entry for performance counter overflows
+const LAPIC_LVT_PERF : u16 = 0x0340;
+/// Interrupt command register, low half
+const LAPIC_ICR_LO : u16 = 0x0300;
+
+// Interrupt command bits
+/// Send to every CPU but this one
+const ICR_ALL_BUT_SELF : u32 = 0b11 << 18;
+const ICR_LEVEL_ASSERT : u32 = 1 << 14;
+/// INIT delivery mode: the CPU waits for a startup IPI
+const ICR_INIT : u32 = 0b101 << 8;
 
 
 
@@ -44,11 +60,18 @@ pub fn install_io(lapic_addr: usize, ioapic_addr: usize) {
     // Generate redirection table for I/O
     unsafe { gen_ioredtable(ioapic_addr as *mut u32); }
 
-    unsafe { kbd_buffer = Some(*Buffer::new()); }
+    // Set up the input devices
+    ps2::init();
+    mouse::init();
+    if serial::init() {
+        info!("serial port COM1 found");
+    }
 
     // Set handlers
     let kbdh = kbd::getkbd;
     irq::set_handler(0x80, kbdh);
+    irq::set_handler(0x81, mouse::getmouse);
+    irq::set_handler(0x82, serial::getserial);
 }
 
 
@@ -105,8 +128,8 @@ unsafe fn print_ioreg(ioapicaddr: *mut u32) {
     let ver = read_ioapic(ioapicaddr, 0x01);
     let arb = read_ioapic(ioapicaddr, 0x02);
 
-    println!("IOAPIC ID: {:x}, VER: {:x}, ARB: {:x}",
-             id, ver, arb);
+    debug!("IOAPIC ID: {:x}, VER: {:x}, ARB: {:x}",
+           id, ver, arb);
 
 }
 
@@ -128,12 +151,32 @@ pub unsafe fn gen_ioredtable(ioapicaddr: *mut u32) {
     write_ioapic(ioapicaddr, KBD_IOWIN_HI, kbd_hi | read_kbd_hi);
     write_ioapic(ioapicaddr, KBD_IOWIN_LO, kbd_lo | read_kbd_lo);
 
+    // The PS/2 mouse is IRQ 12
+    let read_mouse_hi = read_ioapic(ioapicaddr, MOUSE_IOWIN_HI) & IOWIN_RESERVED_HI;
+    let read_mouse_lo = read_ioapic(ioapicaddr, MOUSE_IOWIN_LO) & IOWIN_RESERVED_LO;
+
+    let (mouse_hi, mouse_lo) =
+        gen_irq(0, 0, 0, 0, 0, 0b000, 0x81);
+
+    write_ioapic(ioapicaddr, MOUSE_IOWIN_HI, mouse_hi | read_mouse_hi);
+    write_ioapic(ioapicaddr, MOUSE_IOWIN_LO, mouse_lo | read_mouse_lo);
+
+    // COM1 is IRQ 4
+    let read_serial_hi = read_ioapic(ioapicaddr, SERIAL_IOWIN_HI) & IOWIN_RESERVED_HI;
+    let read_serial_lo = read_ioapic(ioapicaddr, SERIAL_IOWIN_LO) & IOWIN_RESERVED_LO;
+
+    let (serial_hi, serial_lo) =
+        gen_irq(0, 0, 0, 0, 0, 0b000, 0x82);
+
+    write_ioapic(ioapicaddr, SERIAL_IOWIN_HI, serial_hi | read_serial_hi);
+    write_ioapic(ioapicaddr, SERIAL_IOWIN_LO, serial_lo | read_serial_lo);
+
 
     let res_kbd_hi = read_ioapic(ioapicaddr, KBD_IOWIN_HI);
     let res_kbd_lo = read_ioapic(ioapicaddr, KBD_IOWIN_LO);
 
-    // println!("KBD INT HI: {:x}, KBD INT LO: {:x}",
-    //          res_kbd_hi, res_kbd_lo);
+    debug!("KBD INT HI: {:x}, KBD INT LO: {:x}",
+           res_kbd_hi, res_kbd_lo);
 }
 
 
@@ -227,6 +270,42 @@ pub fn disable_pic() {
     }
 }
 
+/// Stop every other CPU, by sending them an INIT IPI. Does nothing
+/// before `install_io()`.
+pub fn stop_other_cpus() {
+    unsafe {
+        if LAPIC_BASE == 0 {
+            return;
+        }
+        let lapic_reg = (LAPIC_BASE | (LAPIC_ICR_LO as usize)) as *mut u32;
+        volatile_store(lapic_reg, ICR_ALL_BUT_SELF | ICR_LEVEL_ASSERT | ICR_INIT);
+    }
+}
+
+/// The local APIC ID of this CPU. Always 0 before `install_io()`.
+pub fn cpu_id() -> usize {
+    unsafe {
+        if LAPIC_BASE == 0 {
+            return 0;
+        }
+        let lapic_reg = (LAPIC_BASE | (LAPIC_ID as usize)) as *const u32;
+        (volatile_load(lapic_reg) >> 24) as usize
+    }
+}
+
+/// Deliver performance counter overflow interrupts at `vector`. A PMI
+/// masks the entry, so the handler must call this again to get the next
+/// one. Does nothing before `install_io()`.
+pub fn route_perf_interrupt(vector: u8) {
+    unsafe {
+        if LAPIC_BASE == 0 {
+            return;
+        }
+        let lapic_reg = (LAPIC_BASE | (LAPIC_LVT_PERF as usize)) as *mut u32;
+        volatile_store(lapic_reg, vector as u32);
+    }
+}
+
 /// Send the End-of-Interrupt (EOI) signal to the LAPIC.
 pub fn send_LAPIC_EOI() {
 
@@ -0,0 +1,262 @@
+//! PS/2 mouse on the second port of the 8042 controller.
+//!
+//! The mouse sends a packet of three bytes for every change, or four if
+//! it has a scroll wheel (an "IntelliMouse"). The interrupt handler
+//! puts the packets together, turns them into `MouseEvent`s and
+//! publishes them as input events.
+//!
+//! `pointer_thread` shows the mouse as a block on the VGA console.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use input::{self, Event, InputEvent};
+use io;
+use io::ps2::{self, Port};
+use vga_buffer;
+
+static PRESENT: AtomicBool = AtomicBool::new(false);
+
+// Mouse commands
+const SET_SAMPLE_RATE: u8 = 0xF3;
+const GET_DEVICE_ID: u8 = 0xF2;
+const SET_DEFAULTS: u8 = 0xF6;
+const ENABLE_REPORTING: u8 = 0xF4;
+
+/// Device ID of a mouse with a scroll wheel
+const ID_INTELLIMOUSE: u8 = 3;
+
+/// Samples per second
+const SAMPLE_RATE: u8 = 100;
+
+// Bits of the first byte of a packet
+const LEFT_BUTTON: u8 = 1 << 0;
+const RIGHT_BUTTON: u8 = 1 << 1;
+const MIDDLE_BUTTON: u8 = 1 << 2;
+/// Always set; used to find the start of a packet
+const ALWAYS_ONE: u8 = 1 << 3;
+const X_SIGN: u8 = 1 << 4;
+const Y_SIGN: u8 = 1 << 5;
+const X_OVERFLOW: u8 = 1 << 6;
+const Y_OVERFLOW: u8 = 1 << 7;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MouseButton {
+    Left,
+    Right,
+    Middle,
+}
+
+/// Something the mouse did.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MouseEvent {
+    /// Moved right by `dx` and up by `dy`
+    Moved { dx: i16, dy: i16 },
+    Button { button: MouseButton, pressed: bool },
+    /// Wheel turned, positive towards the user
+    Scrolled(i8),
+}
+
+/// Puts packets together and turns them into events.
+struct Decoder {
+    packet: [u8; 4],
+    received: usize,
+    /// 3, or 4 with a scroll wheel
+    packet_size: usize,
+    /// Button bits of the last packet
+    buttons: u8,
+}
+
+impl Decoder {
+    const fn new() -> Decoder {
+        Decoder { packet: [0; 4], received: 0, packet_size: 3, buttons: 0 }
+    }
+
+    /// Feed the next byte from the mouse, and call `emit` with every
+    /// event once a whole packet has arrived.
+    fn feed<F>(&mut self, byte: u8, mut emit: F) where F: FnMut(MouseEvent) {
+        // Lost track of where packets start: wait for one that could
+        if self.received == 0 && byte & ALWAYS_ONE == 0 {
+            return;
+        }
+
+        self.packet[self.received] = byte;
+        self.received += 1;
+        if self.received < self.packet_size {
+            return;
+        }
+        self.received = 0;
+
+        let flags = self.packet[0];
+        if flags & (X_OVERFLOW | Y_OVERFLOW) == 0 {
+            // 9-bit two's complement, with the sign bit in `flags`
+            let dx = self.packet[1] as i16 - if flags & X_SIGN != 0 { 0x100 } else { 0 };
+            let dy = self.packet[2] as i16 - if flags & Y_SIGN != 0 { 0x100 } else { 0 };
+            if dx != 0 || dy != 0 {
+                emit(MouseEvent::Moved { dx: dx, dy: dy });
+            }
+        }
+
+        for &(bit, button) in &[(LEFT_BUTTON, MouseButton::Left),
+                                (RIGHT_BUTTON, MouseButton::Right),
+                                (MIDDLE_BUTTON, MouseButton::Middle)] {
+            if (flags ^ self.buttons) & bit != 0 {
+                emit(MouseEvent::Button { button: button, pressed: flags & bit != 0 });
+            }
+        }
+        self.buttons = flags & (LEFT_BUTTON | RIGHT_BUTTON | MIDDLE_BUTTON);
+
+        if self.packet_size == 4 && self.packet[3] != 0 {
+            emit(MouseEvent::Scrolled(self.packet[3] as i8));
+        }
+    }
+}
+
+static mut DECODER: Decoder = Decoder::new();
+
+/// Set up the mouse, if there is one. Must be called with interrupts
+/// disabled, after `ps2::init()`. Returns `true` if there is a mouse.
+pub fn init() -> bool {
+    if !ps2::aux_present() {
+        return false;
+    }
+
+    match unsafe { init_device() } {
+        Ok(wheel) => {
+            if wheel {
+                unsafe { DECODER.packet_size = 4 };
+            }
+            PRESENT.store(true, Ordering::Relaxed);
+            info!("PS/2 mouse found{}", if wheel { ", with scroll wheel" } else { "" });
+            true
+        },
+        Err(error) => {
+            warn!("PS/2 mouse not working: {:?}", error);
+            false
+        },
+    }
+}
+
+/// True if `init()` found a mouse.
+pub fn present() -> bool {
+    PRESENT.load(Ordering::Relaxed)
+}
+
+/// Configure the mouse and turn on reporting. Returns `true` if it has
+/// a scroll wheel.
+unsafe fn init_device() -> Result<bool, ps2::Error> {
+    try!(ps2::device_command(Port::Second, SET_DEFAULTS));
+
+    // The magic knock for turning on the scroll wheel: sample rates
+    // 200, 100, 80. Only IntelliMice answer with the new device ID.
+    for &rate in &[200, 100, 80] {
+        try!(ps2::device_command(Port::Second, SET_SAMPLE_RATE));
+        try!(ps2::device_command(Port::Second, rate));
+    }
+    try!(ps2::device_command(Port::Second, GET_DEVICE_ID));
+    let wheel = try!(ps2::device_read()) == ID_INTELLIMOUSE;
+
+    try!(ps2::device_command(Port::Second, SET_SAMPLE_RATE));
+    try!(ps2::device_command(Port::Second, SAMPLE_RATE));
+    try!(ps2::device_command(Port::Second, ENABLE_REPORTING));
+    try!(ps2::enable_interrupts(Port::Second));
+    Ok(wheel)
+}
+
+/// Mouse interrupt handler.
+pub unsafe fn getmouse(_arg: usize) {
+    let status: u8;
+    let data: u8;
+
+    asm!("in al, 0x64"
+          : "={al}"(status)
+          :
+          : "{al}"
+          : "intel" );
+
+    if status & ps2::STATUS_AUX_DATA != 0 {
+        asm!("in al, 0x60"
+              : "={al}"(data)
+              :
+              : "{al}"
+              : "intel" );
+
+        DECODER.feed(data, |event| input::publish(Event::Mouse(event)));
+    }
+
+    io::send_LAPIC_EOI()
+}
+
+/// Mouse movement per character cell on the screen
+const MICKEYS_PER_COLUMN: i32 = 8;
+const MICKEYS_PER_ROW: i32 = 16;
+
+/// Thread entry point: move a pointer around the screen as the mouse
+/// moves. The pointer is hidden while the left button is down.
+pub fn pointer_thread(_arg: usize) {
+    let mouse = input::subscribe(input::MOUSE);
+
+    let (width, height) = vga_buffer::size();
+    let max_x = width as i32 * MICKEYS_PER_COLUMN - 1;
+    let max_y = height as i32 * MICKEYS_PER_ROW - 1;
+    let (mut x, mut y) = (max_x / 2, max_y / 2);
+    let mut visible = true;
+
+    loop {
+        let mut next = Some(mouse.read());
+
+        // Catch up with everything there is before moving the pointer
+        while let Some(InputEvent { event: Event::Mouse(event), .. }) = next {
+            match event {
+                MouseEvent::Moved { dx, dy } => {
+                    x = clamp(x + dx as i32, 0, max_x);
+                    // The mouse counts up, the screen down
+                    y = clamp(y - dy as i32, 0, max_y);
+                },
+                MouseEvent::Button { button: MouseButton::Left, pressed } =>
+                    visible = !pressed,
+                _ => {},
+            }
+            next = mouse.try_read();
+        }
+
+        let cell = if visible {
+            Some(((y / MICKEYS_PER_ROW) as usize, (x / MICKEYS_PER_COLUMN) as usize))
+        } else {
+            None
+        };
+        vga_buffer::set_pointer(cell);
+    }
+}
+
+fn clamp(value: i32, min: i32, max: i32) -> i32 {
+    if value < min { min } else if value > max { max } else { value }
+}
+
+
+#[test]
+/// A three-byte packet with negative movement and a button press
+fn mouse_packet() {
+    let mut decoder = Decoder::new();
+    let mut events = [None; 4];
+    let mut count = 0;
+    for &byte in &[ALWAYS_ONE | X_SIGN | LEFT_BUTTON, 0xFF, 0x02] {
+        decoder.feed(byte, |event| { events[count] = Some(event); count += 1; });
+    }
+    assert_eq!(count, 2);
+    assert_eq!(events[0], Some(MouseEvent::Moved { dx: -1, dy: 2 }));
+    assert_eq!(events[1], Some(MouseEvent::Button { button: MouseButton::Left,
+                                                     pressed: true }));
+}
+
+#[test]
+/// Bytes before the start of a packet are skipped, and the wheel byte
+/// is decoded
+fn mouse_wheel_packet() {
+    let mut decoder = Decoder::new();
+    decoder.packet_size = 4;
+    let mut scrolled = None;
+    for &byte in &[0x00, ALWAYS_ONE, 0, 0, 0xFF] {
+        decoder.feed(byte, |event| scrolled = Some(event));
+    }
+    assert_eq!(scrolled, Some(MouseEvent::Scrolled(-1)));
+}
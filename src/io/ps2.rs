@@ -0,0 +1,407 @@
+//! The 8042 PS/2 controller, and the keyboard behind it.
+//!
+//! `init()` runs the controller self-test, finds out which of the two
+//! ports work and have a device connected, and sets up the keyboard:
+//! scancode set 2 (translated to set 1 by the controller, which is what
+//! `kbd::Decoder` understands), repeat rate and LEDs. Missing devices
+//! are only reported; the rest of the kernel works without them.
+//!
+//! After `init()` the keyboard only talks to us through interrupts, so
+//! its replies to commands (like setting the LEDs) show up in the
+//! keyboard handler, which passes them on to `keyboard_response()`.
+//!
+//! See http://wiki.osdev.org/%228042%22_PS/2_Controller
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
+use x86::io::{inb, outb};
+
+use io::kbd::{Modifiers, CAPS_LOCK, NUM_LOCK, SCROLL_LOCK};
+
+/// Data to and from the controller and devices
+const DATA_PORT: u16 = 0x60;
+/// Status when read, commands when written
+const COMMAND_PORT: u16 = 0x64;
+
+// Status register bits
+/// There is a byte for us to read
+const STATUS_OUTPUT_FULL: u8 = 1 << 0;
+/// The controller hasn't taken our last byte yet
+const STATUS_INPUT_FULL: u8 = 1 << 1;
+/// The byte to read came from the second port
+pub const STATUS_AUX_DATA: u8 = 1 << 5;
+
+// Controller commands
+const CMD_READ_CONFIG: u8 = 0x20;
+const CMD_WRITE_CONFIG: u8 = 0x60;
+const CMD_DISABLE_SECOND: u8 = 0xA7;
+const CMD_ENABLE_SECOND: u8 = 0xA8;
+const CMD_TEST_SECOND: u8 = 0xA9;
+const CMD_SELF_TEST: u8 = 0xAA;
+const CMD_TEST_FIRST: u8 = 0xAB;
+const CMD_DISABLE_FIRST: u8 = 0xAD;
+const CMD_ENABLE_FIRST: u8 = 0xAE;
+/// Send the next data byte to the second port instead of the first
+const CMD_WRITE_SECOND: u8 = 0xD4;
+/// Pulse the CPU reset line
+const CMD_RESET_CPU: u8 = 0xFE;
+
+// Configuration byte bits
+const CONFIG_FIRST_IRQ: u8 = 1 << 0;
+const CONFIG_SECOND_IRQ: u8 = 1 << 1;
+const CONFIG_SECOND_CLOCK_OFF: u8 = 1 << 5;
+const CONFIG_TRANSLATION: u8 = 1 << 6;
+
+// Replies from the controller
+const SELF_TEST_PASSED: u8 = 0x55;
+const PORT_TEST_PASSED: u8 = 0x00;
+
+// Device commands and replies
+const DEV_SET_LEDS: u8 = 0xED;
+const DEV_SCANCODE_SET: u8 = 0xF0;
+const DEV_SET_TYPEMATIC: u8 = 0xF3;
+const DEV_ENABLE_SCANNING: u8 = 0xF4;
+const DEV_DISABLE_SCANNING: u8 = 0xF5;
+const DEV_RESET: u8 = 0xFF;
+const DEV_ACK: u8 = 0xFA;
+const DEV_RESEND: u8 = 0xFE;
+const DEV_SELF_TEST_PASSED: u8 = 0xAA;
+
+// Keyboard LED bits
+const LED_SCROLL_LOCK: u8 = 1 << 0;
+const LED_NUM_LOCK: u8 = 1 << 1;
+const LED_CAPS_LOCK: u8 = 1 << 2;
+
+/// Repeat after 500 ms, about 20 times per second
+const TYPEMATIC: u8 = (1 << 5) | 0x04;
+
+/// Times to poll the status register before giving up. A device reset
+/// can take most of a second.
+const TIMEOUT: usize = 100_000;
+const RESET_TIMEOUT: usize = 10_000_000;
+
+/// Times to resend a command the device didn't get
+const RETRIES: usize = 3;
+
+/// One of the two PS/2 ports.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Port {
+    /// The keyboard port
+    First,
+    /// The auxiliary (mouse) port
+    Second,
+}
+
+/// Why talking to the controller or a device failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// No reply in time: usually, nothing there
+    Timeout,
+    /// The controller failed its self-test, with this result
+    SelfTest(u8),
+    /// The port failed its interface test, with this result
+    PortTest(u8),
+    /// The device replied with this instead of an acknowledgement
+    Unexpected(u8),
+}
+
+static KEYBOARD_PRESENT: AtomicBool = AtomicBool::new(false);
+static AUX_PRESENT: AtomicBool = AtomicBool::new(false);
+
+/// True if `init()` found a keyboard.
+pub fn keyboard_present() -> bool {
+    KEYBOARD_PRESENT.load(Ordering::Relaxed)
+}
+
+/// True if `init()` found a device on the second port.
+pub fn aux_present() -> bool {
+    AUX_PRESENT.load(Ordering::Relaxed)
+}
+
+/// Wait until we may write to the controller.
+unsafe fn wait_input_empty(timeout: usize) -> Result<(), Error> {
+    for _ in 0..timeout {
+        if inb(COMMAND_PORT) & STATUS_INPUT_FULL == 0 {
+            return Ok(());
+        }
+    }
+    Err(Error::Timeout)
+}
+
+/// Wait for a byte from the controller or a device, and read it.
+unsafe fn read_data(timeout: usize) -> Result<u8, Error> {
+    for _ in 0..timeout {
+        if inb(COMMAND_PORT) & STATUS_OUTPUT_FULL != 0 {
+            return Ok(inb(DATA_PORT));
+        }
+    }
+    Err(Error::Timeout)
+}
+
+/// Throw away whatever is waiting to be read.
+unsafe fn flush() {
+    while inb(COMMAND_PORT) & STATUS_OUTPUT_FULL != 0 {
+        inb(DATA_PORT);
+    }
+}
+
+/// Send a command to the controller.
+unsafe fn command(cmd: u8) -> Result<(), Error> {
+    try!(wait_input_empty(TIMEOUT));
+    outb(COMMAND_PORT, cmd);
+    Ok(())
+}
+
+/// Reset the machine through the controller's line to the CPU. Returns
+/// only if it didn't work.
+pub fn reset_cpu() {
+    let _ = unsafe { command(CMD_RESET_CPU) };
+}
+
+/// Send a command to the controller, and read its reply.
+unsafe fn command_reply(cmd: u8) -> Result<u8, Error> {
+    try!(command(cmd));
+    read_data(TIMEOUT)
+}
+
+/// Write a new configuration byte.
+unsafe fn write_config(config: u8) -> Result<(), Error> {
+    try!(command(CMD_WRITE_CONFIG));
+    try!(wait_input_empty(TIMEOUT));
+    outb(DATA_PORT, config);
+    Ok(())
+}
+
+/// Send `byte` to the device on `port`, without waiting for a reply.
+unsafe fn write_device(port: Port, byte: u8) -> Result<(), Error> {
+    if port == Port::Second {
+        try!(command(CMD_WRITE_SECOND));
+    }
+    try!(wait_input_empty(TIMEOUT));
+    outb(DATA_PORT, byte);
+    Ok(())
+}
+
+/// Send `byte` to the device on `port` and wait for it to acknowledge,
+/// resending as asked. Only for use with interrupts from the port off.
+pub unsafe fn device_command(port: Port, byte: u8) -> Result<(), Error> {
+    for _ in 0..RETRIES {
+        try!(write_device(port, byte));
+        match try!(read_data(TIMEOUT)) {
+            DEV_ACK => return Ok(()),
+            DEV_RESEND => continue,
+            other => return Err(Error::Unexpected(other)),
+        }
+    }
+    Err(Error::Unexpected(DEV_RESEND))
+}
+
+/// Read the next byte from a device. Only for use with interrupts from
+/// the port off.
+pub unsafe fn device_read() -> Result<u8, Error> {
+    read_data(TIMEOUT)
+}
+
+/// Let the device on `port` raise interrupts.
+pub unsafe fn enable_interrupts(port: Port) -> Result<(), Error> {
+    let config = try!(command_reply(CMD_READ_CONFIG));
+    let irq = match port {
+        Port::First => CONFIG_FIRST_IRQ,
+        Port::Second => CONFIG_SECOND_IRQ,
+    };
+    write_config(config | irq)
+}
+
+/// Reset the device on `port`, and wait for it to pass its self-test.
+unsafe fn reset_device(port: Port) -> Result<(), Error> {
+    try!(device_command(port, DEV_RESET));
+    match try!(read_data(RESET_TIMEOUT)) {
+        DEV_SELF_TEST_PASSED => {},
+        other => return Err(Error::Unexpected(other)),
+    }
+    // Mice send their ID after the self-test result
+    let _ = read_data(TIMEOUT);
+    Ok(())
+}
+
+/// Set up the keyboard on the first port, leaving it scanning.
+unsafe fn init_keyboard() -> Result<(), Error> {
+    try!(reset_device(Port::First));
+    try!(device_command(Port::First, DEV_DISABLE_SCANNING));
+
+    // Scancode set 2, which the controller translates to set 1. Every
+    // keyboard supports set 2, and it is the default anyway, so go on
+    // if the keyboard doesn't like the command.
+    if device_command(Port::First, DEV_SCANCODE_SET).is_ok() {
+        let _ = device_command(Port::First, 2);
+    }
+
+    try!(device_command(Port::First, DEV_SET_TYPEMATIC));
+    try!(device_command(Port::First, TYPEMATIC));
+    try!(device_command(Port::First, DEV_SET_LEDS));
+    try!(device_command(Port::First, 0));
+    device_command(Port::First, DEV_ENABLE_SCANNING)
+}
+
+/// Initialise the controller and the devices behind it. Must be called
+/// with interrupts disabled.
+pub fn init() {
+    match unsafe { init_controller() } {
+        Ok((keyboard, aux)) => {
+            KEYBOARD_PRESENT.store(keyboard, Ordering::Relaxed);
+            AUX_PRESENT.store(aux, Ordering::Relaxed);
+            info!("PS/2: keyboard {}, second port {}",
+                  if keyboard { "found" } else { "missing" },
+                  if aux { "in use" } else { "empty" });
+        },
+        Err(error) => error!("PS/2 controller not working: {:?}", error),
+    }
+}
+
+/// Set up the controller, returning which ports have a working device.
+unsafe fn init_controller() -> Result<(bool, bool), Error> {
+    // Keep the devices quiet while we are at it
+    try!(command(CMD_DISABLE_FIRST));
+    try!(command(CMD_DISABLE_SECOND));
+    flush();
+
+    // No interrupts, and no translation until the devices are set up,
+    // so their replies come through untouched
+    let mut config = try!(command_reply(CMD_READ_CONFIG));
+    config &= !(CONFIG_FIRST_IRQ | CONFIG_SECOND_IRQ | CONFIG_TRANSLATION);
+    try!(write_config(config));
+
+    match try!(command_reply(CMD_SELF_TEST)) {
+        SELF_TEST_PASSED => {},
+        other => return Err(Error::SelfTest(other)),
+    }
+    // The self-test may reset the controller
+    try!(write_config(config));
+
+    // A controller with a second port turns on its clock when enabled
+    try!(command(CMD_ENABLE_SECOND));
+    let dual = try!(command_reply(CMD_READ_CONFIG)) & CONFIG_SECOND_CLOCK_OFF == 0;
+    if dual {
+        try!(command(CMD_DISABLE_SECOND));
+    }
+
+    let first_ok = port_test(CMD_TEST_FIRST);
+    let second_ok = dual && port_test(CMD_TEST_SECOND);
+
+    let mut keyboard = false;
+    if first_ok {
+        try!(command(CMD_ENABLE_FIRST));
+        keyboard = init_keyboard().is_ok();
+        if keyboard {
+            config |= CONFIG_FIRST_IRQ | CONFIG_TRANSLATION;
+        }
+    }
+
+    let mut aux = false;
+    if second_ok {
+        try!(command(CMD_ENABLE_SECOND));
+        // Leave it reset, but not sending anything, until somebody
+        // wants to use it
+        aux = reset_device(Port::Second).is_ok();
+    }
+
+    flush();
+    try!(write_config(config));
+    Ok((keyboard, aux))
+}
+
+/// Run the interface test for a port, returning `true` if it passed.
+unsafe fn port_test(cmd: u8) -> bool {
+    match command_reply(cmd) {
+        Ok(PORT_TEST_PASSED) => true,
+        Ok(result) => {
+            warn!("PS/2: {:?}", Error::PortTest(result));
+            false
+        },
+        Err(_) => false,
+    }
+}
+
+/// Where an update of the keyboard LEDs is.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum LedUpdate {
+    Idle,
+    /// Sent `DEV_SET_LEDS`, waiting for the acknowledgement
+    SentCommand,
+    /// Sent these LEDs, waiting for the acknowledgement
+    SentLeds(u8),
+}
+
+struct Leds {
+    update: LedUpdate,
+    /// What the LEDs should show
+    wanted: u8,
+    /// What they show
+    shown: u8,
+}
+
+/// Only used from the keyboard handler.
+static LEDS: Mutex<Leds> = Mutex::new(Leds {
+    update: LedUpdate::Idle,
+    wanted: 0,
+    shown: 0,
+});
+
+/// Make the keyboard LEDs show the lock keys in `modifiers`. The update
+/// completes through `keyboard_response()`.
+pub fn set_leds(modifiers: Modifiers) {
+    let mut wanted = 0;
+    if modifiers.contains(CAPS_LOCK) { wanted |= LED_CAPS_LOCK; }
+    if modifiers.contains(NUM_LOCK) { wanted |= LED_NUM_LOCK; }
+    if modifiers.contains(SCROLL_LOCK) { wanted |= LED_SCROLL_LOCK; }
+
+    let mut leds = LEDS.lock();
+    leds.wanted = wanted;
+    if leds.update == LedUpdate::Idle && leds.wanted != leds.shown {
+        leds.start();
+    }
+}
+
+impl Leds {
+    fn start(&mut self) {
+        if unsafe { write_device(Port::First, DEV_SET_LEDS) }.is_ok() {
+            self.update = LedUpdate::SentCommand;
+        }
+    }
+}
+
+/// Handle a byte from the keyboard if it is a reply to a command.
+/// Returns `true` if it was, and isn't a scancode.
+pub fn keyboard_response(byte: u8) -> bool {
+    if byte != DEV_ACK && byte != DEV_RESEND {
+        return false;
+    }
+
+    let mut leds = LEDS.lock();
+    let resend = byte == DEV_RESEND;
+    match leds.update {
+        LedUpdate::Idle => {},
+        LedUpdate::SentCommand if resend => leds.start(),
+        LedUpdate::SentCommand => {
+            let wanted = leds.wanted;
+            leds.update = match unsafe { write_device(Port::First, wanted) } {
+                Ok(()) => LedUpdate::SentLeds(wanted),
+                Err(_) => LedUpdate::Idle,
+            };
+        },
+        LedUpdate::SentLeds(value) if resend => {
+            if unsafe { write_device(Port::First, value) }.is_err() {
+                leds.update = LedUpdate::Idle;
+            }
+        },
+        LedUpdate::SentLeds(value) => {
+            leds.shown = value;
+            leds.update = LedUpdate::Idle;
+            // The lock keys may have changed meanwhile
+            if leds.wanted != leds.shown {
+                leds.start();
+            }
+        },
+    }
+    true
+}
@@ -0,0 +1,127 @@
+//! The first serial port (COM1), a 16550 UART.
+//!
+//! Runs at 115200 baud, 8N1. Received bytes are published as input
+//! events from the interrupt handler; writing polls until the UART has
+//! room.
+//!
+//! See http://wiki.osdev.org/Serial_Ports
+
+use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
+use x86::io::{inb, outb};
+
+use input::{self, Event};
+use io;
+
+/// I/O port base of COM1
+const COM1: u16 = 0x3F8;
+
+// Register offsets
+/// Received byte when read, byte to send when written
+const DATA: u16 = 0;
+/// Interrupt enable; divisor high byte with DLAB set
+const INTERRUPT_ENABLE: u16 = 1;
+/// FIFO control
+const FIFO_CONTROL: u16 = 2;
+const LINE_CONTROL: u16 = 3;
+const MODEM_CONTROL: u16 = 4;
+const LINE_STATUS: u16 = 5;
+/// Free for our own use; tells us if there is a UART at all
+const SCRATCH: u16 = 7;
+
+/// Line control: the data and interrupt enable registers hold the baud
+/// rate divisor
+const DLAB: u8 = 1 << 7;
+/// Line control: 8 data bits, no parity, one stop bit
+const EIGHT_N_ONE: u8 = 0b11;
+/// Divisor of 115200 for the baud rate
+const DIVISOR: u16 = 1;
+/// Enable and clear the FIFOs, interrupt at 14 bytes
+const FIFO_SETUP: u8 = 0xC7;
+/// Modem control: DTR, RTS, and OUT2, which connects the interrupt line
+const MODEM_SETUP: u8 = 0x0B;
+/// Interrupt enable: data received
+const RECEIVED_INTERRUPT: u8 = 1 << 0;
+
+// Line status bits
+const DATA_READY: u8 = 1 << 0;
+const TRANSMIT_EMPTY: u8 = 1 << 5;
+
+static PRESENT: AtomicBool = AtomicBool::new(false);
+
+/// True if `init()` found the UART.
+pub fn present() -> bool {
+    PRESENT.load(Ordering::Relaxed)
+}
+
+/// Set up COM1, if there is one. The interrupt must be routed to
+/// `getserial` separately.
+pub fn init() -> bool {
+    unsafe {
+        outb(COM1 + SCRATCH, 0x5A);
+        if inb(COM1 + SCRATCH) != 0x5A {
+            return false;
+        }
+
+        outb(COM1 + INTERRUPT_ENABLE, 0);
+        outb(COM1 + LINE_CONTROL, DLAB);
+        outb(COM1 + DATA, DIVISOR as u8);
+        outb(COM1 + INTERRUPT_ENABLE, (DIVISOR >> 8) as u8);
+        outb(COM1 + LINE_CONTROL, EIGHT_N_ONE);
+        outb(COM1 + FIFO_CONTROL, FIFO_SETUP);
+        outb(COM1 + MODEM_CONTROL, MODEM_SETUP);
+        outb(COM1 + INTERRUPT_ENABLE, RECEIVED_INTERRUPT);
+    }
+
+    PRESENT.store(true, Ordering::Relaxed);
+    true
+}
+
+/// Send `byte`, waiting for room. Does nothing without a UART.
+pub fn write_byte(byte: u8) {
+    if !present() {
+        return;
+    }
+    unsafe {
+        while inb(COM1 + LINE_STATUS) & TRANSMIT_EMPTY == 0 {}
+        outb(COM1 + DATA, byte);
+    }
+}
+
+/// Wait for a byte and return it, by polling rather than through the
+/// interrupt handler. For use with interrupts off, like in the GDB
+/// stub. Returns `None` without a UART.
+pub fn read_byte() -> Option<u8> {
+    if !present() {
+        return None;
+    }
+    unsafe {
+        while inb(COM1 + LINE_STATUS) & DATA_READY == 0 {}
+        Some(inb(COM1 + DATA))
+    }
+}
+
+/// Writes to the serial port with `write!`, turning `\n` into `\r\n`
+/// for terminals.
+pub struct SerialWriter;
+
+impl fmt::Write for SerialWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            if byte == b'\n' {
+                write_byte(b'\r');
+            }
+            write_byte(byte);
+        }
+        Ok(())
+    }
+}
+
+/// Serial interrupt handler: publish every byte received.
+pub unsafe fn getserial(_arg: usize) {
+    while inb(COM1 + LINE_STATUS) & DATA_READY != 0 {
+        input::publish(Event::Serial(inb(COM1 + DATA)));
+    }
+
+    io::send_LAPIC_EOI()
+}
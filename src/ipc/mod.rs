@@ -0,0 +1,242 @@
+//! # Message passing
+//!
+//! Bounded channels carrying typed messages between threads, built on
+//! `pipe::Buffer`:
+//!
+//! ```
+//! let (sender, receiver) = ipc::channel::<(u32, char)>(16);
+//! sender.send((1, 'a')).unwrap();
+//! assert_eq!(receiver.recv(), Ok((1, 'a')));
+//! ```
+//!
+//! Both ends can be cloned. `recv()` fails with `IpcError::Disconnected`
+//! once every sender is gone and the channel is empty, and `send()`
+//! once every receiver is gone.
+//!
+//! `select()` waits for the first of several receivers to have
+//! something, and the `port` module gives channels numbers, so user
+//! programs can use them through system calls.
+
+use alloc::arc::Arc;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use pipe::Buffer;
+use sync::{self, Mutex, WaitQueue};
+use timers;
+
+pub mod port;
+
+pub use self::port::{PortId, Message};
+
+/// Why a channel operation failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpcError {
+    /// The other end is gone
+    Disconnected,
+    /// No room for another message
+    Full,
+    /// No message to receive
+    Empty,
+    /// No message arrived in time
+    Timeout,
+}
+
+/// The state shared by all ends of a channel.
+struct Channel<T: Copy> {
+    buffer: Buffer<T>,
+    senders: AtomicUsize,
+    receivers: AtomicUsize,
+    /// The buffer is single-producer, single-consumer; these make sure
+    /// only one sender and one receiver use it at a time.
+    send_lock: Mutex<()>,
+    recv_lock: Mutex<()>,
+    /// Receivers (and `select()`s) waiting for messages
+    recv_waiters: WaitQueue,
+    /// Senders waiting for room
+    send_waiters: WaitQueue,
+}
+
+/// The sending end of a channel.
+pub struct Sender<T: Copy> {
+    channel: Arc<Channel<T>>,
+}
+
+/// The receiving end of a channel.
+pub struct Receiver<T: Copy> {
+    channel: Arc<Channel<T>>,
+}
+
+/// Create a channel holding at most `capacity` messages.
+pub fn channel<T: Copy>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    let channel = Arc::new(Channel {
+        buffer: Buffer::new(capacity),
+        senders: AtomicUsize::new(1),
+        receivers: AtomicUsize::new(1),
+        send_lock: Mutex::new(()),
+        recv_lock: Mutex::new(()),
+        recv_waiters: WaitQueue::new(),
+        send_waiters: WaitQueue::new(),
+    });
+
+    (Sender { channel: channel.clone() }, Receiver { channel: channel })
+}
+
+impl<T: Copy> Sender<T> {
+    /// Send `message`, waiting for room if the channel is full.
+    pub fn send(&self, message: T) -> Result<(), IpcError> {
+        let _guard = self.channel.send_lock.lock();
+        let channel = &self.channel;
+
+        channel.send_waiters.wait_until(|| {
+            !channel.buffer.is_full()
+                || channel.receivers.load(Ordering::SeqCst) == 0
+        });
+        self.send_locked(message)
+    }
+
+    /// Send `message` if there is room for it.
+    pub fn try_send(&self, message: T) -> Result<(), IpcError> {
+        let _guard = self.channel.send_lock.lock();
+        self.send_locked(message)
+    }
+
+    /// Send, with the send lock held.
+    fn send_locked(&self, message: T) -> Result<(), IpcError> {
+        if self.channel.receivers.load(Ordering::SeqCst) == 0 {
+            return Err(IpcError::Disconnected);
+        }
+        try!(self.channel.buffer.try_push(message)
+             .map_err(|_| IpcError::Full));
+        self.channel.recv_waiters.wake_all();
+        Ok(())
+    }
+}
+
+impl<T: Copy> Receiver<T> {
+    /// Receive a message, waiting for one if the channel is empty.
+    pub fn recv(&self) -> Result<T, IpcError> {
+        let _guard = self.channel.recv_lock.lock();
+        let channel = &self.channel;
+
+        channel.recv_waiters.wait_until(|| channel.is_ready());
+        self.recv_locked()
+    }
+
+    /// Receive a message if there is one.
+    pub fn try_recv(&self) -> Result<T, IpcError> {
+        // Someone else holding the lock may be waiting in `recv()`
+        let _guard = match self.channel.recv_lock.try_lock() {
+            Some(guard) => guard,
+            None => return Err(IpcError::Empty),
+        };
+        self.recv_locked()
+    }
+
+    /// Receive a message, waiting at most `ticks` timer ticks for one.
+    pub fn recv_timeout(&self, ticks: usize) -> Result<T, IpcError> {
+        let deadline = timers::get_ticks().saturating_add(ticks);
+        let _guard = match self.channel.recv_lock.lock_until(deadline) {
+            Some(guard) => guard,
+            None => return Err(IpcError::Timeout),
+        };
+        let channel = &self.channel;
+
+        if !channel.recv_waiters.wait_until_timeout(|| channel.is_ready(),
+                                                    deadline) {
+            return Err(IpcError::Timeout);
+        }
+        self.recv_locked()
+    }
+
+    /// Receive, with the receive lock held.
+    fn recv_locked(&self) -> Result<T, IpcError> {
+        // Check for senders first: anything sent before the last one
+        // left is in the buffer by then.
+        let disconnected = self.channel.senders.load(Ordering::SeqCst) == 0;
+
+        match self.channel.buffer.try_pop() {
+            Ok(message) => {
+                self.channel.send_waiters.wake_all();
+                Ok(message)
+            },
+            Err(_) if disconnected => Err(IpcError::Disconnected),
+            Err(_) => Err(IpcError::Empty),
+        }
+    }
+}
+
+impl<T: Copy> Channel<T> {
+    /// True if receiving wouldn't have to wait.
+    fn is_ready(&self) -> bool {
+        !self.buffer.is_empty() || self.senders.load(Ordering::SeqCst) == 0
+    }
+}
+
+/// Something `select()` can wait for.
+pub trait Selectable {
+    /// True if receiving wouldn't have to wait.
+    fn is_ready(&self) -> bool;
+    /// Where to wait for it to become ready.
+    fn wait_queue(&self) -> &WaitQueue;
+}
+
+impl<T: Copy> Selectable for Receiver<T> {
+    fn is_ready(&self) -> bool {
+        self.channel.is_ready()
+    }
+
+    fn wait_queue(&self) -> &WaitQueue {
+        &self.channel.recv_waiters
+    }
+}
+
+/// Wait until one of `receivers` has a message (or has lost all its
+/// senders), and return its index. Gives up after `timeout` ticks, if
+/// given, and returns `None`.
+///
+/// Another thread sharing the receiver may get the message first, so be
+/// prepared for `try_recv()` to come back empty.
+pub fn select(receivers: &[&Selectable], timeout: Option<usize>) -> Option<usize> {
+    use collections::vec::Vec;
+
+    let queues: Vec<&WaitQueue> = receivers.iter().map(|r| r.wait_queue()).collect();
+    let deadline = timeout.map(|ticks| timers::get_ticks().saturating_add(ticks));
+    let mut ready = None;
+
+    sync::wait_any(&queues, || {
+        ready = receivers.iter().position(|r| r.is_ready());
+        ready.is_some()
+    }, deadline);
+
+    ready
+}
+
+impl<T: Copy> Clone for Sender<T> {
+    fn clone(&self) -> Sender<T> {
+        self.channel.senders.fetch_add(1, Ordering::SeqCst);
+        Sender { channel: self.channel.clone() }
+    }
+}
+
+impl<T: Copy> Clone for Receiver<T> {
+    fn clone(&self) -> Receiver<T> {
+        self.channel.receivers.fetch_add(1, Ordering::SeqCst);
+        Receiver { channel: self.channel.clone() }
+    }
+}
+
+impl<T: Copy> Drop for Sender<T> {
+    fn drop(&mut self) {
+        if self.channel.senders.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.channel.recv_waiters.wake_all();
+        }
+    }
+}
+
+impl<T: Copy> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        if self.channel.receivers.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.channel.send_waiters.wake_all();
+        }
+    }
+}
@@ -0,0 +1,102 @@
+//! Ports: channels with a number, so they can be named in system calls.
+//!
+//! A port carries fixed-size `Message`s. Anybody who knows its number
+//! can send to it or receive from it, until it is destroyed.
+
+use collections::btree_map::BTreeMap;
+use spin;
+
+use irq;
+use super::{channel, Sender, Receiver, IpcError};
+
+/// A port number.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PortId(pub usize);
+
+/// What goes through a port: four machine words.
+pub type Message = [usize; 4];
+
+/// Largest number of messages a port may hold.
+pub const MAX_PORT_CAPACITY: usize = 256;
+
+/// Both ends of every port's channel, by number.
+struct Ports {
+    ports: BTreeMap<PortId, (Sender<Message>, Receiver<Message>)>,
+    next_id: usize,
+}
+
+/// All ports. Only locked with interrupts disabled, and never while
+/// waiting for a message.
+static PORTS: spin::Mutex<Option<Ports>> = spin::Mutex::new(None);
+
+/// Look up the sending end of `port`.
+fn sender(port: PortId) -> Option<Sender<Message>> {
+    irq::without_interrupts(|| {
+        PORTS.lock().as_ref()
+            .and_then(|p| p.ports.get(&port))
+            .map(|&(ref sender, _)| sender.clone())
+    })
+}
+
+/// Look up the receiving end of `port`.
+fn receiver(port: PortId) -> Option<Receiver<Message>> {
+    irq::without_interrupts(|| {
+        PORTS.lock().as_ref()
+            .and_then(|p| p.ports.get(&port))
+            .map(|&(_, ref receiver)| receiver.clone())
+    })
+}
+
+/// Create a port holding at most `capacity` messages. Returns `None` if
+/// `capacity` is zero or too big.
+pub fn create(capacity: usize) -> Option<PortId> {
+    if capacity == 0 || capacity > MAX_PORT_CAPACITY {
+        return None;
+    }
+
+    let ends = channel(capacity);
+
+    irq::without_interrupts(|| {
+        let mut guard = PORTS.lock();
+        if guard.is_none() {
+            *guard = Some(Ports { ports: BTreeMap::new(), next_id: 1 });
+        }
+        let ports = guard.as_mut().unwrap();
+
+        let id = PortId(ports.next_id);
+        ports.next_id += 1;
+        ports.ports.insert(id, ends);
+        Some(id)
+    })
+}
+
+/// Destroy `port`. Threads waiting on it wake up with
+/// `IpcError::Disconnected`. Returns `false` if there is no such port.
+pub fn destroy(port: PortId) -> bool {
+    let ends = irq::without_interrupts(|| {
+        PORTS.lock().as_mut().and_then(|p| p.ports.remove(&port))
+    });
+    // The ends are dropped here, outside of the lock
+    ends.is_some()
+}
+
+/// Send `message` to `port`, waiting for room if needed.
+pub fn send(port: PortId, message: Message) -> Result<(), IpcError> {
+    match sender(port) {
+        Some(sender) => sender.send(message),
+        None => Err(IpcError::Disconnected),
+    }
+}
+
+/// Receive a message from `port`. With a `timeout` (in ticks), give up
+/// after that long; a timeout of zero never waits.
+pub fn recv(port: PortId, timeout: Option<usize>) -> Result<Message, IpcError> {
+    match receiver(port) {
+        Some(receiver) => match timeout {
+            Some(0) => receiver.try_recv(),
+            Some(ticks) => receiver.recv_timeout(ticks),
+            None => receiver.recv(),
+        },
+        None => Err(IpcError::Disconnected),
+    }
+}
@@ -5,10 +5,10 @@
 //! Are you kidding me?
 
 
-/// This is a do-nothing default handler for interrupts. It just echoes
-/// the interrupt number to screen.
+/// This is a do-nothing default handler for interrupts. It just logs
+/// the interrupt number.
 unsafe fn null_handler(vec: usize) {
-    println!("Handled interrupt {}!", vec);
+    warn!("Handled interrupt {}!", vec);
 }
 
 /// This is a static vector of dispatcher functions. Basically, it's a
@@ -16,6 +16,18 @@ unsafe fn null_handler(vec: usize) {
 static mut VEC_DISPATCH_FNS: [unsafe fn(usize); super::idt::IDT_NUM_ENTRIES]
     = [null_handler; super::idt::IDT_NUM_ENTRIES];
 
+/// Where the interrupt being handled came from
+#[derive(Clone, Copy)]
+pub struct Interrupted {
+    /// The instruction that was interrupted
+    pub rip: usize,
+    /// Its frame pointer
+    pub rbp: usize,
+}
+
+/// Set by `entry_from()` before calling the handler.
+static mut INTERRUPTED: Interrupted = Interrupted { rip: 0, rbp: 0 };
+
 /// This is the entry point for the dispatcher. It is supposed to be
 /// called from whatever lower-level code catches the given interrupt.
 pub fn entry(vec: usize) {
@@ -24,6 +36,19 @@ pub fn entry(vec: usize) {
     unsafe {VEC_DISPATCH_FNS[vec](vec);}
 }
 
+/// Like `entry()`, but also remembers where the interrupt came from,
+/// for `interrupted()`.
+pub fn entry_from(vec: usize, rip: usize, rbp: usize) {
+    unsafe { INTERRUPTED = Interrupted { rip: rip, rbp: rbp }; }
+    entry(vec);
+}
+
+/// Where the interrupt being handled came from. Only meaningful inside
+/// an interrupt handler, before anything else could interrupt it.
+pub fn interrupted() -> Interrupted {
+    unsafe { INTERRUPTED }
+}
+
 /// Register `f` as the handler for interrupt `vec`
 pub fn set_handler(vec: usize,
                    f: unsafe fn(usize) -> ()) {
@@ -94,8 +94,8 @@ pub unsafe fn idt_get_ptr()
          : "intel" // use Intel syntax
     );
 
-    println!("IDTR limit is: 0x{:x}", idtr.limit);
-    println!("IDTR base is: 0x{:x}", idtr.base);
+    debug!("IDTR limit is: 0x{:x}", idtr.limit);
+    debug!("IDTR base is: 0x{:x}", idtr.base);
 
     if idtr.limit < 1 {
         // The IDT must have at least one entry!
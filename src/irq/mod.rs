@@ -21,7 +21,7 @@
 //!
 //! Also note that you need to define and export the non-mangled function
 //! `rust_interrupt_handler` from your main file, and in that function
-//! call `irq::entry()`, that is the dispatch entry function.
+//! call `irq::entry_from()`, that is the dispatch entry function.
 
 
 /// Default flags for all system trap gates.
@@ -43,7 +43,7 @@ mod dispatch;
 mod tests;
 
 // Exception entry point re-export
-pub use self::dispatch::entry;
+pub use self::dispatch::{entry, entry_from, interrupted, Interrupted};
 
 // End modules and re-exports
 
@@ -79,6 +79,48 @@ pub fn set_handler(vec: usize,
 
 }
 
+/// Bitmask for the interrupt enable flag (IF) in RFLAGS.
+const RFLAGS_IF: usize = 1 << 9;
+
+/// Disable interrupts and return the previous contents of RFLAGS, to
+/// be handed back to `restore()` later.
+///
+/// # Safety
+/// Every call must be paired with a call to `restore()`, or interrupts
+/// will stay off.
+pub unsafe fn save_and_disable() -> usize {
+    let flags: usize;
+    asm!("pushfq\n\t\
+          pop $0\n\t\
+          cli"
+         : "=r"(flags)
+         :
+         : "memory"
+         : "intel", "volatile");
+    flags
+}
+
+/// Re-enable interrupts if they were enabled in `flags`, as returned by
+/// `save_and_disable()`.
+pub unsafe fn restore(flags: usize) {
+    if flags & RFLAGS_IF != 0 {
+        asm!("sti" :::: "volatile");
+    }
+}
+
+/// Run `f` with interrupts disabled, and restore the previous interrupt
+/// state afterwards. This is what you want when sharing a lock with an
+/// interrupt handler: otherwise, the handler could fire while the lock
+/// is held and spin forever.
+pub fn without_interrupts<F, R>(f: F) -> R
+    where F: FnOnce() -> R
+{
+    let flags = unsafe { save_and_disable() };
+    let ret = f();
+    unsafe { restore(flags) };
+    ret
+}
+
 /// Install and initialise the system IDT and ISR:s for every interrupt
 /// and exception.
 pub unsafe fn install() {
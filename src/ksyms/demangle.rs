@@ -0,0 +1,148 @@
+//! Demangling Rust symbol names.
+//!
+//! Rust symbols are mangled like C++ ones: `_ZN`, then every part of
+//! the path with its length in front, then a hash, then `E`. Characters
+//! that can't be in a symbol are escaped, like `$LT$` for `<`.
+
+use core::fmt::{self, Write};
+use core::str::FromStr;
+
+/// A symbol name, demangled if it is a Rust one:
+/// `_ZN4core9panicking5panic17h0123456789abcdefE` shows as
+/// `core::panicking::panic`.
+pub struct Demangle<'a>(pub &'a str);
+
+/// Escapes in mangled names, and what they stand for
+const ESCAPES: [(&'static str, &'static str); 12] = [
+    ("$SP$", "@"), ("$BP$", "*"), ("$RF$", "&"), ("$LT$", "<"), ("$GT$", ">"),
+    ("$LP$", "("), ("$RP$", ")"), ("$C$", ","), ("$u20$", " "), ("$u27$", "'"),
+    ("$u7b$", "{"), ("$u7d$", "}"),
+];
+
+/// Split the length-prefixed segment at the start of `mangled` from
+/// the rest.
+fn next_segment(mangled: &str) -> Option<(&str, &str)> {
+    let digits = mangled.chars().take_while(|c| c.is_digit(10)).count();
+    let len = match usize::from_str(&mangled[..digits]) {
+        Ok(len) => len,
+        Err(_) => return None,
+    };
+    let rest = &mangled[digits..];
+    if len > rest.len() || !rest.is_char_boundary(len) {
+        return None;
+    }
+    Some((&rest[..len], &rest[len..]))
+}
+
+/// The last segment is a hash, like `h0123456789abcdef`
+fn is_hash(segment: &str) -> bool {
+    segment.len() == 17 && segment.starts_with('h')
+        && segment[1..].chars().all(|c| c.is_digit(16))
+}
+
+impl<'a> fmt::Display for Demangle<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let inner = if self.0.starts_with("_ZN") && self.0.ends_with('E') {
+            &self.0[3..self.0.len() - 1]
+        } else {
+            return f.write_str(self.0);
+        };
+
+        // Check it all first, so a bad name is shown as it is
+        let mut rest = inner;
+        while !rest.is_empty() {
+            match next_segment(rest) {
+                Some((_, next)) => rest = next,
+                None => return f.write_str(self.0),
+            }
+        }
+
+        let mut rest = inner;
+        let mut first = true;
+        while let Some((segment, next)) = next_segment(rest) {
+            rest = next;
+            if rest.is_empty() && is_hash(segment) {
+                break;
+            }
+            if !first {
+                try!(f.write_str("::"));
+            }
+            first = false;
+            try!(write_segment(f, segment));
+        }
+        Ok(())
+    }
+}
+
+/// Write `segment` with its escapes undone.
+fn write_segment(f: &mut fmt::Formatter, segment: &str) -> fmt::Result {
+    // A leading `$` gets an underscore in front of it
+    let mut rest = if segment.starts_with("_$") { &segment[1..] } else { segment };
+    while !rest.is_empty() {
+        if rest.starts_with("..") {
+            try!(f.write_str("::"));
+            rest = &rest[2..];
+            continue;
+        }
+        if let Some(&(escape, text)) = ESCAPES.iter().find(|&&(e, _)| rest.starts_with(e)) {
+            try!(f.write_str(text));
+            rest = &rest[escape.len()..];
+            continue;
+        }
+        let c = rest.chars().next().unwrap();
+        try!(f.write_char(c));
+        rest = &rest[c.len_utf8()..];
+    }
+    Ok(())
+}
+/// Checks written text against the text expected, without keeping it.
+struct Compare<'a> {
+    rest: &'a str,
+}
+
+impl<'a> fmt::Write for Compare<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if self.rest.starts_with(s) {
+            self.rest = &self.rest[s.len()..];
+            Ok(())
+        } else {
+            Err(fmt::Error)
+        }
+    }
+}
+
+/// True if `mangled` is `name`, or demangles to it.
+pub fn matches(mangled: &str, name: &str) -> bool {
+    if mangled == name {
+        return true;
+    }
+    let mut compare = Compare { rest: name };
+    write!(compare, "{}", Demangle(mangled)).is_ok() && compare.rest.is_empty()
+}
+
+
+#[cfg(test)]
+fn demangled(name: &str) -> ::collections::String {
+    format!("{}", Demangle(name))
+}
+
+#[test]
+/// Paths come out with `::`, without the hash, and with escapes undone
+fn ksyms_demangle() {
+    assert_eq!(demangled("_ZN4core9panicking5panic17h0123456789abcdefE"),
+               "core::panicking::panic");
+    assert_eq!(demangled("_ZN41_$LT$banjos..tty..Tty$u20$as$u20$Drop$GT$4drop17h0123456789abcdefE"),
+               "<banjos::tty::Tty as Drop>::drop");
+    assert_eq!(demangled("rust_main"), "rust_main");
+    assert_eq!(demangled("_ZN10truncatedE"), "_ZN10truncatedE");
+}
+
+#[test]
+/// A name is found by its demangled path, but not by a part of it
+fn ksyms_matches() {
+    let mangled = "_ZN6banjos3tty9read_line17h0123456789abcdefE";
+    assert!(matches(mangled, "banjos::tty::read_line"));
+    assert!(matches(mangled, mangled));
+    assert!(!matches(mangled, "banjos::tty"));
+    assert!(!matches(mangled, "banjos::tty::read_line2"));
+}
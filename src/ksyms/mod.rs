@@ -0,0 +1,117 @@
+//! # Kernel symbols
+//!
+//! Looks up the functions of the kernel by address, and the other way
+//! around, for backtraces and the shell's `sym` command.
+//!
+//! GRUB loads the kernel's symbol table (`.symtab`) and its string
+//! table (`.strtab`) along with the kernel, and says where in the ELF
+//! section headers of the multiboot information. `memory::init()` keeps
+//! them out of the frame allocator's hands and `remap_the_kernel` maps
+//! them read-only, where they are (see `loader::boot_area()`); `init()`
+//! then finds them.
+//!
+//! Lookups go through the whole table, and take no locks, so they can
+//! be used while panicking.
+
+use core::{mem, slice, str};
+
+use loader;
+use memory;
+
+mod demangle;
+
+pub use self::demangle::Demangle;
+
+/// An entry of the ELF symbol table
+#[repr(C)]
+struct Symbol {
+    name: u32,
+    info: u8,
+    other: u8,
+    shndx: u16,
+    value: u64,
+    size: u64,
+}
+
+/// Symbol type of a function, in the low bits of `info`
+const STT_FUNC: u8 = 2;
+
+impl Symbol {
+    fn is_function(&self) -> bool {
+        self.info & 0xF == STT_FUNC
+    }
+
+    fn contains(&self, address: usize) -> bool {
+        self.value as usize <= address && address < (self.value + self.size) as usize
+    }
+}
+
+/// The symbol and string tables, once `init()` has found them
+struct Tables {
+    symbols: &'static [Symbol],
+    strings: &'static [u8],
+}
+
+/// Set once by `init()`, before there are other threads to look.
+static mut TABLES: Option<Tables> = None;
+
+/// Find the kernel's symbol tables. Must be called after
+/// `memory::init()` and `loader::init()`. Returns false if there are
+/// none, and lookups will come up empty.
+pub fn init() -> bool {
+    assert_has_not_been_called!("ksyms::init must be called only once");
+
+    let (symtab, strtab) = match loader::kernel_symbol_tables() {
+        Some(tables) => tables,
+        None => return false,
+    };
+    if !memory::kernel_readable(symtab.addr as usize, symtab.size as usize)
+        || !memory::kernel_readable(strtab.addr as usize, strtab.size as usize) {
+        return false;
+    }
+
+    unsafe {
+        TABLES = Some(Tables {
+            symbols: slice::from_raw_parts(symtab.addr as *const Symbol,
+                                           symtab.size as usize / mem::size_of::<Symbol>()),
+            strings: slice::from_raw_parts(strtab.addr as *const u8, strtab.size as usize),
+        });
+    }
+    true
+}
+
+fn tables() -> Option<&'static Tables> {
+    unsafe { TABLES.as_ref() }
+}
+
+/// The function `address` is in, and how far into it. The name is
+/// mangled; show it with `Demangle`.
+pub fn lookup(address: usize) -> Option<(&'static str, usize)> {
+    tables().and_then(|tables| {
+        tables.symbols.iter()
+            .find(|s| s.is_function() && s.contains(address))
+            .map(|s| (name(tables, s), address - s.value as usize))
+    })
+}
+
+/// The address of the function called `name`, which may be mangled or
+/// not, like `banjos::tty::read_line`.
+pub fn address_of(name: &str) -> Option<usize> {
+    tables().and_then(|tables| {
+        tables.symbols.iter()
+            .find(|s| s.is_function() && demangle::matches(self::name(tables, s), name))
+            .map(|s| s.value as usize)
+    })
+}
+
+/// The null-terminated name of `symbol`, from the string table.
+fn name(tables: &Tables, symbol: &Symbol) -> &'static str {
+    let strings: &'static [u8] = tables.strings;
+    let start = symbol.name as usize;
+    if start >= strings.len() {
+        return "";
+    }
+    let bytes = &strings[start..];
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    str::from_utf8(&bytes[..len]).unwrap_or("")
+}
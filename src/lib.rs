@@ -40,6 +40,8 @@ extern crate collections;
 #[macro_use]
 #[doc(inline)]
 mod vga_buffer;
+#[macro_use]
+mod log;
 mod memory;
 
 mod acpi;
@@ -53,9 +55,27 @@ mod msr;
 mod timers;
 
 mod pipe;
+mod ipc;
+mod input;
+mod tty;
+
+mod sched;
+mod sync;
 
 mod shell;
 
+mod syscall;
+mod loader;
+mod ksyms;
+mod panic;
+mod gdb;
+mod profile;
+mod pmu;
+
+/// Lines of output kept for Shift+PageUp, on every console. Each takes
+/// 160 bytes of the heap.
+const SCROLLBACK_LINES: usize = 100;
+
 /// This is the kernel main function! Control is passed after the ASM
 /// parts have finished.
 ///
@@ -80,7 +100,7 @@ pub extern "C" fn rust_main(multiboot_information_address: usize) {
             ioapic = 0x0;
         }
     } else {
-        println!("FAILED to load RSDT");
+        error!("FAILED to load RSDT");
         ioapic = 0x0;
     }
 
@@ -92,9 +112,29 @@ pub extern "C" fn rust_main(multiboot_information_address: usize) {
     enable_nxe_bit();
     enable_write_protect_bit();
 
+    // Find the programs loaded along with the kernel, and its symbols
+    let modules = loader::boot_area(multiboot_information_address);
+    loader::init(multiboot_information_address);
+
     // Initialize memory mapping and paging, as well as
     // kernel-remap and all other memory-related set-up
-    memory::init(boot_info, sdt_loc);
+    memory::init(boot_info, modules, sdt_loc);
+
+    // Now there's a heap for the virtual consoles, and the lines
+    // scrolling off their screens
+    vga_buffer::init_consoles(SCROLLBACK_LINES);
+
+    // Module filters need the heap too
+    log::configure_from_command_line(loader::command_line());
+    panic::configure_from_command_line(loader::command_line());
+
+    // Backtraces show function names
+    if !ksyms::init() {
+        warn!("no kernel symbol table");
+    }
+
+    // Replace the boot GDT with one that has user segments and a TSS
+    unsafe { arch::x86_64::gdt::init(); }
 
 //<<<<<<< HEAD
 //    io::install_io(sdt_loc.lapic_ctrl, sdt_loc.ioapic_start);
@@ -121,11 +161,24 @@ pub extern "C" fn rust_main(multiboot_information_address: usize) {
     // Redirect spurious interrupts
     unsafe {acpi::apic::redirect_spurious(sdt_loc.lapic_ctrl, 255)}
 
-    println!("I/O and interrupt subsystem installed!");
+    info!("I/O and interrupt subsystem installed!");
+
+    if let Some(pmu) = pmu::init() {
+        info!("performance monitoring v{}: {} counters of {} bits, {} fixed",
+              pmu.version, pmu.counters, pmu.counter_width, pmu.fixed_counters);
+    }
+
+    if gdb::init() {
+        info!("GDB stub listening on the serial port");
+        if loader::command_line().split_whitespace().any(|word| word == "gdb") {
+            info!("waiting for GDB");
+            gdb::breakpoint();
+        }
+    }
 
     timers::init(sdt_loc.lapic_ctrl);
 
-    println!("Timer/scheduling system initialised!");
+    info!("Timer/scheduling system initialised!");
     use alloc::boxed::Box;
     use collections::String;
     // let heap_test = Box::new(42);
@@ -140,30 +193,39 @@ pub extern "C" fn rust_main(multiboot_information_address: usize) {
 
     use pipe::Buffer;
 
-    let mut buffer = Buffer::new();
-    buffer.write(42);
-    //println!("{}", buffer.read());
+    let buffer = Buffer::new(4);
+    buffer.try_push(42).unwrap();
+    //println!("{:?}", buffer.try_pop());
 
 
-    // Enable global interrupts!
-    unsafe {x86::irq::enable()};
+    sched::init();
+    syscall::init();
 
-//    println!("Global interrupts enabled!");
-//    println!("Going to sleep!");
-//    timers::busy_sleep(100);
-//    println!("Done sleeping!");
-
-    unsafe {
-        if let Some(ref mut buffer) = io::kbd_buffer {
-            let mut shell = shell::Shell::new();
-            shell.run(buffer);
-        }
+    // Every console has a shell running as a thread of its own, leaving
+    // this one to idle.
+    sched::spawn("tty", tty::tty_thread, 0).expect("could not start the console");
+    for console in 0..vga_buffer::NUM_CONSOLES {
+        sched::spawn(&format!("shell{}", console + 1), shell_thread, console)
+            .expect("could not start the shell");
+    }
+    if io::mouse::present() {
+        sched::spawn("mouse", io::mouse::pointer_thread, 0);
     }
 
-    // Loop to infinity and beyond!
+    info!("Scheduler initialised!");
 
+    // Enable global interrupts!
+    unsafe {x86::irq::enable()};
 
-    loop {}
+    // Loop to infinity and beyond!
+    sched::idle();
+}
+
+/// Entry point for the shell thread of virtual console `console`.
+fn shell_thread(console: usize) {
+    sched::set_console(console);
+    let mut shell = shell::Shell::new();
+    shell.run();
 }
 
 
@@ -189,24 +251,44 @@ fn enable_write_protect_bit() {
 extern fn eh_personality() {}
 
 /// This is an override for the Rust panic handler, and it runs when
-/// something crashes. This version displays PANIC and a description of
-/// where the panic occurred.  Try invoking the panic!(); macro to see
-/// it in action.
+/// something crashes. This version displays PANIC, a description of
+/// where the panic occurred and a backtrace (see the `panic` module).
+/// Try invoking the panic!(); macro to see it in action.
 #[cfg(not(test))]
 #[lang = "panic_fmt"]
 extern fn panic_fmt(fmt: core::fmt::Arguments, file: &str, line: u32) -> ! {
-    println!("\n\nPANIC in {} at line {}:", file, line);
-    println!("    {}", fmt);
-
-    loop{}
+    panic::panic(fmt, file, line)
 }
 
 
 /// This is a static entry point for the ASM interrupt wrappers to hook
 /// into. It has to be here, unfortunately.
 #[no_mangle]
-pub extern fn rust_interrupt_handler(intnr: usize) {
-    irq::entry(intnr);
+pub extern fn rust_interrupt_handler(intnr: usize, rip: usize, rbp: usize) {
+    irq::entry_from(intnr, rip, rbp);
+}
+
+/// Entry point for the debug and breakpoint exceptions, called from
+/// the assembler wrappers in `interrupts.asm` once `gdb::init()` has
+/// installed them.
+#[no_mangle]
+pub extern fn rust_debug_handler(vector: usize, frame: *mut gdb::TrapFrame) {
+    gdb::handle_exception(vector, unsafe { &mut *frame });
+}
+
+/// Entry point for system calls, called from the assembler stub in
+/// `syscall.asm`.
+#[no_mangle]
+pub extern fn rust_syscall_handler(num: usize, arg1: usize, arg2: usize,
+                                   arg3: usize) -> isize {
+    syscall::dispatch(num, arg1, arg2, arg3)
+}
+
+/// Entry point for new kernel threads, called from the assembler
+/// trampoline in `context_switch.asm`.
+#[no_mangle]
+pub extern fn rust_thread_start(entry: usize, arg: usize) -> ! {
+    sched::thread_start(entry, arg)
 }
 
 #[no_mangle]
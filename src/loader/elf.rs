@@ -0,0 +1,118 @@
+//! Just enough of ELF64 to find the loadable segments of an executable.
+
+use core::mem::size_of;
+
+use super::LoadError;
+
+/// `\x7fELF`
+const ELF_MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
+/// 64-bit objects
+const ELFCLASS64: u8 = 2;
+/// Little endian
+const ELFDATA2LSB: u8 = 1;
+/// Executable file
+const ET_EXEC: u16 = 2;
+/// AMD x86-64
+const EM_X86_64: u16 = 62;
+
+/// Loadable segment
+pub const PT_LOAD: u32 = 1;
+
+/// Segment is executable
+pub const PF_X: u32 = 1 << 0;
+/// Segment is writable
+pub const PF_W: u32 = 1 << 1;
+
+/// The ELF file header.
+#[repr(C)]
+struct Header {
+    ident: [u8; 16],
+    typ: u16,
+    machine: u16,
+    version: u32,
+    entry: u64,
+    phoff: u64,
+    shoff: u64,
+    flags: u32,
+    ehsize: u16,
+    phentsize: u16,
+    phnum: u16,
+    shentsize: u16,
+    shnum: u16,
+    shstrndx: u16,
+}
+
+/// A program header, describing a segment.
+#[repr(C)]
+pub struct ProgramHeader {
+    pub typ: u32,
+    pub flags: u32,
+    pub offset: u64,
+    pub vaddr: u64,
+    pub paddr: u64,
+    pub filesz: u64,
+    pub memsz: u64,
+    pub align: u64,
+}
+
+/// A validated ELF64 executable.
+pub struct Elf<'a> {
+    data: &'a [u8],
+    header: &'a Header,
+}
+
+impl<'a> Elf<'a> {
+    /// Check that `data` is an x86-64 executable we can make sense of.
+    pub fn parse(data: &'a [u8]) -> Result<Elf<'a>, LoadError> {
+        if data.len() < size_of::<Header>() || data.as_ptr() as usize % 8 != 0 {
+            return Err(LoadError::NotElf);
+        }
+        let header = unsafe { &*(data.as_ptr() as *const Header) };
+
+        if &header.ident[..4] != &ELF_MAGIC[..] {
+            return Err(LoadError::NotElf);
+        }
+        if header.ident[4] != ELFCLASS64 || header.ident[5] != ELFDATA2LSB
+            || header.typ != ET_EXEC || header.machine != EM_X86_64 {
+            return Err(LoadError::Unsupported);
+        }
+
+        let phoff = header.phoff as usize;
+        let phnum = header.phnum as usize;
+        if header.phentsize as usize != size_of::<ProgramHeader>()
+            || phoff % 8 != 0
+            || phoff > data.len()
+            || phnum > (data.len() - phoff) / size_of::<ProgramHeader>() {
+            return Err(LoadError::Malformed);
+        }
+
+        Ok(Elf { data: data, header: header })
+    }
+
+    /// The entry point.
+    pub fn entry(&self) -> usize {
+        self.header.entry as usize
+    }
+
+    /// The program headers.
+    pub fn program_headers(&self) -> &'a [ProgramHeader] {
+        use core::slice;
+        unsafe {
+            let start = self.data.as_ptr()
+                .offset(self.header.phoff as isize) as *const ProgramHeader;
+            slice::from_raw_parts(start, self.header.phnum as usize)
+        }
+    }
+
+    /// The file contents of segment `ph`, or `None` if they are not
+    /// within the file.
+    pub fn segment_data(&self, ph: &ProgramHeader) -> Option<&'a [u8]> {
+        let start = ph.offset as usize;
+        let len = ph.filesz as usize;
+        if start > self.data.len() || len > self.data.len() - start {
+            None
+        } else {
+            Some(&self.data[start..start + len])
+        }
+    }
+}
@@ -0,0 +1,245 @@
+//! # Program loader
+//!
+//! Loads ELF64 executables handed to us by the boot loader as multiboot
+//! modules, and starts them as user mode processes. Add programs to
+//! `grub.cfg` like this, where the last word is the module's name:
+//!
+//! ```
+//! module2 /boot/hello.elf hello
+//! ```
+//!
+//! Each process gets an address space of its own, with the `PT_LOAD`
+//! segments of the executable and a stack mapped above
+//! `memory::USER_SPACE_START`, and runs in a thread of its own.
+//!
+//! The process' memory is given back when its thread is joined.
+//!
+//! # Limitations
+//! Segments must not share pages.
+
+use alloc::boxed::Box;
+use collections::vec::Vec;
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use memory::{PAGE_SIZE, USER_SPACE_START, USER_SPACE_END, WRITABLE,
+             NO_EXECUTE, EntryFlags, AddressSpace};
+use sched::{self, ThreadId};
+use syscall;
+
+mod elf;
+mod multiboot;
+
+pub use self::multiboot::{Module, SectionHeader};
+
+/// Top of the user stack, leaving the last page of user space unmapped.
+const USER_STACK_TOP: usize = USER_SPACE_END - PAGE_SIZE;
+
+/// Section type of a symbol table
+const SHT_SYMTAB: u32 = 2;
+
+/// Size of the user stack, in pages.
+const USER_STACK_PAGES: usize = 16;
+
+/// Where the multiboot information structure is, set by `init()`.
+static MULTIBOOT_ADDRESS: AtomicUsize = AtomicUsize::new(0);
+
+/// Why a program could not be started.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadError {
+    /// There is no module with that name
+    NoSuchModule,
+    /// The module is not an ELF file
+    NotElf,
+    /// Not a 64-bit little endian x86-64 executable
+    Unsupported,
+    /// The ELF headers don't make sense
+    Malformed,
+    /// A segment lies outside user space, or overlaps another one
+    BadSegment,
+    /// Could not create the thread
+    NoThread,
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            LoadError::NoSuchModule => "no such module",
+            LoadError::NotElf => "not an ELF file",
+            LoadError::Unsupported => "not an x86-64 executable",
+            LoadError::Malformed => "malformed ELF file",
+            LoadError::BadSegment => "bad segment",
+            LoadError::NoThread => "could not create thread",
+        })
+    }
+}
+
+/// Remember where the multiboot information structure is, so modules
+/// can be found later on.
+pub fn init(multiboot_address: usize) {
+    MULTIBOOT_ADDRESS.store(multiboot_address, Ordering::SeqCst);
+}
+
+/// The physical memory spanned by the modules and the kernel's symbol
+/// tables, if there are any. Used by `memory::init()` to keep them from
+/// being overwritten.
+pub fn boot_area(multiboot_address: usize) -> Option<(usize, usize)> {
+    let mut area = multiboot::modules(multiboot_address)
+        .fold(None, |area, module| widen(area, module.start, module.end));
+    if let Some((symtab, strtab)) = symbol_tables(multiboot_address) {
+        for table in &[symtab, strtab] {
+            area = widen(area, table.addr as usize, (table.addr + table.size) as usize);
+        }
+    }
+    area
+}
+
+/// `area`, grown to cover `start` up to `end` too.
+fn widen(area: Option<(usize, usize)>, start: usize, end: usize)
+         -> Option<(usize, usize)> {
+    match area {
+        None => Some((start, end)),
+        Some((s, e)) => Some((if start < s { start } else { s },
+                              if end > e { end } else { e })),
+    }
+}
+
+/// The kernel's symbol table and its string table, from the multiboot
+/// information structure at `multiboot_address`. `None` if the kernel
+/// was stripped, or GRUB didn't load them.
+pub fn symbol_tables(multiboot_address: usize)
+                     -> Option<(&'static SectionHeader, &'static SectionHeader)> {
+    let sections = multiboot::sections(multiboot_address);
+    let symtab = match sections.iter().find(|s| s.typ == SHT_SYMTAB) {
+        Some(symtab) => symtab,
+        None => return None,
+    };
+    match sections.get(symtab.link as usize) {
+        Some(strtab) if symtab.addr != 0 && strtab.addr != 0 => Some((symtab, strtab)),
+        _ => None,
+    }
+}
+
+/// The kernel's symbol tables, once `init()` has been called.
+pub fn kernel_symbol_tables() -> Option<(&'static SectionHeader, &'static SectionHeader)> {
+    let address = MULTIBOOT_ADDRESS.load(Ordering::SeqCst);
+    if address == 0 {
+        None
+    } else {
+        symbol_tables(address)
+    }
+}
+
+/// All boot modules.
+pub fn modules() -> Vec<Module> {
+    let address = MULTIBOOT_ADDRESS.load(Ordering::SeqCst);
+    if address == 0 {
+        Vec::new()
+    } else {
+        multiboot::modules(address).collect()
+    }
+}
+
+/// The kernel command line, like `loglevel=debug`.
+pub fn command_line() -> &'static str {
+    let address = MULTIBOOT_ADDRESS.load(Ordering::SeqCst);
+    if address == 0 {
+        ""
+    } else {
+        multiboot::command_line(address)
+    }
+}
+
+/// Where a new process starts, handed to `start_process`.
+struct ProcessStart {
+    entry: usize,
+    stack_pointer: usize,
+}
+
+/// Load the module `name` and start it as a process. Returns the ID of
+/// the process' thread, to `sched::join()` on.
+pub fn spawn(name: &str) -> Result<ThreadId, LoadError> {
+    let module = match modules().into_iter().find(|m| m.name == name) {
+        Some(module) => module,
+        None => return Err(LoadError::NoSuchModule),
+    };
+
+    let elf = try!(elf::Elf::parse(module.data()));
+    let stack_bottom = USER_STACK_TOP - USER_STACK_PAGES * PAGE_SIZE;
+
+    // Check everything before mapping anything
+    let mut regions: Vec<(usize, usize)> = Vec::new();
+    for ph in elf.program_headers().iter().filter(|ph| ph.typ == elf::PT_LOAD) {
+        let start = ph.vaddr as usize;
+        let end = match start.checked_add(ph.memsz as usize) {
+            Some(end) => end,
+            None => return Err(LoadError::BadSegment),
+        };
+        if start < USER_SPACE_START || end > stack_bottom
+            || ph.filesz > ph.memsz || elf.segment_data(ph).is_none() {
+            return Err(LoadError::BadSegment);
+        }
+
+        let page_start = start & !(PAGE_SIZE - 1);
+        let page_end = (end + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+        if regions.iter().any(|&(s, e)| page_start < e && s < page_end) {
+            return Err(LoadError::BadSegment);
+        }
+        regions.push((page_start, page_end));
+    }
+
+    let entry = elf.entry();
+    if !regions.iter().any(|&(s, e)| entry >= s && entry < e) {
+        return Err(LoadError::Malformed);
+    }
+
+    let mut space = AddressSpace::new();
+
+    for ph in elf.program_headers().iter().filter(|ph| ph.typ == elf::PT_LOAD) {
+        let start = ph.vaddr as usize;
+        let page_start = start & !(PAGE_SIZE - 1);
+        let page_end = (start + ph.memsz as usize + PAGE_SIZE - 1)
+            & !(PAGE_SIZE - 1);
+        space.map_region(page_start, page_end - page_start,
+                         elf.segment_data(ph).unwrap(),
+                         start - page_start, segment_flags(ph.flags));
+    }
+
+    space.map_region(stack_bottom, USER_STACK_TOP - stack_bottom,
+                     &[], 0, WRITABLE | NO_EXECUTE);
+
+    let start = Box::into_raw(Box::new(ProcessStart {
+        entry: entry,
+        stack_pointer: USER_STACK_TOP,
+    })) as usize;
+
+    match sched::spawn_in(module.name, start_process, start, space) {
+        Some(id) => Ok(id),
+        None => {
+            unsafe { Box::from_raw(start as *mut ProcessStart) };
+            Err(LoadError::NoThread)
+        }
+    }
+}
+
+/// Page table flags for a segment with the ELF flags `flags`.
+fn segment_flags(flags: u32) -> EntryFlags {
+    let mut entry_flags = EntryFlags::empty();
+    if flags & elf::PF_W != 0 {
+        entry_flags = entry_flags | WRITABLE;
+    }
+    if flags & elf::PF_X == 0 {
+        entry_flags = entry_flags | NO_EXECUTE;
+    }
+    entry_flags
+}
+
+/// Entry point of a process' thread: drop to user mode. The scheduler
+/// has already loaded the process' address space.
+fn start_process(arg: usize) {
+    let start = unsafe { Box::from_raw(arg as *mut ProcessStart) };
+    let (entry, stack_pointer) = (start.entry, start.stack_pointer);
+    drop(start);
+
+    unsafe { syscall::enter_user_mode(entry, stack_pointer) }
+}
@@ -0,0 +1,165 @@
+//! Walking the multiboot2 information structure for the tags the
+//! `multiboot2` crate doesn't know about (yet).
+
+use core::{slice, str};
+
+/// Tag type of the end tag
+const TAG_END: u32 = 0;
+/// Tag type of the kernel command line
+const TAG_COMMAND_LINE: u32 = 1;
+/// Tag type of a boot module
+const TAG_MODULE: u32 = 3;
+/// Tag type of the kernel's ELF section headers
+const TAG_ELF_SECTIONS: u32 = 9;
+
+/// Every tag starts with this.
+#[repr(C)]
+struct TagHeader {
+    typ: u32,
+    size: u32,
+}
+
+/// A module tag. The module's command line follows, as a
+/// null-terminated string.
+#[repr(C)]
+struct ModuleTag {
+    typ: u32,
+    size: u32,
+    mod_start: u32,
+    mod_end: u32,
+}
+
+/// The ELF sections tag. The section headers follow.
+#[repr(C)]
+struct ElfSectionsTag {
+    typ: u32,
+    size: u32,
+    num: u32,
+    entsize: u32,
+    shndx: u32,
+}
+
+/// A section header of the kernel's ELF file. GRUB loads the sections
+/// that aren't allocated too, like the symbol table, and sets `addr` to
+/// where they are.
+#[repr(C)]
+pub struct SectionHeader {
+    pub name: u32,
+    pub typ: u32,
+    pub flags: u64,
+    pub addr: u64,
+    pub offset: u64,
+    pub size: u64,
+    /// For a symbol table, the index of its string table
+    pub link: u32,
+    pub info: u32,
+    pub addralign: u64,
+    pub entsize: u64,
+}
+
+/// A boot module, as loaded by GRUB's `module2` command.
+#[derive(Clone, Copy)]
+pub struct Module {
+    /// Physical start address
+    pub start: usize,
+    /// Physical end address (exclusive)
+    pub end: usize,
+    /// The module's command line, which we use as its name
+    pub name: &'static str,
+}
+
+impl Module {
+    /// The module's contents. Only valid once they have been mapped,
+    /// see `memory::init()`.
+    pub fn data(&self) -> &'static [u8] {
+        unsafe { slice::from_raw_parts(self.start as *const u8,
+                                       self.end - self.start) }
+    }
+}
+
+/// Iterates over the tags of the multiboot information structure.
+struct TagIter {
+    current: usize,
+}
+
+impl Iterator for TagIter {
+    type Item = &'static TagHeader;
+
+    fn next(&mut self) -> Option<&'static TagHeader> {
+        let tag = unsafe { &*(self.current as *const TagHeader) };
+        if tag.typ == TAG_END {
+            return None;
+        }
+        // Tags are 8-byte aligned
+        self.current += (tag.size as usize + 7) & !7;
+        Some(tag)
+    }
+}
+
+/// Iterates over the boot modules.
+pub struct ModuleIter {
+    tags: TagIter,
+}
+
+impl Iterator for ModuleIter {
+    type Item = Module;
+
+    fn next(&mut self) -> Option<Module> {
+        while let Some(tag) = self.tags.next() {
+            if tag.typ == TAG_MODULE {
+                let module = unsafe { &*(tag as *const _ as *const ModuleTag) };
+                return Some(Module {
+                    start: module.mod_start as usize,
+                    end: module.mod_end as usize,
+                    name: unsafe { tag_string(tag, 16) },
+                });
+            }
+        }
+        None
+    }
+}
+
+/// Read the null-terminated string at `offset` in `tag`. Returns an
+/// empty string if it isn't valid UTF-8, or the tag is too short to
+/// hold one.
+unsafe fn tag_string(tag: &'static TagHeader, offset: usize) -> &'static str {
+    if tag.size as usize <= offset {
+        return "";
+    }
+    let start = (tag as *const _ as usize + offset) as *const u8;
+    let max_len = tag.size as usize - offset;
+    let bytes = slice::from_raw_parts(start, max_len);
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(max_len);
+    str::from_utf8(&bytes[..len]).unwrap_or("")
+}
+
+/// The kernel command line in the multiboot information structure at
+/// `multiboot_address`, or an empty string if there is none.
+pub fn command_line(multiboot_address: usize) -> &'static str {
+    let mut tags = TagIter { current: multiboot_address + 8 };
+    match tags.find(|tag| tag.typ == TAG_COMMAND_LINE) {
+        Some(tag) => unsafe { tag_string(tag, 8) },
+        None => "",
+    }
+}
+
+/// The kernel's ELF section headers, from the multiboot information
+/// structure at `multiboot_address`.
+pub fn sections(multiboot_address: usize) -> &'static [SectionHeader] {
+    let mut tags = TagIter { current: multiboot_address + 8 };
+    match tags.find(|tag| tag.typ == TAG_ELF_SECTIONS) {
+        Some(tag) => unsafe {
+            let elf = &*(tag as *const _ as *const ElfSectionsTag);
+            let first = (elf as *const _ as usize + 20) as *const SectionHeader;
+            slice::from_raw_parts(first, elf.num as usize)
+        },
+        None => &[],
+    }
+}
+
+/// Iterate over the boot modules listed in the multiboot information
+/// structure at `multiboot_address`.
+pub fn modules(multiboot_address: usize) -> ModuleIter {
+    // Skip the total size and reserved fields
+    ModuleIter { tags: TagIter { current: multiboot_address + 8 } }
+}
@@ -0,0 +1,349 @@
+//! # Kernel log
+//!
+//! Leveled logging for the kernel, with the macros `error!`, `warn!`,
+//! `info!`, `debug!` and `trace!`:
+//!
+//! ```
+//! info!("found {} processors", count);
+//! ```
+//!
+//! A record gets through if its level is at most the level of the
+//! module it comes from. That is the level of the longest module filter
+//! matching the module path (`memory` matches `memory::paging` too), or
+//! else the global level, which is `Info` to begin with.
+//!
+//! Records that get through are timestamped and kept in a ring of the
+//! last `LOG_SIZE` records, for `dmesg`, and written to the first
+//! virtual console and the serial port. The ring doesn't need the heap,
+//! so logging works from the very start of `rust_main`.
+//!
+//! # Kernel command line
+//! The levels can be set on the kernel command line in `grub.cfg`:
+//!
+//! ```
+//! multiboot2 /boot/kernel.bin loglevel=debug loglevel=memory=trace
+//! ```
+
+use collections::String;
+use collections::vec::Vec;
+use core::fmt::{self, Write};
+use core::str::FromStr;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+
+use io::serial::SerialWriter;
+use irq;
+use timers;
+use vga_buffer;
+
+/// Log a record at `level`, formatted like `println!`.
+macro_rules! log {
+    ($level:expr, $($arg:tt)*) => ({
+        $crate::log::log($level, module_path!(), format_args!($($arg)*));
+    });
+}
+
+/// Something is broken.
+macro_rules! error {
+    ($($arg:tt)*) => (log!($crate::log::Level::Error, $($arg)*));
+}
+
+/// Something is wrong, but we can go on.
+macro_rules! warn {
+    ($($arg:tt)*) => (log!($crate::log::Level::Warn, $($arg)*));
+}
+
+/// Worth knowing, like what hardware was found.
+macro_rules! info {
+    ($($arg:tt)*) => (log!($crate::log::Level::Info, $($arg)*));
+}
+
+/// Useful when hunting bugs.
+macro_rules! debug {
+    ($($arg:tt)*) => (log!($crate::log::Level::Debug, $($arg)*));
+}
+
+/// Far too much to have on all the time.
+macro_rules! trace {
+    ($($arg:tt)*) => (log!($crate::log::Level::Trace, $($arg)*));
+}
+
+/// Records kept for `dmesg`
+const LOG_SIZE: usize = 128;
+
+/// Longest message kept in a record, in bytes. Longer ones are cut.
+const MESSAGE_SIZE: usize = 120;
+
+/// How important a record is, most important first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error = 1,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+const LEVELS: [Level; 5] = [Level::Error, Level::Warn, Level::Info,
+                            Level::Debug, Level::Trace];
+
+impl Level {
+    fn name(&self) -> &'static str {
+        match *self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        }
+    }
+
+    /// ANSI colour for the level on the console, if any
+    fn color(&self) -> Option<&'static str> {
+        match *self {
+            Level::Error => Some("\x1b[31m"),
+            Level::Warn => Some("\x1b[33m"),
+            Level::Info => None,
+            Level::Debug | Level::Trace => Some("\x1b[90m"),
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Level {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Level, ()> {
+        LEVELS.iter().find(|level| level.name() == s).cloned().ok_or(())
+    }
+}
+
+/// A logged message.
+#[derive(Clone, Copy)]
+pub struct Record {
+    /// Milliseconds since boot
+    pub timestamp: usize,
+    pub level: Level,
+    /// Module path, without the crate name
+    pub module: &'static str,
+    text: [u8; MESSAGE_SIZE],
+    len: usize,
+}
+
+const EMPTY_RECORD: Record = Record {
+    timestamp: 0,
+    level: Level::Info,
+    module: "",
+    text: [0; MESSAGE_SIZE],
+    len: 0,
+};
+
+impl Record {
+    pub fn message(&self) -> &str {
+        // Only whole characters are ever written
+        unsafe { ::core::str::from_utf8_unchecked(&self.text[..self.len]) }
+    }
+}
+
+/// Fills in the message, cutting it off at a character boundary when
+/// it doesn't fit.
+impl fmt::Write for Record {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for (i, c) in s.char_indices() {
+            let len = c.len_utf8();
+            if self.len + len > MESSAGE_SIZE {
+                return Err(fmt::Error);
+            }
+            self.text[self.len..self.len + len].clone_from_slice(&s.as_bytes()[i..i + len]);
+            self.len += len;
+        }
+        Ok(())
+    }
+}
+
+/// Like `[   12.345] memory::paging: message`
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{:>5}.{:03}] {}: {}", self.timestamp / 1000,
+               self.timestamp % 1000, self.module, self.message())
+    }
+}
+
+/// The last `LOG_SIZE` records.
+struct Ring {
+    records: [Record; LOG_SIZE],
+    /// Records ever logged; the next one goes at `count % LOG_SIZE`
+    count: usize,
+}
+
+static RING: Mutex<Ring> = Mutex::new(Ring {
+    records: [EMPTY_RECORD; LOG_SIZE],
+    count: 0,
+});
+
+/// Level of modules without a filter
+static LEVEL: AtomicUsize = AtomicUsize::new(Level::Info as usize);
+
+/// Levels of particular modules, and the modules inside them
+static FILTERS: Mutex<Option<Vec<(String, Level)>>> = Mutex::new(None);
+
+/// Where records go once they are in the ring.
+const SINKS: [fn(&Record); 2] = [console_sink, serial_sink];
+
+/// Log `args` at `level`, for the module with the full path `module`.
+/// Use the macros instead.
+pub fn log(level: Level, module: &'static str, args: fmt::Arguments) {
+    let module = short_path(module);
+    if level > level_of(module) {
+        return;
+    }
+
+    let mut record = Record { timestamp: timers::get_ticks(), level: level,
+                              module: module, ..EMPTY_RECORD };
+    // A message that doesn't fit is kept as far as it goes
+    let _ = record.write_fmt(args);
+
+    irq::without_interrupts(|| {
+        let mut ring = RING.lock();
+        let index = ring.count % LOG_SIZE;
+        ring.records[index] = record;
+        ring.count += 1;
+    });
+
+    for sink in SINKS.iter() {
+        sink(&record);
+    }
+}
+
+/// The module path without the crate name.
+fn short_path(module: &'static str) -> &'static str {
+    match module.find("::") {
+        Some(i) => &module[i + 2..],
+        None => module,
+    }
+}
+
+/// True if `filter` names `module` or a module it is inside.
+fn covers(filter: &str, module: &str) -> bool {
+    module.starts_with(filter)
+        && (module.len() == filter.len() || module[filter.len()..].starts_with("::"))
+}
+
+/// The level records from `module` must be at to get through.
+fn level_of(module: &str) -> Level {
+    let global = level();
+    irq::without_interrupts(|| {
+        let filters = FILTERS.lock();
+        let best = filters.iter().flat_map(|f| f.iter())
+            .filter(|&&(ref filter, _)| covers(filter, module))
+            .max_by_key(|&&(ref filter, _)| filter.len());
+        best.map(|&(_, level)| level).unwrap_or(global)
+    })
+}
+
+/// The level of modules without a filter.
+pub fn level() -> Level {
+    LEVELS[LEVEL.load(Ordering::Relaxed) - 1]
+}
+
+pub fn set_level(level: Level) {
+    LEVEL.store(level as usize, Ordering::Relaxed);
+}
+
+/// Log `module` and the modules inside it at `level`. Needs the heap.
+pub fn set_filter(module: &str, level: Level) {
+    irq::without_interrupts(|| {
+        let mut filters = FILTERS.lock();
+        if filters.is_none() {
+            *filters = Some(Vec::new());
+        }
+        let filters = filters.as_mut().unwrap();
+        filters.retain(|&(ref filter, _)| *filter != module);
+        filters.push((String::from(module), level));
+    })
+}
+
+/// The module filters, like `[("memory", Level::Trace)]`.
+pub fn filters() -> Vec<(String, Level)> {
+    irq::without_interrupts(|| FILTERS.lock().clone().unwrap_or_else(Vec::new))
+}
+
+/// Set a level from `setting`, which is either `LEVEL` or
+/// `MODULE=LEVEL`, as given to the `loglevel` command. Returns false if
+/// it doesn't make sense.
+pub fn configure(setting: &str) -> bool {
+    let mut parts = setting.splitn(2, '=');
+    match (parts.next(), parts.next()) {
+        (Some(level), None) => match level.parse() {
+            Ok(level) => { set_level(level); true },
+            Err(()) => false,
+        },
+        (Some(module), Some(level)) if !module.is_empty() => match level.parse() {
+            Ok(level) => { set_filter(module, level); true },
+            Err(()) => false,
+        },
+        _ => false,
+    }
+}
+
+/// Apply the `loglevel=` settings on the kernel command line.
+pub fn configure_from_command_line(command_line: &str) {
+    for word in command_line.split_whitespace() {
+        if word.starts_with("loglevel=") {
+            let setting = &word["loglevel=".len()..];
+            if !configure(setting) {
+                warn!("bad setting on the command line: {}", word);
+            }
+        }
+    }
+}
+
+/// Every record in the ring, oldest first.
+pub fn records() -> Vec<Record> {
+    irq::without_interrupts(|| {
+        let ring = RING.lock();
+        let kept = if ring.count < LOG_SIZE { ring.count } else { LOG_SIZE };
+        (ring.count - kept..ring.count)
+            .map(|i| ring.records[i % LOG_SIZE])
+            .collect()
+    })
+}
+
+/// Kernel messages go to the first console, whichever is showing.
+fn console_sink(record: &Record) {
+    let mut console = vga_buffer::console(0);
+    let _ = match record.level.color() {
+        Some(color) => write!(console, "{}{}\x1b[0m\n", color, record),
+        None => write!(console, "{}\n", record),
+    };
+}
+
+fn serial_sink(record: &Record) {
+    let _ = write!(SerialWriter, "{} {}\n", record.level, record);
+}
+
+
+#[test]
+/// A filter covers the modules inside it, but not ones that only start
+/// with the same letters
+fn log_filter_covers() {
+    assert!(covers("memory", "memory"));
+    assert!(covers("memory", "memory::paging"));
+    assert!(!covers("memory", "memoryless"));
+    assert!(!covers("memory::paging", "memory"));
+}
+
+#[test]
+/// Long messages are cut off without splitting a character
+fn log_record_truncated() {
+    let mut record = EMPTY_RECORD;
+    let long: String = (0..MESSAGE_SIZE).map(|_| 'å').collect();
+    assert!(record.write_str(&long).is_err());
+    assert_eq!(record.message().len(), MESSAGE_SIZE);
+    assert!(record.message().chars().all(|c| c == 'å'));
+}
@@ -1,10 +1,11 @@
 use memory::{Frame, FrameAllocator};
+use collections::vec::Vec;
 use multiboot2::{MemoryAreaIter, MemoryArea};
 
 /// This is a (fairly dumb) `FrameAllocator`, but it keeps track of
 /// where the kernel and multiboot sectors are located, and allocates
-/// frames linearly.
-/// FIXME: There is currently *no way* to deallocate a frame!
+/// frames linearly. Deallocated frames are kept on a list, and handed
+/// out again before any new ones.
 pub struct AreaFrameAllocator {
     /// Incrementing index, every number beneath this is used
     next_free_frame: Frame,
@@ -21,6 +22,10 @@ pub struct AreaFrameAllocator {
     multiboot_start: Frame,
     /// Index of last multiboot memory area
     multiboot_end: Frame,
+    /// First and last frame of the multiboot modules, if any
+    modules: Option<(Frame, Frame)>,
+    /// Frames given back with `deallocate_frame`
+    recycled: Vec<Frame>,
 }
 
 /// This is the method part of the "object".
@@ -29,6 +34,7 @@ impl AreaFrameAllocator {
     /// Constructor function. Note how it is the only public function!
     pub fn new(kernel_start: usize, kernel_end: usize,
                multiboot_start: usize, multiboot_end: usize,
+               modules: Option<(usize, usize)>,
                memory_areas: MemoryAreaIter) -> AreaFrameAllocator
     {
         let mut allocator = AreaFrameAllocator {
@@ -39,6 +45,11 @@ impl AreaFrameAllocator {
             kernel_end: Frame::containing_address(kernel_end),
             multiboot_start: Frame::containing_address(multiboot_start),
             multiboot_end: Frame::containing_address(multiboot_end),
+            modules: modules.map(|(start, end)| {
+                (Frame::containing_address(start),
+                 Frame::containing_address(end))
+            }),
+            recycled: Vec::new(),
         };
 
         // Set up the next pointer.
@@ -77,6 +88,15 @@ impl AreaFrameAllocator {
         return *frame >= self.multiboot_start &&
             *frame <= self.multiboot_end;
     }
+
+    /// Helper function: returns true if `frame` is inside the area
+    /// holding the multiboot modules, false otherwise.
+    fn frame_in_modules(&self, frame: &Frame) -> bool {
+        match self.modules {
+            Some((ref start, ref end)) => *frame >= *start && *frame <= *end,
+            None => false,
+        }
+    }
 }
 
 /// Implementations for the `FrameAllocator` interface for
@@ -88,6 +108,10 @@ impl FrameAllocator for AreaFrameAllocator {
     /// algorithm tries to allocate a frame from the beginning to the
     /// end, and determines if it failed afterwards.
     fn allocate_frame(&mut self) -> Option<Frame> {
+        if let Some(frame) = self.recycled.pop() {
+            return Some(frame);
+        }
+
         if let Some(area) = self.current_area {
             // If the frame is free, return it by constructing an
             // identical frame.
@@ -118,6 +142,12 @@ impl FrameAllocator for AreaFrameAllocator {
                     number: self.multiboot_end.number + 1
                 };
 
+            } else if self.frame_in_modules(&frame) {
+                // `frame` holds a multiboot module (a program to load)
+                self.next_free_frame = Frame {
+                    number: self.modules.as_ref().unwrap().1.number + 1
+                };
+
             // End of things that could go wrong
             } else {
                 // `frame` is unused, increment `next_free_frame` and return it.
@@ -133,9 +163,10 @@ impl FrameAllocator for AreaFrameAllocator {
         }
     }
 
-    /// FIXME: Not implemented!
-    fn deallocate_frame(&mut self, _frame: Frame) {
-        unimplemented!()
+    /// Put `frame` on the recycle list. The list lives on the heap, so
+    /// this must not be called before the heap is mapped.
+    fn deallocate_frame(&mut self, frame: Frame) {
+        self.recycled.push(frame);
     }
 
 }
@@ -2,24 +2,159 @@
 
 mod area_frame_allocator;
 mod paging;
+mod stack_allocator;
 
 
 /// Include the `AreaFrameAllocator`
 pub use self::area_frame_allocator::AreaFrameAllocator;
 
+/// Include `Stack`, as handed out by `alloc_stack()`
+pub use self::stack_allocator::Stack;
+
+/// Include what is needed to build address spaces for user programs
+pub use self::paging::{AddressSpace, EntryFlags, WRITABLE, NO_EXECUTE,
+                       activate_kernel};
+
 /// Include `PhysicalAddress`
 use self::paging::PhysicalAddress;
 pub use self::paging::{test_paging};
 use self::paging::{remap_the_kernel};
 use multiboot2::BootInformation;
 use acpi::{SDT_Loc};
+use spin::Mutex;
 
 /// The standard Page/Frame size
 pub const PAGE_SIZE: usize = 4096;
 
+/// Start of the area used for kernel stacks: the third P3 entry, right
+/// after the heap.
+const KERNEL_STACKS_START: usize = 0o_000_002_000_000_0000;
+
+/// Size of the kernel stack area (one P3 entry, or 1 GiB).
+const KERNEL_STACKS_SIZE: usize = 0o_000_001_000_000_0000;
+
+/// Page used to temporarily map frames while building page tables.
+/// Lives in the kernel's P4 entry, so it is valid in every address space.
+const TEMPORARY_PAGE_ADDRESS: usize = 0o_000_003_000_000_0000;
+
+/// Start of user space. The whole first P4 entry (512 GiB) belongs to
+/// the kernel, which makes it easy to share between address spaces.
+pub const USER_SPACE_START: usize = 0o_001_000_000_000_0000;
+
+/// End (exclusive) of user space: the end of the lower half.
+pub const USER_SPACE_END: usize = 0x0000_8000_0000_0000;
+
+/// Everything needed to change the kernel's memory mappings once
+/// `init()` has run.
+pub struct MemoryController {
+    active_table: paging::ActivePageTable,
+    frame_allocator: AreaFrameAllocator,
+    stack_allocator: stack_allocator::StackAllocator,
+    temporary_page: paging::TemporaryPage,
+    pcids: paging::PcidAllocator,
+}
+
+/// The frame allocator holds raw pointers into the multiboot memory map,
+/// which makes the compiler nervous. They are only ever read, and the
+/// controller is always accessed through a lock.
+unsafe impl Send for MemoryController {}
+
+/// The memory controller, available after `init()`.
+static MEMORY_CONTROLLER: Mutex<Option<MemoryController>> = Mutex::new(None);
+
+/// Allocate a kernel stack of `size_in_pages` pages, with a guard page
+/// below it. Returns `None` if we ran out of stack space, or if
+/// `init()` hasn't been called yet.
+///
+/// Don't call this from an interrupt handler!
+pub fn alloc_stack(size_in_pages: usize) -> Option<Stack> {
+    if let Some(ref mut mc) = *MEMORY_CONTROLLER.lock() {
+        mc.stack_allocator.alloc_stack(&mut mc.active_table,
+                                       &mut mc.frame_allocator,
+                                       size_in_pages)
+    } else {
+        None
+    }
+}
+
+/// Check that all of `[start, start + len)` lies in user space and is
+/// mapped user accessible in the active page table (and writable, if
+/// `writable` is set). Use this before touching memory on behalf of a
+/// user program.
+pub fn user_range_accessible(start: usize, len: usize, writable: bool) -> bool {
+    use self::paging::{Page, USER_ACCESSIBLE, WRITABLE};
+
+    let end = match start.checked_add(len) {
+        Some(end) => end,
+        None => return false,
+    };
+    if start < USER_SPACE_START || end > USER_SPACE_END {
+        return false;
+    }
+    if len == 0 {
+        return true;
+    }
+
+    let mut required = USER_ACCESSIBLE;
+    if writable {
+        required = required | WRITABLE;
+    }
+
+    if let Some(ref mc) = *MEMORY_CONTROLLER.lock() {
+        Page::range_inclusive(Page::containing_address(start),
+                              Page::containing_address(end - 1))
+            .all(|page| match mc.active_table.page_flags(page) {
+                Some(flags) => flags.contains(required),
+                None => false,
+            })
+    } else {
+        false
+    }
+}
 
-/// Initialization of memory and mapping
-pub fn init(boot_info: &BootInformation, sdt_loc: &mut SDT_Loc) {
+/// Check that `[start, start + len)` can be read without a page fault.
+/// Doesn't wait for the memory controller, so it is safe to use while
+/// panicking; if the controller is in use, the answer is `false`.
+pub fn kernel_readable(start: usize, len: usize) -> bool {
+    use self::paging::Page;
+
+    let end = match start.checked_add(len) {
+        Some(end) => end,
+        None => return false,
+    };
+    if len == 0 {
+        return true;
+    }
+
+    let controller = match MEMORY_CONTROLLER.try_lock() {
+        Some(controller) => controller,
+        None => return false,
+    };
+    match *controller {
+        Some(ref mc) => Page::range_inclusive(Page::containing_address(start),
+                                              Page::containing_address(end - 1))
+            .all(|page| mc.active_table.translate_page(page).is_some()),
+        // The boot page tables map the first gigabyte
+        None => end <= 1024 * 1024 * 1024,
+    }
+}
+
+/// Give back a stack previously handed out by `alloc_stack()`.
+///
+/// Don't call this from an interrupt handler!
+pub fn free_stack(stack: Stack) {
+    if let Some(ref mut mc) = *MEMORY_CONTROLLER.lock() {
+        mc.stack_allocator.free_stack(stack);
+    }
+}
+
+
+/// Initialization of memory and mapping. `modules` is the physical
+/// memory holding the multiboot modules and the kernel's symbol tables,
+/// if any, which is kept out of the frame allocator's hands and
+/// identity mapped (read-only).
+pub fn init(boot_info: &BootInformation, modules: Option<(usize, usize)>,
+            sdt_loc: &mut SDT_Loc) {
     assert_has_not_been_called!("memory::init must be called only once");
 
     // Get memory map tag
@@ -39,12 +174,9 @@ pub fn init(boot_info: &BootInformation, sdt_loc: &mut SDT_Loc) {
         .filter(|s| s.is_allocated()).map(|s| s.addr + s.size).max()
         .unwrap();
 
-    // println!("Kernel start: 0x{:#x}, kernel end: 0x{:#x}",
-    //          kernel_start,
-    //          kernel_end);
-    // println!("Multiboot start: 0x{:#x}, multiboot end: 0x{:#x}",
-    //          boot_info.start_address(),
-    //          boot_info.end_address());
+    debug!("kernel start: {:#x}, kernel end: {:#x}", kernel_start, kernel_end);
+    debug!("multiboot start: {:#x}, multiboot end: {:#x}",
+           boot_info.start_address(), boot_info.end_address());
 
     // Set up a frame allocator.
     let mut frame_allocator = AreaFrameAllocator::new(
@@ -52,11 +184,12 @@ pub fn init(boot_info: &BootInformation, sdt_loc: &mut SDT_Loc) {
         kernel_end as usize,
         boot_info.start_address(),
         boot_info.end_address(),
+        modules,
         memory_map_tag.memory_areas());
 
     // Remap the kernel
     let mut active_table = paging::remap_the_kernel(
-        &mut frame_allocator, boot_info, sdt_loc);
+        &mut frame_allocator, boot_info, modules, sdt_loc);
 
 
     // TODO: Move apic/SDT page mapping here?
@@ -81,6 +214,47 @@ pub fn init(boot_info: &BootInformation, sdt_loc: &mut SDT_Loc) {
         }
     }
 
+    // Set aside an area for kernel (thread) stacks
+    let stack_allocator = {
+        use self::paging::Page;
+        let stack_start_page = Page::containing_address(KERNEL_STACKS_START);
+        let stack_end_page = Page::containing_address(
+            KERNEL_STACKS_START + KERNEL_STACKS_SIZE - 1);
+        stack_allocator::StackAllocator::new(
+            Page::range_inclusive(stack_start_page, stack_end_page))
+    };
+
+    let temporary_page = paging::TemporaryPage::new(
+        paging::Page::containing_address(TEMPORARY_PAGE_ADDRESS),
+        &mut frame_allocator);
+
+    let kernel_p4 = unsafe { ::x86::controlregs::cr3() } as usize;
+    paging::KERNEL_P4.store(kernel_p4, ::core::sync::atomic::Ordering::Relaxed);
+
+    *MEMORY_CONTROLLER.lock() = Some(MemoryController {
+        active_table: active_table,
+        frame_allocator: frame_allocator,
+        stack_allocator: stack_allocator,
+        temporary_page: temporary_page,
+        pcids: paging::PcidAllocator::new(enable_pcid()),
+    });
+}
+
+/// Turn on PCIDs (and global pages, which the temporary page relies on
+/// with them) if the CPU has them. Returns whether it did.
+fn enable_pcid() -> bool {
+    use arch::x86_64::cpuid::CPUID;
+    use x86::controlregs::{cr4, cr4_write};
+
+    let pge_bit = 1 << 7;
+    let pcide_bit = 1 << 17;
+
+    if CPUID::new().features().pcid() {
+        unsafe { cr4_write(cr4() | pge_bit | pcide_bit) };
+        true
+    } else {
+        false
+    }
 }
 
 /// The `Frame` is represented by its `number`.
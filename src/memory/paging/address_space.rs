@@ -0,0 +1,274 @@
+//! Address spaces for user programs.
+//!
+//! Every address space shares the kernel's P4 entry (and with it the
+//! kernel, heap and stacks) with all the others, and owns whatever is
+//! mapped above `USER_SPACE_START`. Dropping it frees all of that.
+//!
+//! If the CPU supports PCIDs, each address space gets one of its own, so
+//! switching between them doesn't throw away the whole TLB.
+
+use collections::vec::Vec;
+use core::ptr;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use x86::controlregs;
+
+use irq;
+use memory::{MemoryController, MEMORY_CONTROLLER, PAGE_SIZE, Frame,
+             FrameAllocator, USER_SPACE_START, USER_SPACE_END};
+use super::{Page, InactivePageTable, EntryFlags, USER_ACCESSIBLE,
+            VirtualAddress, PhysicalAddress, ENTRY_COUNT};
+
+/// The P4 entries covering user space.
+const USER_P4_START: usize = USER_SPACE_START >> 39;
+const USER_P4_END: usize = USER_SPACE_END >> 39;
+
+/// CR3 bits holding the PCID
+const CR3_PCID_MASK: u64 = 0xfff;
+/// Don't flush the TLB entries of the new PCID when loading CR3
+const CR3_NO_FLUSH: u64 = 1 << 63;
+
+/// Physical address of the kernel's own P4 table, set by
+/// `memory::init()`. Not kept in the memory controller: the scheduler
+/// loads it while the thread it preempted may hold the controller.
+pub static KERNEL_P4: AtomicUsize = AtomicUsize::new(0);
+
+/// Load the kernel's own page table into CR3, unless it's already there.
+/// For threads without an address space of their own.
+pub unsafe fn activate_kernel() {
+    let kernel_p4 = KERNEL_P4.load(Ordering::Relaxed) as u64;
+    if kernel_p4 != 0 && controlregs::cr3() as u64 & !CR3_PCID_MASK != kernel_p4 {
+        controlregs::cr3_write(kernel_p4);
+    }
+}
+
+/// The number of PCIDs. PCID 0 is the kernel's.
+const NUM_PCIDS: u16 = 4096;
+
+/// Hands out PCIDs to address spaces.
+pub struct PcidAllocator {
+    /// Whether PCIDs are enabled at all
+    enabled: bool,
+    /// The next never-used PCID
+    next: u16,
+    /// PCIDs given back by dropped address spaces
+    recycled: Vec<u16>,
+}
+
+impl PcidAllocator {
+    /// Create an allocator, handing out PCIDs only if `enabled`.
+    pub fn new(enabled: bool) -> PcidAllocator {
+        PcidAllocator {
+            enabled: enabled,
+            next: 1,
+            recycled: Vec::new(),
+        }
+    }
+
+    fn allocate(&mut self) -> Option<u16> {
+        if !self.enabled {
+            None
+        } else if let Some(pcid) = self.recycled.pop() {
+            Some(pcid)
+        } else if self.next < NUM_PCIDS {
+            self.next += 1;
+            Some(self.next - 1)
+        } else {
+            None
+        }
+    }
+
+    fn deallocate(&mut self, pcid: u16) {
+        self.recycled.push(pcid);
+    }
+}
+
+/// A user address space.
+pub struct AddressSpace {
+    table: InactivePageTable,
+    /// PCID tagging this space's TLB entries, if we have one
+    pcid: Option<u16>,
+    /// Whether the TLB may still hold entries from the last owner of
+    /// `pcid`, which must be flushed on first use
+    stale: bool,
+}
+
+impl AddressSpace {
+    /// Create a new, empty address space with the kernel mapped in.
+    pub fn new() -> AddressSpace {
+        let mut guard = MEMORY_CONTROLLER.lock();
+        let MemoryController { ref mut active_table,
+                               ref mut frame_allocator,
+                               ref mut temporary_page,
+                               ref mut pcids, .. } =
+            *guard.as_mut().expect("memory not initialised");
+
+        let (kernel_p3, kernel_flags) = {
+            let entry = &active_table.p4()[0];
+            (entry.pointed_frame().expect("kernel P3 table missing"),
+             entry.flags())
+        };
+
+        let frame = frame_allocator.allocate_frame().expect("out of memory");
+        let mut table = InactivePageTable::new(frame, active_table, temporary_page);
+
+        active_table.with(&mut table, temporary_page, |mapper| {
+            mapper.p4_mut()[0].set(kernel_p3, kernel_flags);
+        });
+
+        AddressSpace {
+            table: table,
+            pcid: pcids.allocate(),
+            stale: true,
+        }
+    }
+
+    /// Map `size` bytes of fresh memory at `start` (page aligned), user
+    /// accessible with the given `flags`. The memory is zeroed, except
+    /// for `data`, which is copied in at `data_offset` bytes from
+    /// `start`.
+    ///
+    /// Panics if the region is outside user space, already mapped, or if
+    /// we run out of memory.
+    pub fn map_region(&mut self, start: VirtualAddress, size: usize,
+                      data: &[u8], data_offset: usize, flags: EntryFlags) {
+        assert!(start % PAGE_SIZE == 0, "region must be page aligned");
+        assert!(start >= USER_SPACE_START && size <= USER_SPACE_END - start,
+                "region outside of user space");
+        assert!(data_offset + data.len() <= size, "data does not fit the region");
+
+        if size == 0 {
+            return;
+        }
+
+        let mut guard = MEMORY_CONTROLLER.lock();
+        let MemoryController { ref mut active_table,
+                               ref mut frame_allocator,
+                               ref mut temporary_page, .. } =
+            *guard.as_mut().expect("memory not initialised");
+
+        // Fill the frames through the temporary page first: it can't be
+        // used for that while the recursive mapping points at our table.
+        let mut mappings = Vec::new();
+        let pages = Page::range_inclusive(
+            Page::containing_address(start),
+            Page::containing_address(start + size - 1));
+        for (i, page) in pages.enumerate() {
+            let frame = frame_allocator.allocate_frame().expect("out of memory");
+            let address = temporary_page.map(frame.clone(), active_table);
+
+            let page_start = i * PAGE_SIZE;
+            let page_end = page_start + PAGE_SIZE;
+            let data_end = data_offset + data.len();
+            let copy_start = if data_offset > page_start { data_offset } else { page_start };
+            let copy_end = if data_end < page_end { data_end } else { page_end };
+
+            unsafe {
+                ptr::write_bytes(address as *mut u8, 0, PAGE_SIZE);
+                if copy_start < copy_end {
+                    ptr::copy_nonoverlapping(
+                        data.as_ptr().offset((copy_start - data_offset) as isize),
+                        (address + copy_start - page_start) as *mut u8,
+                        copy_end - copy_start);
+                }
+            }
+
+            temporary_page.unmap(active_table);
+            mappings.push((page, frame));
+        }
+
+        active_table.with(&mut self.table, temporary_page, |mapper| {
+            for (page, frame) in mappings {
+                mapper.map_to(page, frame, flags | USER_ACCESSIBLE, frame_allocator);
+            }
+        });
+    }
+
+    /// Physical address of the P4 table.
+    pub fn p4_address(&self) -> PhysicalAddress {
+        self.table.p4_address()
+    }
+
+    /// Whether this is the address space the CPU is using.
+    pub fn is_active(&self) -> bool {
+        let cr3 = unsafe { controlregs::cr3() } as u64;
+        (cr3 & !CR3_PCID_MASK) as usize == self.p4_address()
+    }
+
+    /// Load this address space into CR3, unless it's already there.
+    ///
+    /// # Safety
+    /// The kernel stack in use must be mapped in every address space,
+    /// which all kernel stacks are.
+    pub unsafe fn activate(&mut self) {
+        if self.is_active() {
+            return;
+        }
+
+        let mut cr3 = self.p4_address() as u64;
+        if let Some(pcid) = self.pcid {
+            cr3 |= pcid as u64;
+            if !self.stale {
+                cr3 |= CR3_NO_FLUSH;
+            }
+            self.stale = false;
+        }
+        controlregs::cr3_write(cr3);
+    }
+}
+
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        let mut guard = MEMORY_CONTROLLER.lock();
+        let MemoryController { ref mut active_table,
+                               ref mut frame_allocator,
+                               ref mut temporary_page,
+                               ref mut pcids, .. } =
+            *guard.as_mut().expect("memory not initialised");
+
+        // Don't pull the rug out from under ourselves, should this
+        // address space still be loaded.
+        irq::without_interrupts(|| {
+            if self.is_active() {
+                unsafe { activate_kernel() };
+            }
+        });
+
+        active_table.with(&mut self.table, temporary_page, |mapper| {
+            let p4 = mapper.p4_mut();
+            for i in USER_P4_START..USER_P4_END {
+                if let Some(p3) = p4.next_table_mut(i) {
+                    for j in 0..ENTRY_COUNT {
+                        if let Some(p2) = p3.next_table_mut(j) {
+                            for k in 0..ENTRY_COUNT {
+                                if let Some(p1) = p2.next_table_mut(k) {
+                                    for l in 0..ENTRY_COUNT {
+                                        free_entry_frame(&p1[l], frame_allocator);
+                                    }
+                                }
+                                free_entry_frame(&p2[k], frame_allocator);
+                            }
+                        }
+                        free_entry_frame(&p3[j], frame_allocator);
+                    }
+                }
+                free_entry_frame(&p4[i], frame_allocator);
+            }
+        });
+
+        frame_allocator.deallocate_frame(
+            Frame::containing_address(self.p4_address()));
+
+        if let Some(pcid) = self.pcid {
+            pcids.deallocate(pcid);
+        }
+    }
+}
+
+/// Give back the frame `entry` points to, if any.
+fn free_entry_frame<A>(entry: &super::Entry, allocator: &mut A)
+    where A: FrameAllocator
+{
+    if let Some(frame) = entry.pointed_frame() {
+        allocator.deallocate_frame(frame);
+    }
+}
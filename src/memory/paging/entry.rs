@@ -31,6 +31,11 @@ impl Entry {
         }
     }
 
+    /// Add 'flags' to the entry, keeping the frame and other flags
+    pub fn add_flags(&mut self, flags: EntryFlags) {
+        self.0 |= flags.bits();
+    }
+
     /// Safety check that address is page aligned and smaller than 2^52
     pub fn set(&mut self, frame: Frame, flags: EntryFlags) {
         assert!(frame.start_address() & ! 0x000fffff_fffff000 == 0);
@@ -77,17 +77,48 @@ impl Mapper {
     /// Maps the page to the frame with the provided flags.
     /// The 'PRESENT' flag is added by default. Needs a 
     /// 'FrameAllocator' as it might need to create new page tables.
+    /// If the page is 'USER_ACCESSIBLE', so are the tables leading to it,
+    /// since the CPU checks the flag on every level.
     pub fn map_to<A>(&mut self, page: Page, frame: Frame, flags: EntryFlags, allocator: &mut A)
         where A: FrameAllocator
     {
-        let mut p3 = self.p4_mut().next_table_create(page.p4_index(), allocator);
-        let mut p2 = p3.next_table_create(page.p3_index(), allocator);
-        let mut p1 = p2.next_table_create(page.p2_index(), allocator);
+        let user = flags & USER_ACCESSIBLE;
+
+        {
+            let p4 = self.p4_mut();
+            p4.next_table_create(page.p4_index(), allocator);
+            p4[page.p4_index()].add_flags(user);
+        }
+        let mut p3 = self.p4_mut().next_table_mut(page.p4_index()).unwrap();
+
+        p3.next_table_create(page.p3_index(), allocator);
+        p3[page.p3_index()].add_flags(user);
+        let mut p2 = p3.next_table_mut(page.p3_index()).unwrap();
+
+        p2.next_table_create(page.p2_index(), allocator);
+        p2[page.p2_index()].add_flags(user);
+        let mut p1 = p2.next_table_mut(page.p2_index()).unwrap();
 
         assert!(p1[page.p1_index()].is_unused());
         p1[page.p1_index()].set(frame, flags | PRESENT);
     }
 
+    /// Returns the flags of the entry mapping 'page', or 'None' if the
+    /// page is not mapped. Does not support huge pages.
+    pub fn page_flags(&self, page: Page) -> Option<EntryFlags> {
+        self.p4().next_table(page.p4_index())
+            .and_then(|p3| p3.next_table(page.p3_index()))
+            .and_then(|p2| p2.next_table(page.p2_index()))
+            .and_then(|p1| {
+                let flags = p1[page.p1_index()].flags();
+                if flags.contains(PRESENT) {
+                    Some(flags)
+                } else {
+                    None
+                }
+            })
+    }
+
     /// Maps the page to some free frame with the provided flags.
     /// The free frame is allocated from the given 'FrameAllocator'
     pub fn map<A>(&mut self, page: Page, flags: EntryFlags, allocator: &mut A)
@@ -13,7 +13,7 @@ use self::table::{Table, Level4};
 pub use self::mapper::Mapper;
 use core::ptr::Unique;
 use core::ops::{Deref, DerefMut};
-use self::temporary_page::TemporaryPage;
+pub use self::temporary_page::TemporaryPage;
 use multiboot2::BootInformation;
 
 //use acpi::rsdp;
@@ -27,6 +27,10 @@ use core::option;
 mod entry;
 mod table;
 mod mapper;
+mod address_space;
+
+pub use self::address_space::{AddressSpace, PcidAllocator, KERNEL_P4,
+                              activate_kernel};
 
 ///Used to temporary map a frame to virtal address
 mod temporary_page;
@@ -56,7 +60,7 @@ impl Page {
     }
 
     /// Takes a VirtualAddress and calculates start address
-    fn start_address(&self) -> PhysicalAddress {
+    pub fn start_address(&self) -> PhysicalAddress {
         self.number * PAGE_SIZE
     }
 
@@ -89,6 +93,7 @@ impl Page {
     }
 }
 
+#[derive(Clone)]
 pub struct PageIter {
     start: Page,
     end: Page
@@ -212,9 +217,16 @@ impl InactivePageTable {
 
         InactivePageTable {p4_frame: frame }
     }
+
+    /// Physical address of the P4 table, as loaded into CR3.
+    pub fn p4_address(&self) -> PhysicalAddress {
+        self.p4_frame.start_address()
+    }
 }
 /// Remaps the kernel sections by creating a temporary page.
-pub fn remap_the_kernel<A>(allocator: &mut A, boot_info: &BootInformation, sdt_loc: &mut SDT_Loc)
+pub fn remap_the_kernel<A>(allocator: &mut A, boot_info: &BootInformation,
+                           modules: Option<(usize, usize)>,
+                           sdt_loc: &mut SDT_Loc)
     -> ActivePageTable
     where A: FrameAllocator{
     use core::ops::Range;
@@ -243,9 +255,8 @@ pub fn remap_the_kernel<A>(allocator: &mut A, boot_info: &BootInformation, sdt_l
 
             assert!(section.addr as usize % PAGE_SIZE == 0,
                     "sections need to be page aligned");
-            // println!("mapping section at addr: {:#x}, size: {:#x}",
-            //          section.addr,
-            //          section.size);
+            trace!("mapping section at addr: {:#x}, size: {:#x}",
+                   section.addr, section.size);
 
             let flags = EntryFlags::from_elf_section_flags(section);
 
@@ -270,11 +281,21 @@ pub fn remap_the_kernel<A>(allocator: &mut A, boot_info: &BootInformation, sdt_l
             mapper.identity_map(frame, PRESENT, allocator);
         }
 
-
+        // identity map the multiboot modules (programs to load) and the
+        // kernel's symbol tables, read-only
+        if let Some((start, end)) = modules {
+            let modules_start = Frame::containing_address(start);
+            let modules_end = Frame::containing_address(end - 1);
+            for frame in Frame::range_inclusive(modules_start, modules_end) {
+                if mapper.is_unused(&frame, allocator) {
+                    mapper.identity_map(frame, PRESENT | NO_EXECUTE, allocator);
+                }
+            }
+        }
 
 
         for (start, end, next) in &mut sdt_loc.into_iter() {
-            //println!("Allocating addresses {:x} to {:x} and {:x}", start, end, next);
+            trace!("mapping ACPI tables at {:#x} to {:#x}, next at {:#x}", start, end, next);
             let start_addr = Frame::containing_address(start);
             let end_addr = Frame::containing_address(end);
             for frame in Frame::range_inclusive(start_addr, end_addr) {
@@ -307,14 +328,13 @@ pub fn remap_the_kernel<A>(allocator: &mut A, boot_info: &BootInformation, sdt_l
 
 
     });
-    // TODO: Delete when appropriate
     let old_table = active_table.switch(new_table);
-    //println!("NEW TABLE!!!");
+    debug!("switched to the new page table");
 
-    // TODO: Delete when appropriate
+    // The old P4 table becomes the guard page below the kernel stack
     let old_p4_page = Page::containing_address(old_table.p4_frame.start_address());
     active_table.unmap(old_p4_page, allocator);
-    //println!("guard page at {:#x}", old_p4_page.start_address());
+    debug!("guard page at {:#x}", old_p4_page.start_address());
 
     active_table
 }
@@ -26,11 +26,14 @@ impl TemporaryPage {
     /// Returns the start address of the temporary page.
     pub fn map(&mut self, frame: Frame, active_table: &mut ActivePageTable)
                -> VirtualAddress{
-            use super::entry::WRITABLE;
+            use super::entry::{WRITABLE, GLOBAL};
             
             assert!(active_table.translate_page(self.page).is_none(),
                     "temporary page is already mapped");
-            active_table.map_to(self.page, frame, WRITABLE, &mut self.allocator);
+            // Global, so that unmapping it flushes it from the TLB of
+            // every address space (PCID), not just the active one.
+            active_table.map_to(self.page, frame, WRITABLE | GLOBAL,
+                                &mut self.allocator);
             self.page.start_address()
         }
     
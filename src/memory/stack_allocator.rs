@@ -0,0 +1,117 @@
+/// Hands out kernel stacks from a dedicated area of virtual memory.
+/// Every stack gets an unmapped guard page below it, so that a stack
+/// overflow causes a page fault instead of silently corrupting whatever
+/// happens to be mapped underneath.
+
+use memory::paging::{self, Page, PageIter, ActivePageTable};
+use memory::{PAGE_SIZE, FrameAllocator};
+use collections::vec::Vec;
+
+/// A mapped kernel stack. The stack grows downwards from `top` (which
+/// is exclusive) towards `bottom`.
+#[derive(Debug)]
+pub struct Stack {
+    top: usize,
+    bottom: usize,
+}
+
+impl Stack {
+    fn new(top: usize, bottom: usize) -> Stack {
+        assert!(top > bottom);
+        Stack {
+            top: top,
+            bottom: bottom,
+        }
+    }
+
+    /// The (exclusive) upper end of the stack, that is the initial
+    /// stack pointer.
+    pub fn top(&self) -> usize {
+        self.top
+    }
+
+    /// The lowest usable address of the stack.
+    pub fn bottom(&self) -> usize {
+        self.bottom
+    }
+
+    /// Size of the stack in pages.
+    fn size_in_pages(&self) -> usize {
+        (self.top - self.bottom) / PAGE_SIZE
+    }
+}
+
+/// Allocates stacks linearly from a range of pages. Freed stacks are
+/// kept mapped and are handed out again to the next request of the same
+/// size.
+pub struct StackAllocator {
+    /// Pages not yet used by any stack
+    range: PageIter,
+    /// Previously freed stacks, still mapped
+    recycled: Vec<Stack>,
+}
+
+impl StackAllocator {
+    pub fn new(page_range: PageIter) -> StackAllocator {
+        StackAllocator {
+            range: page_range,
+            recycled: Vec::new(),
+        }
+    }
+
+    /// Allocate and map a stack of `size_in_pages` pages, plus a guard
+    /// page. Returns `None` if the stack area is exhausted.
+    pub fn alloc_stack<A>(&mut self,
+                          active_table: &mut ActivePageTable,
+                          frame_allocator: &mut A,
+                          size_in_pages: usize) -> Option<Stack>
+        where A: FrameAllocator
+    {
+        if size_in_pages == 0 {
+            return None;
+        }
+
+        // Prefer re-using an old stack of the right size
+        if let Some(index) = self.recycled.iter()
+            .position(|s| s.size_in_pages() == size_in_pages) {
+            return Some(self.recycled.swap_remove(index));
+        }
+
+        // Clone the range, since we only want to change it on success
+        let mut range = self.range.clone();
+
+        // Try to allocate the stack pages and a guard page
+        let guard_page = range.next();
+        let stack_start = range.next();
+        let stack_end = if size_in_pages == 1 {
+            stack_start
+        } else {
+            // Index starts at 0 and we have already allocated the start page
+            range.nth(size_in_pages - 2)
+        };
+
+        match (guard_page, stack_start, stack_end) {
+            (Some(_), Some(start), Some(end)) => {
+                // Success! Write back the updated range
+                self.range = range;
+
+                // Map the stack pages to physical frames. The guard
+                // page is deliberately left unmapped.
+                for page in Page::range_inclusive(start, end) {
+                    active_table.map(page,
+                                     paging::WRITABLE | paging::NO_EXECUTE,
+                                     frame_allocator);
+                }
+
+                let top_of_stack = end.start_address() + PAGE_SIZE;
+                Some(Stack::new(top_of_stack, start.start_address()))
+            }
+            _ => None, // not enough pages left
+        }
+    }
+
+    /// Return a stack to the allocator. Its pages stay mapped.
+    pub fn free_stack(&mut self, stack: Stack) {
+        self.recycled.push(stack);
+    }
+}
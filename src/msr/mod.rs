@@ -8,6 +8,33 @@ const TSM_ADJUST : u32 = 0x0000_003B;
 const TSC_AUX : u32 = 0xC000_0103; 
 // Loacal APIC
 pub const APIC_BASE : u32 = 0x0000_001B;
+// Extended feature enable register (long mode, NX, SYSCALL)
+pub const EFER : u32 = 0xC000_0080;
+// SYSCALL/SYSRET segment selectors
+pub const STAR : u32 = 0xC000_0081;
+// SYSCALL target address (long mode)
+pub const LSTAR : u32 = 0xC000_0082;
+// RFLAGS bits to clear on SYSCALL
+pub const SFMASK : u32 = 0xC000_0084;
+
+// EFER flag: enable SYSCALL/SYSRET
+pub const EFER_SCE : u64 = 1 << 0;
+
+// Performance monitoring (see `cpuid::PerfMonitoring` for how many there are)
+// First general-purpose performance counter; the others follow
+pub const IA32_PMC0 : u32 = 0x0000_00C1;
+// First event select register, for IA32_PMC0; the others follow
+pub const IA32_PERFEVTSEL0 : u32 = 0x0000_0186;
+// First fixed-function counter (instructions retired); the others follow
+pub const IA32_FIXED_CTR0 : u32 = 0x0000_0309;
+// Enables for the fixed-function counters, four bits each
+pub const IA32_FIXED_CTR_CTRL : u32 = 0x0000_038D;
+// Which counters have overflowed (version 2 and up)
+pub const IA32_PERF_GLOBAL_STATUS : u32 = 0x0000_038E;
+// Enables for all counters: general-purpose from bit 0, fixed from bit 32
+pub const IA32_PERF_GLOBAL_CTRL : u32 = 0x0000_038F;
+// Write bits of IA32_PERF_GLOBAL_STATUS here to clear them
+pub const IA32_PERF_GLOBAL_OVF_CTRL : u32 = 0x0000_0390;
 
 
 
@@ -0,0 +1,171 @@
+//! # Panics
+//!
+//! When the kernel panics, `panic()` stops everything else, and shows
+//! what went wrong on the screen and the serial port: the message, the
+//! control registers and a backtrace, with the functions looked up in
+//! the kernel's symbol table. Then it halts, or reboots if asked to on
+//! the kernel command line:
+//!
+//! ```
+//! multiboot2 /boot/kernel.bin panic=10
+//! ```
+//!
+//! reboots ten seconds after a panic.
+//!
+//! The backtrace follows the chain of saved frame pointers, so the
+//! kernel must be built with frame pointers (see the target file).
+
+use core::fmt::{self, Write};
+use core::str::FromStr;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use x86::controlregs::{cr0, cr2, cr3, cr4};
+use x86::io::outb;
+
+use io;
+use io::serial::SerialWriter;
+use irq;
+use ksyms::{self, Demangle};
+use memory;
+use vga_buffer::{self, Writer};
+
+/// Frames to show at most
+const MAX_FRAMES: usize = 32;
+
+/// Set by the first panic
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
+/// Seconds to wait before rebooting after a panic; 0 means never
+static REBOOT_SECONDS: AtomicUsize = AtomicUsize::new(0);
+
+/// White on red, from the top left corner of a blank screen
+const PANIC_SCREEN: &'static str = "\x1b[97;41m\x1b[2J\x1b[H";
+
+/// Writes to both the screen and the serial port.
+struct PanicWriter {
+    screen: Writer,
+}
+
+impl fmt::Write for PanicWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.screen.write_str(s);
+        SerialWriter.write_str(s)
+    }
+}
+
+/// Apply the `panic=SECONDS` setting on the kernel command line.
+pub fn configure_from_command_line(command_line: &str) {
+    for word in command_line.split_whitespace() {
+        if word.starts_with("panic=") {
+            match usize::from_str(&word["panic=".len()..]) {
+                Ok(seconds) => REBOOT_SECONDS.store(seconds, Ordering::Relaxed),
+                Err(_) => warn!("bad setting on the command line: {}", word),
+            }
+        }
+    }
+}
+
+/// Show the panic `message`, from `line` of `file`, and stop.
+pub fn panic(message: fmt::Arguments, file: &str, line: u32) -> ! {
+    let rflags = unsafe { irq::save_and_disable() };
+    if PANICKING.swap(true, Ordering::SeqCst) {
+        // Panicked while panicking; the screen can't be trusted anymore
+        let _ = write!(SerialWriter, "\nPANIC while panicking in {} at line {}\n",
+                       file, line);
+        halt();
+    }
+    io::stop_other_cpus();
+
+    let mut out = PanicWriter { screen: unsafe { vga_buffer::panic_writer() } };
+    out.screen.write_str(PANIC_SCREEN);
+    let _ = write!(out, "\nPANIC in {} at line {}:\n    {}\n\n", file, line, message);
+    let _ = registers(&mut out, rflags);
+    let _ = backtrace(&mut out);
+
+    let seconds = REBOOT_SECONDS.load(Ordering::Relaxed);
+    if seconds == 0 {
+        halt();
+    }
+    let _ = write!(out, "\nRebooting in {} seconds", seconds);
+    for _ in 0..seconds {
+        delay_ms(1000);
+        let _ = out.write_str(".");
+    }
+    reboot();
+}
+
+fn registers(out: &mut PanicWriter, rflags: usize) -> fmt::Result {
+    unsafe {
+        try!(write!(out, "CR0 {:016x}  CR2 {:016x}  CR3 {:016x}\n", cr0(), cr2(), cr3()));
+        write!(out, "CR4 {:016x}  RFLAGS {:016x}\n\n", cr4(), rflags)
+    }
+}
+
+/// Follow the saved frame pointers up the stack, and show the return
+/// address of every frame.
+fn backtrace(out: &mut PanicWriter) -> fmt::Result {
+    try!(out.write_str("Backtrace:\n"));
+
+    let mut frame: usize;
+    unsafe { asm!("mov $0, rbp" : "=r"(frame) ::: "intel") };
+
+    for _ in 0..MAX_FRAMES {
+        // The saved frame pointer, then the return address
+        if frame == 0 || frame % 8 != 0 || !memory::kernel_readable(frame, 16) {
+            break;
+        }
+        let (next, return_address) = unsafe {
+            (*(frame as *const usize), *((frame + 8) as *const usize))
+        };
+        if return_address == 0 {
+            break;
+        }
+
+        // Look up the call, which comes just before the return address
+        try!(match ksyms::lookup(return_address - 1) {
+            Some((name, offset)) => write!(out, "  {:016x}  {}+{:#x}\n", return_address,
+                                           Demangle(name), offset + 1),
+            None => write!(out, "  {:016x}  ?\n", return_address),
+        });
+
+        // The callers' frames are further up the stack
+        if next <= frame {
+            break;
+        }
+        frame = next;
+    }
+    Ok(())
+}
+
+/// Busy-wait about `ms` milliseconds. The timer interrupt is off, but
+/// every write to the POST code port 0x80 takes about a microsecond.
+fn delay_ms(ms: usize) {
+    for _ in 0..ms * 1000 {
+        unsafe { outb(0x80, 0) };
+    }
+}
+
+/// Stop for good. Interrupts are off, so nothing wakes us up.
+fn halt() -> ! {
+    loop {
+        unsafe { asm!("hlt" :::: "volatile") };
+    }
+}
+
+/// Reset the machine through the keyboard controller, or failing that,
+/// with a triple fault.
+fn reboot() -> ! {
+    io::ps2::reset_cpu();
+    delay_ms(100);
+
+    // An exception with an empty IDT takes the CPU down
+    let empty_idt = [0u16; 5];
+    unsafe {
+        asm!("lidt [$0]\n\t\
+              int3"
+             :
+             : "r"(&empty_idt)
+             : "memory"
+             : "intel", "volatile");
+    }
+    halt();
+}
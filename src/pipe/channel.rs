@@ -0,0 +1,218 @@
+//! Byte pipes between threads.
+//!
+//! ```
+//! let (reader, writer) = pipe::channel();
+//! writer.write(b"hello").unwrap();
+//! drop(writer);
+//!
+//! let mut buf = [0; 16];
+//! let n = reader.read(&mut buf).unwrap();   // 5
+//! let n = reader.read(&mut buf).unwrap();   // 0: end of file
+//! ```
+//!
+//! Both ends can be cloned. Reading returns end of file (`Ok(0)`) once
+//! every writer is gone and the pipe is empty, and writing fails with
+//! `PipeError::BrokenPipe` once every reader is gone.
+
+use alloc::arc::Arc;
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use sync::{Mutex, WaitQueue};
+use super::Buffer;
+
+/// Number of bytes a pipe holds before writers have to wait.
+pub const PIPE_SIZE: usize = 512;
+
+/// Why a pipe operation failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PipeError {
+    /// The operation would have had to wait
+    WouldBlock,
+    /// Every reader is gone, nobody will see what is written
+    BrokenPipe,
+}
+
+/// The state shared by both ends of a pipe.
+struct Pipe {
+    buffer: Buffer<u8>,
+    /// Number of live `PipeReader`s
+    readers: AtomicUsize,
+    /// Number of live `PipeWriter`s
+    writers: AtomicUsize,
+    /// The buffer is single-producer, single-consumer; these make sure
+    /// only one reader and one writer use it at a time.
+    read_lock: Mutex<()>,
+    write_lock: Mutex<()>,
+    /// Readers waiting for data (or end of file)
+    read_waiters: WaitQueue,
+    /// Writers waiting for room (or a broken pipe)
+    write_waiters: WaitQueue,
+}
+
+/// The reading end of a pipe.
+pub struct PipeReader {
+    pipe: Arc<Pipe>,
+}
+
+/// The writing end of a pipe.
+pub struct PipeWriter {
+    pipe: Arc<Pipe>,
+}
+
+/// Create a new pipe, returning its two ends.
+pub fn channel() -> (PipeReader, PipeWriter) {
+    let pipe = Arc::new(Pipe {
+        buffer: Buffer::new(PIPE_SIZE),
+        readers: AtomicUsize::new(1),
+        writers: AtomicUsize::new(1),
+        read_lock: Mutex::new(()),
+        write_lock: Mutex::new(()),
+        read_waiters: WaitQueue::new(),
+        write_waiters: WaitQueue::new(),
+    });
+
+    (PipeReader { pipe: pipe.clone() }, PipeWriter { pipe: pipe })
+}
+
+impl PipeReader {
+    /// Read at most `buf.len()` bytes without waiting. Returns `Ok(0)`
+    /// at end of file, and `PipeError::WouldBlock` if the pipe is empty
+    /// but still has writers.
+    pub fn try_read(&self, buf: &mut [u8]) -> Result<usize, PipeError> {
+        // Another reader holding the lock may be waiting in `read()`
+        let _guard = match self.pipe.read_lock.try_lock() {
+            Some(guard) => guard,
+            None => return Err(PipeError::WouldBlock),
+        };
+        self.read_locked(buf)
+    }
+
+    /// Read at most `buf.len()` bytes, waiting until there is at least
+    /// one (or end of file, when `Ok(0)` is returned).
+    pub fn read(&self, buf: &mut [u8]) -> Result<usize, PipeError> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let _guard = self.pipe.read_lock.lock();
+        let pipe = &self.pipe;
+        pipe.read_waiters.wait_until(|| {
+            !pipe.buffer.is_empty() || pipe.writers.load(Ordering::SeqCst) == 0
+        });
+        self.read_locked(buf)
+    }
+
+    /// Read whatever is there. The read lock must be held.
+    fn read_locked(&self, buf: &mut [u8]) -> Result<usize, PipeError> {
+        // Check for writers first: anything written before the last one
+        // left is in the buffer by then.
+        let no_writers = self.pipe.writers.load(Ordering::SeqCst) == 0;
+
+        let mut count = 0;
+        while count < buf.len() {
+            match self.pipe.buffer.try_pop() {
+                Ok(byte) => buf[count] = byte,
+                Err(_) => break,
+            }
+            count += 1;
+        }
+
+        if count > 0 {
+            self.pipe.write_waiters.wake_all();
+            Ok(count)
+        } else if no_writers || buf.is_empty() {
+            Ok(0)
+        } else {
+            Err(PipeError::WouldBlock)
+        }
+    }
+}
+
+impl PipeWriter {
+    /// Write as much of `buf` as fits without waiting, and return how
+    /// much that was. Fails with `PipeError::WouldBlock` if nothing fit.
+    pub fn try_write(&self, buf: &[u8]) -> Result<usize, PipeError> {
+        let _guard = match self.pipe.write_lock.try_lock() {
+            Some(guard) => guard,
+            None => return Err(PipeError::WouldBlock),
+        };
+        match self.write_locked(buf) {
+            Ok(0) if !buf.is_empty() => Err(PipeError::WouldBlock),
+            result => result,
+        }
+    }
+
+    /// Write all of `buf`, waiting for room as needed.
+    pub fn write(&self, buf: &[u8]) -> Result<usize, PipeError> {
+        let _guard = self.pipe.write_lock.lock();
+        let pipe = &self.pipe;
+
+        let mut written = 0;
+        while written < buf.len() {
+            pipe.write_waiters.wait_until(|| {
+                !pipe.buffer.is_full() || pipe.readers.load(Ordering::SeqCst) == 0
+            });
+            written += try!(self.write_locked(&buf[written..]));
+        }
+        Ok(written)
+    }
+
+    /// Write whatever fits. The write lock must be held.
+    fn write_locked(&self, buf: &[u8]) -> Result<usize, PipeError> {
+        if self.pipe.readers.load(Ordering::SeqCst) == 0 {
+            return Err(PipeError::BrokenPipe);
+        }
+
+        let mut count = 0;
+        for &byte in buf {
+            if self.pipe.buffer.try_push(byte).is_err() {
+                break;
+            }
+            count += 1;
+        }
+
+        if count > 0 {
+            self.pipe.read_waiters.wake_all();
+        }
+        Ok(count)
+    }
+}
+
+impl fmt::Write for PipeWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write(s.as_bytes()).map(|_| ()).map_err(|_| fmt::Error)
+    }
+}
+
+impl Clone for PipeReader {
+    fn clone(&self) -> PipeReader {
+        self.pipe.readers.fetch_add(1, Ordering::SeqCst);
+        PipeReader { pipe: self.pipe.clone() }
+    }
+}
+
+impl Clone for PipeWriter {
+    fn clone(&self) -> PipeWriter {
+        self.pipe.writers.fetch_add(1, Ordering::SeqCst);
+        PipeWriter { pipe: self.pipe.clone() }
+    }
+}
+
+impl Drop for PipeReader {
+    fn drop(&mut self) {
+        if self.pipe.readers.fetch_sub(1, Ordering::SeqCst) == 1 {
+            // Writers waiting for room will now get a broken pipe
+            self.pipe.write_waiters.wake_all();
+        }
+    }
+}
+
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        if self.pipe.writers.fetch_sub(1, Ordering::SeqCst) == 1 {
+            // Readers waiting for data will now get end of file
+            self.pipe.read_waiters.wake_all();
+        }
+    }
+}
@@ -1,279 +1,323 @@
-/// Box implemenatations an memory stuffs
-use alloc::boxed::Box;
-
-/// Content in buffer
-pub type Content = u8;
-
-/// BUFFER_SIZE is set to a low number
-const BUFFER_SIZE: usize = 24;
-/// START_POINTER is kind of a test thing since usize would not work as intended
-const START_POINT: usize = 0;
-
-/// A circular buffer with fixed size [5]
-/// Contains:
-/// - Read pointer (rp), the current location of the reader
-/// - Write pointer (wp), the current location of the writer
-/// - Buffer (buf), list of BUFFER_SIZE, containing elements of size u8
-
-#[cfg(not(test))]
-pub struct Buffer {
-    wp: usize,
-    rp: usize,
-    buf: [u8; BUFFER_SIZE],
+//! Pipes, and the circular buffer they are built on.
+//!
+//! `channel()` creates a blocking byte pipe between threads; see the
+//! `channel` module.
+//!
+//! `Buffer` is a circular buffer, safe to share between one producer
+//! and one consumer without a lock. The producer may be an interrupt
+//! handler.
+//!
+//! The read (`head`) and write (`tail`) positions only ever increase
+//! (wrapping around at `usize::MAX`), and index the storage modulo the
+//! capacity, so `tail - head` is always the number of elements in the
+//! buffer.
+//!
+//! ```
+//! let buffer = Buffer::new(16);
+//! buffer.try_push(42).unwrap();
+//! assert_eq!(buffer.try_pop(), Ok(42));
+//! assert_eq!(buffer.try_pop(), Err(Error::Empty));
+//! ```
+
+use collections::vec::Vec;
+use core::mem;
+use core::ptr;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+mod channel;
+
+pub use self::channel::{channel, PipeReader, PipeWriter, PipeError, PIPE_SIZE};
+
+/// What `try_push` does when the buffer is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    /// Refuse the new element
+    Reject,
+    /// Throw away the oldest element to make room for the new one
+    OverwriteOldest,
 }
 
-#[cfg(test)]
-pub struct Buffer {
-    pub wp: usize,
-    pub rp: usize,
-    pub buf: [u8; BUFFER_SIZE],
+/// Why a push or pop failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// No room for another element
+    Full,
+    /// Nothing to read
+    Empty,
 }
 
-/// Implementation of buffer
-impl Buffer{
-    // Allocates a new Buffer with two pointers, everything set to 0.
-    pub fn new()-> Box<Buffer> {
-        Box::new(Buffer{
-            wp: 0,
-            rp: 0,
-            buf: [0; BUFFER_SIZE]
-        })
+/// A single-producer, single-consumer circular buffer of `T`s.
+///
+/// Only one thread (or interrupt handler) may push, and only one may
+/// pop, `peek` or `clear`, at any one time.
+pub struct Buffer<T: Copy> {
+    /// Storage for `capacity` elements
+    buf: *mut T,
+    capacity: usize,
+    policy: Policy,
+    /// Position of the next element to read
+    head: AtomicUsize,
+    /// Position of the next element to write
+    tail: AtomicUsize,
+}
+
+unsafe impl<T: Copy + Send> Send for Buffer<T> {}
+unsafe impl<T: Copy + Send> Sync for Buffer<T> {}
+
+impl<T: Copy> Buffer<T> {
+    /// Create an empty buffer with room for `capacity` elements, which
+    /// rejects new elements when full.
+    pub fn new(capacity: usize) -> Buffer<T> {
+        Buffer::with_policy(capacity, Policy::Reject)
     }
-    
-    // Check if writer +1 is equal to reader, returns boolean  
-    pub fn is_full(&self) -> bool{
-         (self.wp+1) % BUFFER_SIZE == self.rp 
+
+    /// Create an empty buffer with room for `capacity` elements, using
+    /// `policy` when full.
+    pub fn with_policy(capacity: usize, policy: Policy) -> Buffer<T> {
+        assert!(capacity > 0, "buffer capacity must be non-zero");
+
+        let mut storage: Vec<T> = Vec::with_capacity(capacity);
+        let buf = storage.as_mut_ptr();
+        mem::forget(storage);
+
+        Buffer {
+            buf: buf,
+            capacity: capacity,
+            policy: policy,
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
     }
-    
-    // Check if writer and reader are at the same position,
-    // if so buffer should be empty.
-    pub fn is_empty(&self) -> bool{
-        self.rp == self.wp
+
+    /// The number of elements the buffer can hold.
+    pub fn capacity(&self) -> usize {
+        self.capacity
     }
 
-    // Steps writer one step forward, circle aspect included
-    pub fn step_wp(&mut self){
-        if self.wp == (BUFFER_SIZE -1) { //&& self.rp != START_POINT{
-            self.wp = START_POINT; 
-        } else {
-            self.wp += 1; 
-        }
+    /// The number of elements in the buffer right now.
+    pub fn len(&self) -> usize {
+        let head = self.head.load(Ordering::Acquire);
+        let tail = self.tail.load(Ordering::Acquire);
+        tail.wrapping_sub(head)
     }
 
-    // Steps reader one step forward, circle aspect included 
-    pub fn step_rp(&mut self){
-        if self.rp == (BUFFER_SIZE -1) { // && self.wp != START_POINT{
-            self.rp = START_POINT; 
-        } else {
-            self.rp += 1; 
-        }
+    /// True if there is nothing to read.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// True if there is no room for another element.
+    pub fn is_full(&self) -> bool {
+        self.len() >= self.capacity
     }
-    
-    // Check if full, write Content to current writerpointer address 
-    // then steps once. 
-    pub fn write(&mut self, insert: Content){
-        assert!(!self.is_full(), "Buffer is full!");
-        self.buf[self.wp] = insert;
-        self.step_wp(); 
+
+    /// The storage slot for `position`.
+    fn slot(&self, position: usize) -> *mut T {
+        unsafe { self.buf.offset((position % self.capacity) as isize) }
     }
 
-    // Check if empty, read Content from current reader pointer
-    // then steps once
-    pub fn read(&mut self) -> Content {
-        assert!(!self.is_empty(), "Reading empty space!");
-        let content = self.buf[self.rp];
-        self.step_rp();
-        return content;
+    /// Add `item` at the end. Fails with `Error::Full` if there is no
+    /// room, unless the policy is to overwrite the oldest element.
+    /// Producer side only.
+    pub fn try_push(&self, item: T) -> Result<(), Error> {
+        let tail = self.tail.load(Ordering::Relaxed);
+
+        loop {
+            let head = self.head.load(Ordering::Acquire);
+            if tail.wrapping_sub(head) < self.capacity {
+                break;
+            }
+            if self.policy == Policy::Reject {
+                return Err(Error::Full);
+            }
+            // Drop the oldest element, unless the consumer beat us to it
+            self.head.compare_and_swap(head, head.wrapping_add(1),
+                                       Ordering::AcqRel);
+        }
+
+        unsafe { ptr::write_volatile(self.slot(tail), item) };
+        self.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Ok(())
     }
 
-//    pub fn unread(&mut self) -> bool {
-//        self.rp < self.wp
-//    }
-}
+    /// Remove and return the oldest element, or fail with
+    /// `Error::Empty`. Consumer side only.
+    pub fn try_pop(&self) -> Result<T, Error> {
+        loop {
+            let head = self.head.load(Ordering::Acquire);
+            let tail = self.tail.load(Ordering::Acquire);
+            if head == tail {
+                return Err(Error::Empty);
+            }
 
-/// Implements iterator for buffer, lets you process all unread data.
-impl Iterator for Buffer {
-    type Item = Content;
-    fn next(&mut self) -> Option<Content> {
-        if self.is_empty() {
-            None
-        } else {
-            Some(self.read())
+            let item = unsafe { ptr::read_volatile(self.slot(head)) };
+
+            // If the producer moved `head` meanwhile, the slot may have
+            // been overwritten: try again.
+            if self.head.compare_and_swap(head, head.wrapping_add(1),
+                                          Ordering::AcqRel) == head {
+                return Ok(item);
+            }
         }
     }
-}
 
+    /// Return the oldest element without removing it. Consumer side only.
+    pub fn peek(&self) -> Option<T> {
+        loop {
+            let head = self.head.load(Ordering::Acquire);
+            let tail = self.tail.load(Ordering::Acquire);
+            if head == tail {
+                return None;
+            }
 
-#[test]
-/// Test pointers set to zero
-fn buffer_zero_ptr(){
-    let mut b = Buffer::new();
-    assert!(b.wp == 0 && b.rp == 0);
+            let item = unsafe { ptr::read_volatile(self.slot(head)) };
+
+            if self.head.load(Ordering::Acquire) == head {
+                return Some(item);
+            }
+        }
+    }
+
+    /// Throw away everything in the buffer. Consumer side only.
+    pub fn clear(&self) {
+        while self.try_pop().is_ok() {}
+    }
+
+    /// Pop elements until the buffer is empty.
+    pub fn drain(&self) -> Drain<T> {
+        Drain { buffer: self }
+    }
 }
 
-#[test]
-/// Add 42, read and expect 42.
-fn buffer_add_read(){
-    let mut b = Buffer::new();
-    b.write(42);
-    let first = b.read();
-    assert!(first == 42);
+impl<T: Copy> Drop for Buffer<T> {
+    fn drop(&mut self) {
+        // `T` is `Copy`, so there is nothing to drop but the storage
+        unsafe { Vec::from_raw_parts(self.buf, 0, self.capacity) };
+    }
 }
 
-#[test]
-/// Test pointers after one write and read
-fn buffer_pointers_after_one_read(){
-    let mut b = Buffer::new();
-    b.write(1);
-    let first = b.read();
-    assert!(b.wp == 1 && b.rp == 1);
+/// Iterator popping elements off a `Buffer` until it is empty.
+pub struct Drain<'a, T: 'a + Copy> {
+    buffer: &'a Buffer<T>,
 }
 
-#[test]
-#[should_panic]
-/// Test to read first, should panic
-fn buffer_read_first(){
-    let mut b = Buffer::new();
-    let first = b.read(); // Should result in a buffer_empty panic
+impl<'a, T: Copy> Iterator for Drain<'a, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.buffer.try_pop().ok()
+    }
 }
 
+
 #[test]
-/// Test is_empty
+/// A new buffer is empty
 fn buffer_is_empty(){
-    let mut b = Buffer::new();
-    assert!(b.is_empty() == true);
+    let b: Buffer<u8> = Buffer::new(5);
+    assert!(b.is_empty());
+    assert_eq!(b.len(), 0);
+    assert_eq!(b.capacity(), 5);
 }
 
 #[test]
-#[should_panic]
-/// Add 42, read twice, should fail if working properly
-fn buffer_add_once_read_twice(){
-    let mut b = Buffer::new();
-    b.write(42);
-    let first = b.read();
-    let second = b.read();  
+/// Add 42, read and expect 42.
+fn buffer_add_read(){
+    let b = Buffer::new(5);
+    b.try_push(42).unwrap();
+    assert_eq!(b.try_pop(), Ok(42));
 }
 
 #[test]
-/// Add five times, should fail if working properly.
-fn buffer_add_five_times(){
-    let mut b = Buffer::new();
-    b.write(1); //wp == 0, rp == 0 
-    b.write(2); //wp == 1, rp == 0
-    b.write(3); //wp == 2, rp == 0
-    b.write(4); //wp == 3, rp == 0 
-    b.write(5); //wp == 4, rp == 0
-    b.write(6); //wp == 0, rp == 0: Should fail here since 4+1%5=0=rp=buffer full
+/// Reading an empty buffer fails
+fn buffer_read_first(){
+    let b: Buffer<u8> = Buffer::new(5);
+    assert_eq!(b.try_pop(), Err(Error::Empty));
 }
 
 #[test]
-/// Add four times, read four times
-fn buffer_read_write_four_times(){
-    let mut b = Buffer::new();
-    b.write(1);
-    b.write(2);
-    b.write(3);
-    b.write(4);
-    let first  = b.read();
-    let second = b.read();
-    let third  = b.read();
-    let four   = b.read();
-    assert!(four == 4);
+/// Add 42, read twice, the second read fails
+fn buffer_add_once_read_twice(){
+    let b = Buffer::new(5);
+    b.try_push(42).unwrap();
+    assert_eq!(b.try_pop(), Ok(42));
+    assert_eq!(b.try_pop(), Err(Error::Empty));
 }
 
 #[test]
-/// Add five times, check if buffer is full
-fn buffer_write_four_times(){
-    let mut b = Buffer::new();
-    b.write(1); // wp should be 1
-    b.write(2); // wp should be 2
-    b.write(3); // wp should be 3
-    b.write(4); // wp should be 4
-    assert!(b.wp == 4);
+/// Add six times to a buffer of five, the last one is rejected
+fn buffer_add_six_times(){
+    let b = Buffer::new(5);
+    for i in 1..6 {
+        b.try_push(i).unwrap();
+    }
+    assert!(b.is_full());
+    assert_eq!(b.try_push(6), Err(Error::Full));
+    assert_eq!(b.try_pop(), Ok(1));
 }
 
 #[test]
-/// Test pointer stepping with four inserts and reads
-fn buffer_pinters_after_four_reads_and_writes(){
-    let mut b = Buffer::new();
-    b.write(1);
-    b.write(2);
-    b.write(3);
-    b.write(4);
-    let first  = b.read();
-    let second = b.read();
-    let third  = b.read();
-    let four   = b.read();
-    assert!(b.wp == 4 && b.rp == 4);
+/// Add six times to an overwriting buffer of five, the first one is lost
+fn buffer_overwrite_oldest(){
+    let b = Buffer::with_policy(5, Policy::OverwriteOldest);
+    for i in 1..7 {
+        b.try_push(i).unwrap();
+    }
+    assert_eq!(b.len(), 5);
+    let contents: Vec<i32> = b.drain().collect();
+    assert_eq!(contents, vec![2, 3, 4, 5, 6]);
 }
 
 #[test]
-/// Read and write six times in 'random' order. 
-/// Testing if circularity is correct.
-fn buffer_read_write_random_one_circle(){
-    let mut b = Buffer::new();
-    b.write(1);
-    let first = b.read();
-    b.write(2);
-    let second = b.read();
-    b.write(3);
-    b.write(4);
-    let third = b.read();
-    let four  = b.read();
-    b.write(5);
-    b.write(6);
-    let five = b.read();
-    let six = b.read();
-    assert!(first == 1 && second == 2 && third == 3 && four == 4 && five == 5 && six == 6);
+/// Peeking doesn't remove anything
+fn buffer_peek(){
+    let b = Buffer::new(5);
+    assert_eq!(b.peek(), None);
+    b.try_push(1).unwrap();
+    b.try_push(2).unwrap();
+    assert_eq!(b.peek(), Some(1));
+    assert_eq!(b.len(), 2);
+    assert_eq!(b.try_pop(), Ok(1));
+    assert_eq!(b.peek(), Some(2));
 }
 
 #[test]
-/// Test pointers after six read writes in 'random' order. 
-/// Testing if circularity works as intended
-fn buffer_pointers_after_six_read_write(){
-    let mut b = Buffer::new();
-    b.write(1); // wp == 1
-    let first = b.read(); // rp == 1
-    b.write(2); // wp == 2
-    let second = b.read(); // wp == 2
-    b.write(3); // wp == 3
-    b.write(4); // wp == 4
-    let third = b.read(); // rp == 3
-    let four  = b.read(); // rp == 4
-    b.write(5); // wp == 0
-    b.write(6); // wp == 1
-    let five = b.read(); // rp == 0
-    let six = b.read(); //rp == 1
-    assert!(b.wp == b.wp % BUFFER_SIZE && b.rp == b.rp % BUFFER_SIZE);
+/// Add four times, read four times
+fn buffer_read_write_four_times(){
+    let b = Buffer::new(5);
+    for i in 1..5 {
+        b.try_push(i).unwrap();
+    }
+    assert_eq!(b.len(), 4);
+    for i in 1..5 {
+        assert_eq!(b.try_pop(), Ok(i));
+    }
+    assert!(b.is_empty());
 }
 
 #[test]
-/// Read and write a shit-ton of times
+/// Read and write in 'random' order, going around the buffer a few
+/// times. Testing if circularity is correct.
 fn buffer_write_read_many_times(){
-    let mut b = Buffer::new();
-    b.write(1); // wp == 1
-    let first = b.read(); // rp == 1
-    b.write(2); // wp == 2
-    let second = b.read(); // wp == 2
-    b.write(3); // wp == 3
-    b.write(4); // wp == 4
-    let third = b.read(); // rp == 3
-    let four  = b.read(); // rp == 4
-    b.write(5); // wp == 0
-    b.write(6); // wp == 1
-    let five = b.read(); // rp == 0
-    let six =  b.read(); // rp == 1
-    b.write(7); // wp == 2
-    let seven = b.read(); // rp == 2
-    b.write(8); // wp == 3
-    let eight = b.read(); // wp == 3
-    b.write(9); // wp == 4
-    b.write(10); // wp == 0
-    let nine = b.read(); // rp == 4
-    let ten  = b.read(); // rp == 0
-    b.write(11); // wp == 1
-    b.write(12); // wp == 2
-    let eleven = b.read(); // rp == 1
-    let twelve = b.read(); //rp == 2
-    assert!(b.wp == b.wp % BUFFER_SIZE && b.rp == b.rp % BUFFER_SIZE && twelve == 12);
+    let b = Buffer::new(5);
+    let mut next_write = 0;
+    let mut next_read = 0;
+    for round in 0..20 {
+        for _ in 0..(round % 4 + 1) {
+            b.try_push(next_write).unwrap();
+            next_write += 1;
+        }
+        while let Ok(item) = b.try_pop() {
+            assert_eq!(item, next_read);
+            next_read += 1;
+        }
+    }
+    assert_eq!(next_read, next_write);
+}
+
+#[test]
+/// Works with other types than bytes
+fn buffer_of_tuples(){
+    let b = Buffer::new(2);
+    b.try_push((1u32, 'a')).unwrap();
+    assert_eq!(b.try_pop(), Ok((1, 'a')));
 }
@@ -0,0 +1,374 @@
+//! # Performance counters
+//!
+//! A driver for the architectural performance monitoring unit (PMU), as
+//! described by CPUID option `BASIC_PEMO`: a few general-purpose
+//! counters that can count any of the architectural events, and from
+//! version 2 on, fixed-function counters for instructions, cycles and
+//! reference cycles.
+//!
+//! `start()` sets up counters for some events, and `Counters::stop()`
+//! reads them; the shell's `perf stat` does that around a command.
+//! `start_sampling()` instead has a counter interrupt the CPU, through
+//! the local APIC's performance counter entry, every so many events,
+//! and takes a profiler sample each time (see the `profile` module).
+//!
+//! The counters belong to the CPU that set them up, and count all it
+//! does, other threads and interrupt handlers included. There is only
+//! one set, so only one user at a time.
+
+use collections::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use arch::x86_64::cpuid::{CPUID, PerfMonitoring};
+use io;
+use irq;
+use msr::{self, read_msr, write_msr};
+use profile;
+
+/// Interrupt vector of counter overflows (PMIs)
+const PMI_VECTOR: u8 = 0x83;
+
+// Event select bits, besides the event and unit mask
+/// Count in user mode
+const EVTSEL_USR: u32 = 1 << 16;
+/// Count in kernel mode
+const EVTSEL_OS: u32 = 1 << 17;
+/// Interrupt on overflow
+const EVTSEL_INT: u32 = 1 << 20;
+const EVTSEL_EN: u32 = 1 << 22;
+
+// Fixed counter control bits, for every counter
+const FIXED_OS: u64 = 1 << 0;
+const FIXED_USR: u64 = 1 << 1;
+/// Bits per counter in IA32_FIXED_CTR_CTRL
+const FIXED_CTRL_BITS: u8 = 4;
+
+/// Fixed counters start at this bit of the global registers
+const GLOBAL_FIXED_SHIFT: u8 = 32;
+
+/// Longest sampling period: writes to IA32_PMCx are sign-extended from
+/// 32 bits.
+pub const MAX_PERIOD: usize = 0x7FFF_FFFF;
+
+/// Architectural events, numbered like the bits in CPUID
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    Cycles = 0,
+    Instructions = 1,
+    ReferenceCycles = 2,
+    CacheReferences = 3,
+    CacheMisses = 4,
+    Branches = 5,
+    BranchMisses = 6,
+}
+
+pub const EVENTS: [Event; 7] = [
+    Event::Cycles, Event::Instructions, Event::ReferenceCycles, Event::CacheReferences,
+    Event::CacheMisses, Event::Branches, Event::BranchMisses,
+];
+
+impl Event {
+    /// Event select and unit mask
+    fn code(&self) -> (u8, u8) {
+        match *self {
+            Event::Cycles => (0x3C, 0x00),
+            Event::Instructions => (0xC0, 0x00),
+            Event::ReferenceCycles => (0x3C, 0x01),
+            Event::CacheReferences => (0x2E, 0x4F),
+            Event::CacheMisses => (0x2E, 0x41),
+            Event::Branches => (0xC4, 0x00),
+            Event::BranchMisses => (0xC5, 0x00),
+        }
+    }
+
+    /// The fixed-function counter that counts this, if any
+    fn fixed_counter(&self) -> Option<u8> {
+        match *self {
+            Event::Instructions => Some(0),
+            Event::Cycles => Some(1),
+            Event::ReferenceCycles => Some(2),
+            _ => None,
+        }
+    }
+
+    /// Name of the event, as in Linux's `perf`
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Event::Cycles => "cycles",
+            Event::Instructions => "instructions",
+            Event::ReferenceCycles => "ref-cycles",
+            Event::CacheReferences => "cache-references",
+            Event::CacheMisses => "cache-misses",
+            Event::Branches => "branches",
+            Event::BranchMisses => "branch-misses",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Event> {
+        EVENTS.iter().cloned().find(|event| event.name() == name)
+    }
+}
+
+/// Why counters couldn't be set up
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// There is no architectural PMU
+    NoPmu,
+    /// Somebody else is using the counters
+    Busy,
+    /// The CPU can't count this event, or sample on it
+    Unsupported(Event),
+    /// More events than counters
+    NoCounters,
+}
+
+/// Set once by `init()`, before there are other threads to look.
+static mut PMU: Option<PerfMonitoring> = None;
+
+static IN_USE: AtomicBool = AtomicBool::new(false);
+
+/// Events between samples, while sampling; otherwise 0
+static SAMPLING_PERIOD: AtomicUsize = AtomicUsize::new(0);
+
+/// Find out what the PMU has, and route its overflow interrupts. Must
+/// be called after `io::install_io()`. Returns `None` if there is no
+/// PMU, and counters can't be used.
+pub fn init() -> Option<PerfMonitoring> {
+    assert_has_not_been_called!("pmu::init must be called only once");
+
+    let pmu = match CPUID::new().perf_monitoring() {
+        Some(pmu) if pmu.counters > 0 => pmu,
+        _ => return None,
+    };
+    unsafe { PMU = Some(pmu); }
+
+    irq::set_handler(PMI_VECTOR as usize, overflow);
+    io::route_perf_interrupt(PMI_VECTOR);
+    Some(pmu)
+}
+
+/// What the PMU has, if there is one
+pub fn info() -> Option<PerfMonitoring> {
+    unsafe { PMU }
+}
+
+/// True if `event` can be counted
+pub fn supported(event: Event) -> bool {
+    info().map_or(false, |pmu| pmu.has_event(event as u8))
+}
+
+/// A counter of the PMU
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Counter {
+    General(u8),
+    Fixed(u8),
+}
+
+impl Counter {
+    /// Bit of the counter in the global registers
+    fn global_bit(&self) -> u64 {
+        match *self {
+            Counter::General(n) => 1 << n,
+            Counter::Fixed(n) => 1 << (GLOBAL_FIXED_SHIFT + n),
+        }
+    }
+
+    /// Zero the counter and have it count `event`, interrupting the CPU
+    /// on overflow if `interrupt` is set.
+    unsafe fn program(&self, event: Event, interrupt: bool) {
+        match *self {
+            Counter::General(n) => {
+                let (select, umask) = event.code();
+                let mut evtsel = select as u32 | (umask as u32) << 8
+                    | EVTSEL_USR | EVTSEL_OS | EVTSEL_EN;
+                if interrupt {
+                    evtsel |= EVTSEL_INT;
+                }
+                write_msr(msr::IA32_PMC0 + n as u32, 0, 0);
+                write_msr(msr::IA32_PERFEVTSEL0 + n as u32, 0, evtsel);
+            },
+            Counter::Fixed(n) => {
+                write_msr(msr::IA32_FIXED_CTR0 + n as u32, 0, 0);
+                let shift = FIXED_CTRL_BITS * n;
+                let ctrl = read_msr(msr::IA32_FIXED_CTR_CTRL) | (FIXED_OS | FIXED_USR) << shift;
+                write_msr(msr::IA32_FIXED_CTR_CTRL, (ctrl >> 32) as u32, ctrl as u32);
+            },
+        }
+    }
+
+    unsafe fn disable(&self) {
+        match *self {
+            Counter::General(n) => write_msr(msr::IA32_PERFEVTSEL0 + n as u32, 0, 0),
+            Counter::Fixed(n) => {
+                let shift = FIXED_CTRL_BITS * n;
+                let ctrl = read_msr(msr::IA32_FIXED_CTR_CTRL) & !(0xF << shift);
+                write_msr(msr::IA32_FIXED_CTR_CTRL, (ctrl >> 32) as u32, ctrl as u32);
+            },
+        }
+    }
+
+    unsafe fn read(&self, pmu: &PerfMonitoring) -> u64 {
+        let (register, width) = match *self {
+            Counter::General(n) => (msr::IA32_PMC0 + n as u32, pmu.counter_width),
+            Counter::Fixed(n) => (msr::IA32_FIXED_CTR0 + n as u32, pmu.fixed_width),
+        };
+        let value = read_msr(register);
+        if width >= 64 { value } else { value & ((1 << width) - 1) }
+    }
+}
+
+/// Turn the `counters` (global bits) on or off, on PMUs where there is
+/// a global switch.
+unsafe fn set_global(pmu: &PerfMonitoring, counters: u64, on: bool) {
+    if pmu.version < 2 {
+        return;
+    }
+    let ctrl = read_msr(msr::IA32_PERF_GLOBAL_CTRL);
+    let ctrl = if on { ctrl | counters } else { ctrl & !counters };
+    write_msr(msr::IA32_PERF_GLOBAL_CTRL, (ctrl >> 32) as u32, ctrl as u32);
+}
+
+/// Take the counters, for the caller to give back with `release()`.
+fn claim() -> Result<PerfMonitoring, Error> {
+    let pmu = try!(info().ok_or(Error::NoPmu));
+    if IN_USE.swap(true, Ordering::SeqCst) {
+        return Err(Error::Busy);
+    }
+    Ok(pmu)
+}
+
+fn release() {
+    IN_USE.store(false, Ordering::SeqCst);
+}
+
+/// A counter for every event, fixed-function counters first.
+fn assign(pmu: &PerfMonitoring, events: &[Event]) -> Result<Vec<(Event, Counter)>, Error> {
+    let mut counters: Vec<(Event, Counter)> = Vec::new();
+    let mut next_general = 0;
+    for &event in events {
+        if !pmu.has_event(event as u8) {
+            return Err(Error::Unsupported(event));
+        }
+        let counter = match event.fixed_counter() {
+            Some(n) if n < pmu.fixed_counters
+                && !counters.iter().any(|&(_, c)| c == Counter::Fixed(n)) => Counter::Fixed(n),
+            _ if next_general < pmu.counters => {
+                next_general += 1;
+                Counter::General(next_general - 1)
+            },
+            _ => return Err(Error::NoCounters),
+        };
+        counters.push((event, counter));
+    }
+    Ok(counters)
+}
+
+/// Counters set up by `start()`. They count until `stop()`, or until
+/// they are dropped.
+pub struct Counters {
+    pmu: PerfMonitoring,
+    counters: Vec<(Event, Counter)>,
+}
+
+/// Start counting `events` on this CPU.
+pub fn start(events: &[Event]) -> Result<Counters, Error> {
+    let pmu = try!(claim());
+    let counters = match assign(&pmu, events) {
+        Ok(counters) => counters,
+        Err(error) => {
+            release();
+            return Err(error);
+        },
+    };
+
+    let mut global = 0;
+    for &(event, counter) in &counters {
+        unsafe { counter.program(event, false) };
+        global |= counter.global_bit();
+    }
+    unsafe { set_global(&pmu, global, true) };
+
+    Ok(Counters { pmu: pmu, counters: counters })
+}
+
+impl Counters {
+    /// The counts so far, in the order the events were given to `start()`
+    pub fn read(&self) -> Vec<(Event, u64)> {
+        self.counters.iter()
+            .map(|&(event, counter)| (event, unsafe { counter.read(&self.pmu) }))
+            .collect()
+    }
+
+    /// Stop counting, and return the counts.
+    pub fn stop(self) -> Vec<(Event, u64)> {
+        self.read()
+    }
+}
+
+impl Drop for Counters {
+    fn drop(&mut self) {
+        let mut global = 0;
+        for &(_, counter) in &self.counters {
+            unsafe { counter.disable() };
+            global |= counter.global_bit();
+        }
+        unsafe { set_global(&self.pmu, global, false) };
+        release();
+    }
+}
+
+/// Take a profiler sample every `period` `event`s, on the first general-
+/// purpose counter, until `stop_sampling()`. Needs version 2 or up, to
+/// tell overflows apart.
+pub fn start_sampling(event: Event, period: usize) -> Result<(), Error> {
+    let pmu = try!(claim());
+    if pmu.version < 2 || !pmu.has_event(event as u8) {
+        release();
+        return Err(Error::Unsupported(event));
+    }
+    let period = if period == 0 { 1 } else if period > MAX_PERIOD { MAX_PERIOD } else { period };
+    SAMPLING_PERIOD.store(period, Ordering::SeqCst);
+
+    let counter = Counter::General(0);
+    unsafe {
+        counter.program(event, true);
+        reload(period);
+        set_global(&pmu, counter.global_bit(), true);
+    }
+    Ok(())
+}
+
+/// Stop what `start_sampling()` started.
+pub fn stop_sampling() {
+    if SAMPLING_PERIOD.swap(0, Ordering::SeqCst) == 0 {
+        return;
+    }
+    if let Some(pmu) = info() {
+        let counter = Counter::General(0);
+        unsafe {
+            counter.disable();
+            set_global(&pmu, counter.global_bit(), false);
+        }
+    }
+    release();
+}
+
+/// Have the sampling counter overflow after `period` more events.
+unsafe fn reload(period: usize) {
+    write_msr(msr::IA32_PMC0, 0, (period as u32).wrapping_neg());
+}
+
+/// The counter overflow interrupt handler. The local APIC masks its
+/// entry on every PMI, so unmask it again.
+unsafe fn overflow(_vec: usize) {
+    let status = read_msr(msr::IA32_PERF_GLOBAL_STATUS);
+    let period = SAMPLING_PERIOD.load(Ordering::Relaxed);
+    if period != 0 && status & Counter::General(0).global_bit() != 0 {
+        profile::sample();
+        reload(period);
+    }
+    write_msr(msr::IA32_PERF_GLOBAL_OVF_CTRL, (status >> 32) as u32, status as u32);
+
+    io::route_perf_interrupt(PMI_VECTOR);
+    io::send_LAPIC_EOI();
+}
@@ -0,0 +1,277 @@
+//! # Profiler
+//!
+//! A sampling profiler. Once started, it records where the kernel was
+//! every few timer ticks, or every so many events of a performance
+//! counter (see the `pmu` module): the interrupted instruction and the return
+//! addresses above it on the stack, in a sample buffer of the CPU it
+//! happened on. The shell's `profile` command starts and stops it,
+//! shows the functions that were hit the most, and sends the samples
+//! over the serial port as folded stacks, one line per stack:
+//!
+//! ```
+//! banjos::sched::idle;banjos::timers::busy_sleep 12
+//! ```
+//!
+//! which is what `flamegraph.pl` takes to draw a flame graph.
+//!
+//! Like panic backtraces, the stacks come from following frame
+//! pointers. Time spent in user programs shows up as `[user]`.
+
+use collections::vec::Vec;
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use spin::Mutex;
+
+use io;
+use io::serial::SerialWriter;
+use irq;
+use ksyms::{self, Demangle};
+use memory;
+use pmu;
+
+/// CPUs with a sample buffer. Samples from others are dropped.
+const MAX_CPUS: usize = 4;
+
+/// Samples kept per CPU. Once the buffer is full, more are dropped.
+pub const SAMPLES_PER_CPU: usize = 1024;
+
+/// Addresses kept per sample, innermost first
+const STACK_DEPTH: usize = 8;
+
+/// Sample every this many timer ticks, unless told otherwise
+pub const DEFAULT_INTERVAL: usize = 10;
+
+/// Shown for samples taken in user programs
+const USER: &'static str = "[user]";
+
+#[derive(Clone, Copy)]
+struct Sample {
+    /// The interrupted instruction, then the calls it is inside
+    stack: [usize; STACK_DEPTH],
+    depth: usize,
+}
+
+struct Buffer {
+    samples: [Sample; SAMPLES_PER_CPU],
+    len: usize,
+}
+
+impl Buffer {
+    fn push(&mut self, sample: Sample) -> bool {
+        if self.len == SAMPLES_PER_CPU {
+            return false;
+        }
+        self.samples[self.len] = sample;
+        self.len += 1;
+        true
+    }
+
+    fn samples(&self) -> &[Sample] {
+        &self.samples[..self.len]
+    }
+}
+
+macro_rules! buffer {
+    () => (Mutex::new(Buffer {
+        samples: [Sample { stack: [0; STACK_DEPTH], depth: 0 }; SAMPLES_PER_CPU],
+        len: 0,
+    }))
+}
+
+/// One buffer per CPU, by local APIC ID. Interrupt handlers only
+/// `try_lock()` them, and drop the sample if that fails.
+static BUFFERS: [Mutex<Buffer>; MAX_CPUS] = [buffer!(), buffer!(), buffer!(), buffer!()];
+
+static RUNNING: AtomicBool = AtomicBool::new(false);
+static INTERVAL: AtomicUsize = AtomicUsize::new(DEFAULT_INTERVAL);
+/// Samples that were dropped since `start()`
+static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+/// Stop sampling and throw away the old samples.
+fn reset() {
+    stop();
+    for buffer in BUFFERS.iter() {
+        buffer.lock().len = 0;
+    }
+    DROPPED.store(0, Ordering::SeqCst);
+}
+
+/// Throw away the old samples, and start sampling every `interval`
+/// timer ticks.
+pub fn start(interval: usize) {
+    reset();
+    INTERVAL.store(if interval == 0 { 1 } else { interval }, Ordering::SeqCst);
+    RUNNING.store(true, Ordering::SeqCst);
+}
+
+/// Throw away the old samples, and start sampling every `period`
+/// `event`s instead, when the performance counter overflows.
+pub fn start_on_overflow(event: pmu::Event, period: usize) -> Result<(), pmu::Error> {
+    reset();
+    INTERVAL.store(0, Ordering::SeqCst);
+    try!(pmu::start_sampling(event, period));
+    RUNNING.store(true, Ordering::SeqCst);
+    Ok(())
+}
+
+/// Stop sampling. The samples are kept until the next start.
+pub fn stop() {
+    RUNNING.store(false, Ordering::SeqCst);
+    pmu::stop_sampling();
+}
+
+pub fn running() -> bool {
+    RUNNING.load(Ordering::Relaxed)
+}
+
+/// Timer ticks between samples, or 0 when sampling on counter overflows
+pub fn interval() -> usize {
+    INTERVAL.load(Ordering::Relaxed)
+}
+
+/// Samples taken, and samples dropped, since `start()`
+pub fn counts() -> (usize, usize) {
+    let taken = BUFFERS.iter().fold(0, |taken, buffer| taken + buffer.lock().len);
+    (taken, DROPPED.load(Ordering::Relaxed))
+}
+
+/// Called by the timer interrupt handler on every tick.
+pub fn timer_tick(ticks: usize) {
+    let interval = interval();
+    if running() && interval != 0 && ticks % interval == 0 {
+        sample();
+    }
+}
+
+/// Record where the interrupt being handled came from, if the profiler
+/// is running. Only call this from an interrupt handler.
+pub fn sample() {
+    if !running() {
+        return;
+    }
+    let from = irq::interrupted();
+    let mut sample = Sample { stack: [0; STACK_DEPTH], depth: 1 };
+    sample.stack[0] = from.rip;
+    if from.rip < memory::USER_SPACE_START {
+        walk_stack(from.rbp, &mut sample);
+    }
+
+    let cpu = io::cpu_id();
+    let recorded = cpu < MAX_CPUS && match BUFFERS[cpu].try_lock() {
+        Some(mut buffer) => buffer.push(sample),
+        None => false,
+    };
+    if !recorded {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Add the calls on the kernel stack to `sample`, following the saved
+/// frame pointers from `frame`.
+fn walk_stack(mut frame: usize, sample: &mut Sample) {
+    while sample.depth < STACK_DEPTH {
+        // The saved frame pointer, then the return address
+        if frame == 0 || frame % 8 != 0 || frame >= memory::USER_SPACE_START
+            || !memory::kernel_readable(frame, 16) {
+            break;
+        }
+        let (next, return_address) = unsafe {
+            (*(frame as *const usize), *((frame + 8) as *const usize))
+        };
+        if return_address == 0 {
+            break;
+        }
+        // The call is just before the return address
+        sample.stack[sample.depth] = return_address - 1;
+        sample.depth += 1;
+
+        if next <= frame {
+            break;
+        }
+        frame = next;
+    }
+}
+
+/// The function `address` is in, mangled, `[user]` or `None` if it
+/// isn't known.
+fn function(address: usize) -> Option<&'static str> {
+    if address >= memory::USER_SPACE_START {
+        Some(USER)
+    } else {
+        ksyms::lookup(address).map(|(name, _)| name)
+    }
+}
+
+/// The functions that were sampled, and how many samples were in each,
+/// most first. Samples outside of known functions count as `?`.
+pub fn histogram() -> Vec<(&'static str, usize)> {
+    let mut counts: Vec<(&'static str, usize)> = Vec::new();
+    for buffer in BUFFERS.iter() {
+        for sample in buffer.lock().samples() {
+            let name = function(sample.stack[0]).unwrap_or("?");
+            match counts.iter().position(|&(n, _)| n == name) {
+                Some(i) => counts[i].1 += 1,
+                None => counts.push((name, 1)),
+            }
+        }
+    }
+    counts.sort_by(|a, b| b.1.cmp(&a.1));
+    counts
+}
+
+/// Write every sampled stack to the serial port, in the folded-stack
+/// format. Returns the number of lines.
+pub fn export() -> usize {
+    let mut lines = 0;
+    for buffer in BUFFERS.iter() {
+        let buffer = buffer.lock();
+        let samples = buffer.samples();
+
+        // Equal stacks next to each other, to count them
+        let mut order: Vec<usize> = (0..samples.len()).collect();
+        order.sort_by(|&a, &b| samples[a].stack.cmp(&samples[b].stack));
+
+        let mut i = 0;
+        while i < order.len() {
+            let sample = &samples[order[i]];
+            let count = order[i..].iter()
+                .take_while(|&&j| samples[j].stack == sample.stack)
+                .count();
+            let _ = write_folded(&mut SerialWriter, sample, count);
+            lines += 1;
+            i += count;
+        }
+    }
+    lines
+}
+
+/// One line of folded stacks: the functions from the outermost in,
+/// separated by `;`, and how many samples had that stack.
+fn write_folded<W: Write>(out: &mut W, sample: &Sample, count: usize) -> fmt::Result {
+    for (i, &address) in sample.stack[..sample.depth].iter().rev().enumerate() {
+        if i > 0 {
+            try!(out.write_str(";"));
+        }
+        try!(match function(address) {
+            Some(name) => write!(out, "{}", Demangle(name)),
+            None => write!(out, "{:#x}", address),
+        });
+    }
+    write!(out, " {}\n", count)
+}
+
+
+#[test]
+/// Folded stacks go from the outermost call in
+fn profile_folded() {
+    use collections::String;
+
+    let mut sample = Sample { stack: [0; STACK_DEPTH], depth: 3 };
+    sample.stack[0] = 0x30;
+    sample.stack[1] = 0x20;
+    sample.stack[2] = 0x10;
+
+    let mut line = String::new();
+    write_folded(&mut line, &sample, 7).unwrap();
+    assert_eq!(line, "0x10;0x20;0x30 7\n");
+}
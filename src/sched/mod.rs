@@ -0,0 +1,478 @@
+//! # Scheduler
+//!
+//! Preemptive, round-robin scheduling of kernel threads. Each thread
+//! has its own (guarded) stack and a saved register context, and the
+//! APIC timer interrupt periodically forces the running thread to give
+//! way to the next one in the run queue.
+//!
+//! # Usage
+//! Call `sched::init()` once memory and interrupts are set up. This
+//! turns the calling code into the _idle thread_, which runs whenever
+//! nobody else wants the CPU. Then start threads with `spawn()`:
+//!
+//! ```
+//! fn worker(arg: usize) {
+//!     println!("Hello from thread number {}!", arg);
+//! }
+//! //...
+//! let id = sched::spawn("worker", worker, 42).expect("out of stacks");
+//! let exit_code = sched::join(id);
+//! ```
+//!
+//! Thread entry functions take a single `usize` argument, just like
+//! interrupt handlers. Pass anything bigger as a pointer.
+//!
+//! # Safety
+//! The scheduler state is shared with the timer interrupt handler, so
+//! it is only ever touched with interrupts disabled.
+
+use collections::btree_map::BTreeMap;
+use collections::vec_deque::VecDeque;
+use collections::vec::Vec;
+use collections::String;
+use alloc::boxed::Box;
+use spin::Mutex;
+
+use arch::x86_64::gdt;
+use irq;
+use memory::{self, AddressSpace};
+use sync::WaitQueue;
+use timers;
+
+mod thread;
+
+pub use self::thread::{ThreadId, State};
+use self::thread::{Thread, STACK_SIZE_PAGES};
+
+/// Number of timer ticks a thread may run before being preempted.
+const TIMESLICE_TICKS: usize = 10;
+
+/// A deadline that never comes: what sleeping "forever" waits for.
+const FOREVER: usize = ::core::usize::MAX;
+
+/// The thread ID of the idle (bootstrap) thread.
+const IDLE_THREAD: ThreadId = ThreadId(0);
+
+extern {
+    fn switch_context(old_stack_pointer: *mut usize, new_stack_pointer: usize);
+}
+
+/// The scheduler's book-keeping.
+struct Scheduler {
+    /// Every thread not yet joined. Boxed, so that the saved stack
+    /// pointers don't move around when the map is modified.
+    threads: BTreeMap<ThreadId, Box<Thread>>,
+    /// Threads ready to run, in order. Never contains the idle thread.
+    run_queue: VecDeque<ThreadId>,
+    /// The currently running thread
+    current: ThreadId,
+    /// The next free thread ID
+    next_id: usize,
+    /// Ticks left of the current thread's time slice
+    ticks_left: usize,
+}
+
+impl Scheduler {
+    fn new() -> Scheduler {
+        let mut threads = BTreeMap::new();
+        threads.insert(IDLE_THREAD, Box::new(Thread::bootstrap(IDLE_THREAD)));
+
+        Scheduler {
+            threads: threads,
+            run_queue: VecDeque::new(),
+            current: IDLE_THREAD,
+            next_id: IDLE_THREAD.0 + 1,
+            ticks_left: TIMESLICE_TICKS,
+        }
+    }
+
+    /// Move every sleeping thread whose time is up to the run queue.
+    fn wake_sleepers(&mut self, now: usize) {
+        for (id, thread) in self.threads.iter_mut() {
+            match thread.state {
+                State::Sleeping(until) | State::BlockedUntil(until)
+                    if now >= until && until != FOREVER => {
+                    thread.state = State::Ready;
+                    self.run_queue.push_back(*id);
+                },
+                _ => {},
+            }
+        }
+    }
+
+    /// Pick the next thread to run, update the states of it and the
+    /// current thread, and return the arguments for `switch_context`.
+    /// Returns `None` if the current thread should just keep running.
+    fn switch_next(&mut self) -> Option<(*mut usize, usize)> {
+        let current_runnable =
+            self.threads[&self.current].state == State::Running;
+
+        let next = match self.run_queue.pop_front() {
+            Some(id) => id,
+            // Nobody else wants to run: keep going if we can, otherwise idle.
+            None if current_runnable => return None,
+            None => IDLE_THREAD,
+        };
+
+        if next == self.current {
+            self.threads.get_mut(&next).unwrap().state = State::Running;
+            return None;
+        }
+
+        // Put the current thread back in line, unless it is idling,
+        // sleeping, blocked or dead.
+        if current_runnable {
+            self.threads.get_mut(&self.current).unwrap().state = State::Ready;
+            if self.current != IDLE_THREAD {
+                self.run_queue.push_back(self.current);
+            }
+        }
+
+        let old_sp = &mut self.threads.get_mut(&self.current)
+            .unwrap().stack_pointer as *mut usize;
+
+        let new_sp = {
+            let next_thread = self.threads.get_mut(&next).unwrap();
+            next_thread.state = State::Running;
+
+            // Interrupts and system calls from user mode must land on
+            // the next thread's kernel stack.
+            if let Some(ref stack) = next_thread.stack {
+                gdt::set_kernel_stack(stack.top());
+            }
+
+            // Kernel threads go back to the kernel's table, so that no
+            // address space stays loaded once its program is gone.
+            match next_thread.address_space {
+                Some(ref mut space) => unsafe { space.activate() },
+                None => unsafe { memory::activate_kernel() },
+            }
+
+            next_thread.stack_pointer
+        };
+
+        self.current = next;
+        self.ticks_left = TIMESLICE_TICKS;
+
+        Some((old_sp, new_sp))
+    }
+}
+
+/// The global scheduler, available after `init()`.
+static SCHEDULER: Mutex<Option<Scheduler>> = Mutex::new(None);
+
+/// Threads waiting in `join()` sleep here until some thread exits.
+static EXITED: WaitQueue = WaitQueue::new();
+
+/// Initialise the scheduler, and turn the calling code into the idle
+/// thread. Call this once, after `memory::init()`.
+pub fn init() {
+    assert_has_not_been_called!("sched::init must be called only once");
+
+    irq::without_interrupts(|| {
+        *SCHEDULER.lock() = Some(Scheduler::new());
+    });
+}
+
+/// Start a new kernel thread named `name`, running `entry(arg)`.
+/// Returns the new thread's ID, or `None` if no stack could be
+/// allocated.
+pub fn spawn(name: &str, entry: fn(usize), arg: usize) -> Option<ThreadId> {
+    spawn_thread(name, entry, arg, None)
+}
+
+/// Like `spawn()`, but the thread runs in (and owns) `address_space`.
+/// Used for user programs.
+pub fn spawn_in(name: &str, entry: fn(usize), arg: usize,
+                address_space: AddressSpace) -> Option<ThreadId> {
+    spawn_thread(name, entry, arg, Some(address_space))
+}
+
+fn spawn_thread(name: &str, entry: fn(usize), arg: usize,
+                address_space: Option<AddressSpace>) -> Option<ThreadId> {
+    let stack = match memory::alloc_stack(STACK_SIZE_PAGES) {
+        Some(stack) => stack,
+        None => return None,
+    };
+
+    irq::without_interrupts(|| {
+        let mut guard = SCHEDULER.lock();
+        let sched = guard.as_mut().expect("scheduler not initialised");
+
+        let id = ThreadId(sched.next_id);
+        sched.next_id += 1;
+
+        let mut thread = Box::new(Thread::new(id, name, stack, entry, arg));
+        thread.address_space = address_space;
+        // The new thread uses the console of whoever started it
+        thread.console = sched.threads[&sched.current].console;
+        sched.threads.insert(id, thread);
+        sched.run_queue.push_back(id);
+        Some(id)
+    })
+}
+
+/// Switch to the next thread in line, if any.
+///
+/// # Safety
+/// Interrupts must be disabled.
+unsafe fn schedule() {
+    let switch = match *SCHEDULER.lock() {
+        Some(ref mut sched) => sched.switch_next(),
+        None => None,
+    };
+
+    // The lock must be released before switching, or the next thread
+    // would never be able to take it.
+    if let Some((old_sp, new_sp)) = switch {
+        switch_context(old_sp, new_sp);
+    }
+}
+
+/// Give up the rest of the current time slice.
+pub fn yield_now() {
+    irq::without_interrupts(|| unsafe { schedule() });
+}
+
+/// Sleep for at least `ticks` timer ticks. Sleeps forever if that is
+/// past the end of time.
+pub fn sleep(ticks: usize) {
+    irq::without_interrupts(|| {
+        {
+            let mut guard = SCHEDULER.lock();
+            let sched = guard.as_mut().expect("scheduler not initialised");
+            let current = sched.current;
+            if current == IDLE_THREAD {
+                // The idle thread must always be runnable!
+                return;
+            }
+            sched.threads.get_mut(&current).unwrap().state =
+                State::Sleeping(timers::get_ticks().saturating_add(ticks));
+        }
+        unsafe { schedule() };
+    });
+}
+
+/// Block the running thread until someone calls `wake()` on it.
+/// You probably want a `sync::WaitQueue` instead.
+///
+/// # Safety
+/// Interrupts must be disabled, or the wake-up could be lost.
+pub unsafe fn block() {
+    {
+        let mut guard = SCHEDULER.lock();
+        let sched = guard.as_mut().expect("scheduler not initialised");
+        let current = sched.current;
+        assert!(current != IDLE_THREAD, "the idle thread cannot block");
+        sched.threads.get_mut(&current).unwrap().state = State::Blocked;
+    }
+    schedule();
+}
+
+/// Like `block()`, but wake up by ourselves at tick `deadline` at the
+/// latest.
+///
+/// # Safety
+/// Interrupts must be disabled, or the wake-up could be lost.
+pub unsafe fn block_until(deadline: usize) {
+    {
+        let mut guard = SCHEDULER.lock();
+        let sched = guard.as_mut().expect("scheduler not initialised");
+        let current = sched.current;
+        assert!(current != IDLE_THREAD, "the idle thread cannot block");
+        sched.threads.get_mut(&current).unwrap().state =
+            State::BlockedUntil(deadline);
+    }
+    schedule();
+}
+
+/// Make the blocked thread `id` runnable again. Does nothing if the
+/// thread isn't blocked. Safe to call from interrupt handlers.
+pub fn wake(id: ThreadId) {
+    irq::without_interrupts(|| {
+        if let Some(ref mut sched) = *SCHEDULER.lock() {
+            if let Some(thread) = sched.threads.get_mut(&id) {
+                match thread.state {
+                    State::Blocked | State::BlockedUntil(_) => {
+                        thread.state = State::Ready;
+                        sched.run_queue.push_back(id);
+                    },
+                    _ => {},
+                }
+            }
+        }
+    });
+}
+
+/// Terminate the current thread with exit code `code`. The code is
+/// handed to whoever `join()`s the thread.
+pub fn exit(code: usize) -> ! {
+    irq::without_interrupts(|| {
+        {
+            let mut guard = SCHEDULER.lock();
+            let sched = guard.as_mut().expect("scheduler not initialised");
+            let current = sched.current;
+            assert!(current != IDLE_THREAD, "the idle thread cannot exit");
+            sched.threads.get_mut(&current).unwrap().state = State::Dead(code);
+        }
+        EXITED.wake_all();
+        unsafe { schedule() };
+    });
+
+    unreachable!("dead thread was scheduled");
+}
+
+/// Wait for the thread `id` to exit, and return its exit code. Returns
+/// `None` if there is no such thread (or someone else joined it first).
+pub fn join(id: ThreadId) -> Option<usize> {
+    let mut reaped = None;
+
+    EXITED.wait_until(|| {
+        let mut guard = SCHEDULER.lock();
+        let sched = guard.as_mut().expect("scheduler not initialised");
+
+        let code = match sched.threads.get(&id) {
+            None => return true,
+            Some(thread) => match thread.state {
+                State::Dead(code) => code,
+                _ => return false,
+            },
+        };
+
+        let thread = sched.threads.remove(&id).unwrap();
+        reaped = Some((code, thread));
+        true
+    });
+
+    reaped.map(|(code, thread)| {
+        // Free the stack (and address space, when `thread` goes out of
+        // scope) outside of the scheduler lock
+        let mut thread = thread;
+        if let Some(stack) = thread.stack.take() {
+            memory::free_stack(stack);
+        }
+        code
+    })
+}
+
+/// Ask the thread `id` to stop. Nothing happens to it by itself: the
+/// thread finds out with `interrupted()`, and user programs are stopped
+/// on their way back to user mode, from a system call or the timer
+/// interrupt. A sleeping or blocked thread is woken up to look.
+pub fn interrupt(id: ThreadId) {
+    irq::without_interrupts(|| {
+        if let Some(ref mut sched) = *SCHEDULER.lock() {
+            if let Some(thread) = sched.threads.get_mut(&id) {
+                thread.interrupted = true;
+                match thread.state {
+                    State::Sleeping(_) | State::Blocked | State::BlockedUntil(_) => {
+                        thread.state = State::Ready;
+                        sched.run_queue.push_back(id);
+                    },
+                    _ => {},
+                }
+            }
+        }
+    });
+}
+
+/// True if the running thread has been asked to stop with `interrupt()`.
+pub fn interrupted() -> bool {
+    irq::without_interrupts(|| {
+        match *SCHEDULER.lock() {
+            Some(ref sched) => sched.threads[&sched.current].interrupted,
+            None => false,
+        }
+    })
+}
+
+/// The virtual console of the running thread, or the first one if
+/// there are no threads yet.
+pub fn console() -> usize {
+    irq::without_interrupts(|| {
+        // Called for every `print!`, which may come with the lock held,
+        // from a panic
+        match SCHEDULER.try_lock() {
+            Some(guard) => guard.as_ref().map_or(0, |s| s.threads[&s.current].console),
+            None => 0,
+        }
+    })
+}
+
+/// Move the running thread to virtual console `console`. The threads it
+/// starts from now on go with it.
+pub fn set_console(console: usize) {
+    irq::without_interrupts(|| {
+        if let Some(ref mut sched) = *SCHEDULER.lock() {
+            let current = sched.current;
+            sched.threads.get_mut(&current).unwrap().console = console;
+        }
+    });
+}
+
+/// Return the ID of the running thread.
+pub fn current() -> ThreadId {
+    irq::without_interrupts(|| {
+        SCHEDULER.lock().as_ref().map(|s| s.current).unwrap_or(IDLE_THREAD)
+    })
+}
+
+/// Return the ID, name and state of every thread.
+pub fn list() -> Vec<(ThreadId, String, State)> {
+    irq::without_interrupts(|| {
+        let guard = SCHEDULER.lock();
+        match *guard {
+            Some(ref sched) => sched.threads.values()
+                .map(|t| (t.id, t.name.clone(), t.state))
+                .collect(),
+            None => Vec::new(),
+        }
+    })
+}
+
+/// Called on every timer tick, from the timer interrupt handler.
+/// Preempts the running thread when its time slice is up.
+///
+/// # Safety
+/// Interrupts must be disabled, and the LAPIC must already have
+/// received its EOI.
+pub unsafe fn tick() {
+    let preempt = match *SCHEDULER.lock() {
+        Some(ref mut sched) => {
+            sched.wake_sleepers(timers::get_ticks());
+
+            if sched.ticks_left > 0 {
+                sched.ticks_left -= 1;
+            }
+
+            sched.ticks_left == 0
+                || (sched.current == IDLE_THREAD && !sched.run_queue.is_empty())
+        },
+        None => false,
+    };
+
+    if preempt {
+        schedule();
+    }
+}
+
+/// Where new threads start out, via `thread_trampoline`.
+pub fn thread_start(entry: usize, arg: usize) -> ! {
+    use core::mem;
+
+    // We got here from `schedule()`, which runs with interrupts
+    // disabled.
+    unsafe { asm!("sti" :::: "volatile") };
+
+    let entry: fn(usize) = unsafe { mem::transmute(entry) };
+    entry(arg);
+
+    exit(0)
+}
+
+/// Run the idle loop: halt until the next interrupt, forever.
+pub fn idle() -> ! {
+    loop {
+        unsafe { asm!("hlt" :::: "volatile") };
+    }
+}
@@ -0,0 +1,112 @@
+//! The kernel's idea of a thread: a saved stack pointer, a stack, and
+//! some bookkeeping.
+
+use collections::String;
+use memory::{Stack, AddressSpace};
+
+/// A unique (for the lifetime of the system) thread identifier.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ThreadId(pub usize);
+
+/// The states a thread can be in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum State {
+    /// Currently executing on the CPU
+    Running,
+    /// Waiting in the run queue
+    Ready,
+    /// Sleeping until the given tick
+    Sleeping(usize),
+    /// Waiting for something else to wake it up
+    Blocked,
+    /// Waiting for something else to wake it up, or the given tick,
+    /// whichever comes first
+    BlockedUntil(usize),
+    /// Exited with the given exit code, but not yet joined
+    Dead(usize),
+}
+
+/// A kernel thread.
+pub struct Thread {
+    /// The thread's ID
+    pub id: ThreadId,
+    /// Human-readable name, for `ps` and friends
+    pub name: String,
+    /// Current state
+    pub state: State,
+    /// The saved stack pointer, as stored by `switch_context`. Only
+    /// valid when the thread is not running.
+    pub stack_pointer: usize,
+    /// The thread's stack. `None` for the bootstrap thread, which
+    /// runs on the stack set up in `boot.asm`.
+    pub stack: Option<Stack>,
+    /// The thread's address space, if it has one of its own. Kernel
+    /// threads run in whatever is active.
+    pub address_space: Option<AddressSpace>,
+    /// Asked to stop, by Ctrl-C on the console
+    pub interrupted: bool,
+    /// The virtual console the thread reads from and writes to
+    pub console: usize,
+}
+
+/// Size of a kernel thread stack, in pages.
+pub const STACK_SIZE_PAGES: usize = 4;
+
+impl Thread {
+    /// Create a thread structure for the code that is already running,
+    /// that is the one which called `sched::init()`.
+    pub fn bootstrap(id: ThreadId) -> Thread {
+        Thread {
+            id: id,
+            name: String::from("idle"),
+            state: State::Running,
+            stack_pointer: 0,
+            stack: None,
+            address_space: None,
+            interrupted: false,
+            console: 0,
+        }
+    }
+
+    /// Create a new thread which will call `entry(arg)` the first time
+    /// it is switched to. The stack is prepared so that the first
+    /// `switch_context` into it ends up in `thread_trampoline`.
+    pub fn new(id: ThreadId, name: &str, stack: Stack,
+               entry: fn(usize), arg: usize) -> Thread {
+        extern {
+            fn thread_trampoline();
+        }
+
+        // The initial stack contents, from the top down. Returning to
+        // the trampoline leaves the stack pointer at the 16-byte aligned
+        // top, so its `call` enters `rust_thread_start` with it 8 off
+        // alignment, as the ABI requires.
+        let initial: [usize; 7] = [
+            thread_trampoline as usize,     // return address
+            0,                              // rbp
+            0,                              // rbx
+            entry as usize,                 // r12
+            arg,                            // r13
+            0,                              // r14
+            0,                              // r15
+        ];
+
+        let top = stack.top() & !0xf;
+        let mut sp = top;
+        for value in initial.iter() {
+            sp -= 8;
+            unsafe { *(sp as *mut usize) = *value; }
+        }
+
+        Thread {
+            id: id,
+            name: String::from(name),
+            state: State::Ready,
+            stack_pointer: sp,
+            stack: Some(stack),
+            address_space: None,
+            interrupted: false,
+            console: 0,
+        }
+    }
+}
@@ -4,14 +4,13 @@
 //!
 //! ```
 //! mod shell;
-//! unsafe { // Unsafe to read global variables!
-//!     if let Some(ref mut buffer) = io::kbd_buffer {
-//!         let mut shell = shell::Shell::new();
-//!         shell.run(buffer);
-//!     }
-//! }
+//! let mut shell = shell::Shell::new();
+//! shell.run();
 //! ```
 //!
+//! The shell reads lines from the TTY of its virtual console (see the
+//! `tty` module), which does the line editing and keeps the history.
+//!
 //!
 //! Current accepted commands:
 //!
@@ -27,6 +26,10 @@
 //! + `set-lang LANG`
 //!     - Sets language given by _LANG_ to Swedish (sv), English (en) respectively
 //!     - `välj-språk` in Swedish
+//! + `set-keymap LAYOUT`
+//!     - Switches the keyboard layout to _LAYOUT_: US (us), Swedish (sv)
+//!       or Dvorak (dvorak). Lists the layouts if no _LAYOUT_ is given
+//!     - `välj-tangentbord` in Swedish
 //! + `set-name ARG`
 //!     - Sets current username to _ARG_
 //!     - `välj-namn` in Swedish
@@ -36,20 +39,68 @@
 //! + `clear`
 //!     - Clears screen
 //!     - `rensa` in Swedish
+//! + `ps`
+//!     - Lists all kernel threads and their states
+//!     - `trådar` in Swedish
+//! + `run MODULE`
+//!     - Runs the program _MODULE_ and waits for it to exit. Lists the
+//!       available programs if no _MODULE_ is given
+//!     - `kör` in Swedish
+//! + `dmesg`
+//!     - Prints the kernel log
+//!     - `kärnlogg` in Swedish
+//! + `loglevel [LEVEL | MODULE=LEVEL]...`
+//!     - Sets the kernel log level, or the level of _MODULE_ and the
+//!       modules inside it: error, warn, info, debug or trace. Shows
+//!       the levels if none is given
+//!     - `loggnivå` in Swedish
+//! + `sym ADDRESS | NAME`
+//!     - Prints the kernel function at _ADDRESS_ (in hex, like
+//!       `0x10a2f0`), or the address of the function _NAME_
+//!     - `symbol` in Swedish
+//! + `gdb`
+//!     - Stops the kernel here for GDB, over the serial port (see the
+//!       `gdb` module)
+//!     - `felsök` in Swedish
+//! + `profile start [TICKS | EVENT [PERIOD]] | stop | report | export`
+//!     - Starts the sampling profiler, taking a sample every _TICKS_
+//!       timer ticks or every _PERIOD_ _EVENT_s (like `cycles`), or
+//!       stops it. `report` shows the functions it found the kernel in
+//!       the most, and `export` sends the samples over the serial port
+//!       as folded stacks (see the `profile` module)
+//!     - `profilera start [TICKS | EVENT [PERIOD]] | stopp | rapport |
+//!       exportera` in Swedish
+//! + `perf stat COMMAND`
+//!     - Runs _COMMAND_, and shows the cycles, instructions and cache
+//!       misses it took, as counted by the performance counters
+//!     - `perf` in Swedish
+//!
+//! Commands can also be connected with `|`, like `ps | grep shell`; see
+//! the `pipeline` module for which ones.
 
 
 use collections::String;
+use collections::vec::Vec;
 use collections::str::SplitWhitespace;
 use collections::str::FromStr;
-use collections::vec::Vec;
 
 
 use vga_buffer;
 
 
-use pipe::Buffer;
+use io::keymap;
 use msr;
-use io;
+use pmu::{self, Event};
+use profile;
+use sched;
+use timers;
+use gdb;
+use ksyms::{self, Demangle};
+use loader;
+use log;
+use tty::{self, ReadError};
+
+mod pipeline;
 
 
 enum Lang {
@@ -59,17 +110,22 @@ enum Lang {
 
 const DEFAULT_LANG: Lang = Lang::en;
 
+/// Functions shown by `profile report`
+const PROFILE_LINES: usize = 20;
+
+/// Events between samples for `profile start EVENT`, unless given
+const DEFAULT_PERIOD: usize = 1_000_000;
+
+/// What `perf stat` counts
+const PERF_STAT_EVENTS: [Event; 3] = [Event::Cycles, Event::Instructions, Event::CacheMisses];
+
 
-/// Struct for the Shell, this could allow multiple TTY:s if one would like
+/// Struct for the Shell; every virtual console runs one
 pub struct Shell {
     /// Language currently set
     current_lang: Lang,
     /// The prompt (start of each new line)
     host: String,
-    /// Vector containing previously entered lines
-    history: Vec<String>,
-    /// Index of current line
-    cur_line: usize,
     /// User name
     user_name: String,
 }
@@ -80,114 +136,30 @@ impl Shell {
     pub fn new() -> Shell {
         Shell { current_lang: DEFAULT_LANG,
                 host: String::from("BanjOS"),
-                history: Vec::new(),
-                cur_line: 0,
                 user_name: String::from("super_user"),
         }
     }
 
-    /// Main loop for SHELL, expects a readable input buffer (which could be used for testing)
-    pub fn run(&mut self, input: &mut Buffer) {
+    /// Main loop for SHELL
+    pub fn run(&mut self) {
 
         print!("\n");
         loop {
             print!("{}@{}: ", self.user_name, self.host);
 
-            let mut line: String = String::from("");
-            let mut tmp_line: String = String::from("");
-            let mut tmp_cur_line: usize = self.cur_line;
-
-            // Input loop
-            let mut end_of_input: bool = false;
-            while !end_of_input {
-
-                // Nag the io variable until there's something to read
-                while unsafe { !io::kbd_buffer_empty } {
-
-                    let mut_input: &mut Buffer = input;
-                    for current in mut_input {
-                        match current {
-                            0x08 => { // Backspace
-                                if !line.is_empty() {
-                                    vga_buffer::step_left();
-                                    print!(" ");
-                                    vga_buffer::step_left();
-                                    line.pop();
-                                }
-                            },
-                            0x82 => { // UP arrow
-                                if tmp_cur_line > 0 {
-                                    for _ in line.chars() {
-                                        vga_buffer::step_left();
-                                        print!(" ");
-                                        vga_buffer::step_left();
-                                    }
-
-                                    tmp_cur_line -= 1;
-
-                                    print!("{}", self.history[tmp_cur_line]);
-                                    line = self.history[tmp_cur_line].clone();
-                                }
-                            },
-                            0x83 => { // DOWN arrow
-                                if tmp_cur_line + 1 < self.cur_line {
-                                    for _ in line.chars() {
-                                        vga_buffer::step_left();
-                                        print!(" ");
-                                        vga_buffer::step_left();
-                                    }
-
-                                    tmp_cur_line += 1;
-
-                                    print!("{}", self.history[tmp_cur_line]);
-                                    line = self.history[tmp_cur_line].clone();
-
-                                } else if tmp_cur_line + 1 == self.cur_line {
-                                    for _ in line.chars() {
-                                        vga_buffer::step_left();
-                                        print!(" ");
-                                        vga_buffer::step_left();
-                                    }
-                                    tmp_cur_line += 1;
-
-                                    print!("{}", tmp_line);
-                                    line = tmp_line.clone();
-                                }
-                            },
-                            _ => {
-                                let cur_char = match current {
-                                    0xC5 => 'Å', // Å
-                                    0xE5 => 'å', // å
-                                    0xC4 => 'Ä', // Ä
-                                    0xE4 => 'ä', // ä
-                                    0xD6 => 'Ö', // Ö
-                                    0xF6 => 'ö', // ö
-                                    ch => ch as char,
-                                };
-                                print!("{}", cur_char as char);
-
-                                line.push(cur_char as char);
-                                tmp_line.push(cur_char as char);
-
-                                // This could be expanded to check for quotes, escape char, etc
-                                if current == b'\n' {
-                                    end_of_input = true;
-                                }
-                            },
-                        }
-                    }
-
-                    // Set the io variable to empty
-                    unsafe { io::kbd_buffer_empty = true; }
-                }
-            }
-
-            // Remove newline char
-            line.pop();
+            // The TTY does the editing and keeps the history
+            let line = match tty::read_line() {
+                Ok(line) => line,
+                Err(ReadError::Interrupted) => continue,
+                Err(ReadError::EndOfFile) => {
+                    println!("");
+                    continue;
+                },
+            };
 
-            if !line.is_empty() { // Ignore empty lines
-                self.history.push(line.clone());
-                self.cur_line += 1;
+            if line.contains('|') {
+                pipeline::run(&line);
+                continue;
             }
 
             let ref mut split_line = line.split_whitespace();
@@ -211,6 +183,296 @@ impl Shell {
         };
     }
 
+    /// Switches keyboard layout to `name`, or lists the layouts if no
+    /// `name` is given
+    fn set_keymap(&self, name: Option<&str>) {
+        match name {
+            Some(name) => if !keymap::set_keymap(name) {
+                match self.current_lang {
+                    Lang::en => println!("No such keyboard layout: {}", name),
+                    Lang::sv => println!("Ingen sådan tangentbordslayout: {}", name),
+                }
+            },
+            None => {
+                let current = keymap::current().name;
+                for layout in keymap::KEYMAPS.iter() {
+                    let marker = if layout.name == current { '*' } else { ' ' };
+                    println!("{} {:<8} {}", marker, layout.name, layout.description);
+                }
+            },
+        }
+    }
+
+    /// Prints every kernel thread along with its state
+    fn print_threads(&self) {
+        for (id, name, state) in sched::list() {
+            println!("{:>4} {:<16} {:?}", id.0, name, state);
+        }
+    }
+
+    /// Prints the kernel log, oldest first
+    fn print_log(&self) {
+        for record in log::records() {
+            println!("{}", record);
+        }
+    }
+
+    /// Sets log levels from `settings`, each `LEVEL` or `MODULE=LEVEL`,
+    /// or shows them if there are no `settings`
+    fn set_log_level(&self, settings: &mut SplitWhitespace) {
+        let mut any = false;
+        for setting in settings {
+            any = true;
+            if !log::configure(setting) {
+                match self.current_lang {
+                    Lang::en => println!("Bad log level: {}", setting),
+                    Lang::sv => println!("Felaktig loggnivå: {}", setting),
+                }
+            }
+        }
+
+        if !any {
+            println!("{}", log::level());
+            for (module, level) in log::filters() {
+                println!("{}={}", module, level);
+            }
+        }
+    }
+
+    /// Prints the kernel function at `arg`, if it is an address, or the
+    /// address of the function `arg`
+    fn print_symbol(&self, arg: Option<&str>) {
+        let arg = match arg {
+            Some(arg) => arg,
+            None => {
+                match self.current_lang {
+                    Lang::en => println!("No argument given"),
+                    Lang::sv => println!("Inget argument givet"),
+                }
+                return;
+            },
+        };
+
+        let address = if arg.starts_with("0x") {
+            usize::from_str_radix(&arg[2..], 16).ok()
+        } else {
+            None
+        };
+        match address {
+            Some(address) => match ksyms::lookup(address) {
+                Some((name, offset)) => println!("{}+{:#x}", Demangle(name), offset),
+                None => println!("{:#x}: ?", address),
+            },
+            None => match ksyms::address_of(arg) {
+                Some(address) => println!("{:#x}", address),
+                None => println!("{}: ?", arg),
+            },
+        }
+    }
+
+    /// Stops for GDB, if there is a serial port to talk to it on
+    fn debug(&self) {
+        if !gdb::installed() {
+            match self.current_lang {
+                Lang::en => println!("No serial port for GDB"),
+                Lang::sv => println!("Ingen serieport för GDB"),
+            }
+            return;
+        }
+        match self.current_lang {
+            Lang::en => println!("Waiting for GDB on the serial port"),
+            Lang::sv => println!("Väntar på GDB på serieporten"),
+        }
+        gdb::breakpoint();
+    }
+
+    /// Starts or stops the profiler, or shows what it found
+    fn profile(&self, args: &mut SplitWhitespace) {
+        match args.next() {
+            Some("start") => self.start_profile(args),
+            Some("stop") | Some("stopp") => profile::stop(),
+            Some("report") | Some("rapport") => self.print_profile(),
+            Some("export") | Some("exportera") => {
+                let lines = profile::export();
+                match self.current_lang {
+                    Lang::en => println!("Sent {} stacks over the serial port", lines),
+                    Lang::sv => println!("Skickade {} stackar över serieporten", lines),
+                }
+            },
+            _ => match self.current_lang {
+                Lang::en => println!("Usage: profile start [TICKS | EVENT [PERIOD]] | stop | \
+                                      report | export"),
+                Lang::sv => println!("Användning: profilera start [TICKS | EVENT [PERIOD]] | \
+                                      stopp | rapport | exportera"),
+            },
+        }
+    }
+
+    /// Starts the profiler on the timer, every `TICKS` in `args`, or on
+    /// the performance counter `EVENT [PERIOD]`
+    fn start_profile(&self, args: &mut SplitWhitespace) {
+        let arg = match args.next() {
+            Some(arg) => arg,
+            None => return profile::start(profile::DEFAULT_INTERVAL),
+        };
+        if let Ok(ticks) = usize::from_str(arg) {
+            return profile::start(ticks);
+        }
+
+        match Event::from_name(arg) {
+            Some(event) => {
+                let period = args.next()
+                    .and_then(|period| usize::from_str(period).ok())
+                    .unwrap_or(DEFAULT_PERIOD);
+                if let Err(error) = profile::start_on_overflow(event, period) {
+                    self.print_pmu_error(error);
+                }
+            },
+            None => match self.current_lang {
+                Lang::en => println!("No such event: {}", arg),
+                Lang::sv => println!("Ingen sådan händelse: {}", arg),
+            },
+        }
+    }
+
+    /// Runs the command after `perf stat` in `args`, and shows what the
+    /// performance counters counted meanwhile
+    fn perf(&mut self, args: &mut SplitWhitespace) {
+        if args.next() != Some("stat") {
+            match self.current_lang {
+                Lang::en => println!("Usage: perf stat COMMAND"),
+                Lang::sv => println!("Användning: perf stat KOMMANDO"),
+            }
+            return;
+        }
+
+        let events: Vec<Event> = PERF_STAT_EVENTS.iter().cloned()
+            .filter(|&event| pmu::supported(event))
+            .collect();
+        let counters = match pmu::start(&events) {
+            Ok(counters) => counters,
+            Err(error) => return self.print_pmu_error(error),
+        };
+        let start = timers::get_ticks();
+
+        match self.current_lang {
+            Lang::en => self.parse_line_en(args),
+            Lang::sv => self.parse_line_sv(args),
+        }
+
+        let elapsed = timers::get_ticks() - start;
+        let counts = counters.stop();
+
+        println!("");
+        for &(event, count) in &counts {
+            println!("{:>16}  {}", count, event.name());
+        }
+        let count_of = |event: Event| {
+            counts.iter().find(|&&(e, _)| e == event).map(|&(_, count)| count)
+        };
+        if let (Some(cycles), Some(instructions)) =
+            (count_of(Event::Cycles), count_of(Event::Instructions)) {
+            if cycles > 0 {
+                let per_100 = instructions * 100 / cycles;
+                match self.current_lang {
+                    Lang::en => println!("{:>13}.{:02}  instructions per cycle",
+                                         per_100 / 100, per_100 % 100),
+                    Lang::sv => println!("{:>13}.{:02}  instruktioner per cykel",
+                                         per_100 / 100, per_100 % 100),
+                }
+            }
+        }
+        for event in PERF_STAT_EVENTS.iter().filter(|&&event| !pmu::supported(event)) {
+            println!("{:>16}  {}", "-", event.name());
+        }
+        println!("{:>16}  ms", elapsed);
+    }
+
+    /// Explains why the performance counters couldn't be used
+    fn print_pmu_error(&self, error: pmu::Error) {
+        match (&self.current_lang, error) {
+            (&Lang::en, pmu::Error::NoPmu) =>
+                println!("This CPU has no performance counters"),
+            (&Lang::sv, pmu::Error::NoPmu) =>
+                println!("Processorn har inga prestandaräknare"),
+            (&Lang::en, pmu::Error::Busy) =>
+                println!("The performance counters are in use"),
+            (&Lang::sv, pmu::Error::Busy) =>
+                println!("Prestandaräknarna används redan"),
+            (&Lang::en, pmu::Error::Unsupported(event)) =>
+                println!("This CPU can't count {}", event.name()),
+            (&Lang::sv, pmu::Error::Unsupported(event)) =>
+                println!("Processorn kan inte räkna {}", event.name()),
+            (&Lang::en, pmu::Error::NoCounters) =>
+                println!("Not enough performance counters"),
+            (&Lang::sv, pmu::Error::NoCounters) =>
+                println!("Inte tillräckligt många prestandaräknare"),
+        }
+    }
+
+    /// Prints the functions the profiler found the kernel in the most
+    fn print_profile(&self) {
+        let (taken, dropped) = profile::counts();
+        let interval = profile::interval();
+        match self.current_lang {
+            Lang::en => {
+                print!("{} samples", taken);
+                if interval == 0 {
+                    print!(" on counter overflows");
+                } else {
+                    print!(" every {} ticks", interval);
+                }
+                println!(", {} dropped{}", dropped,
+                         if profile::running() { ", still running" } else { "" });
+            },
+            Lang::sv => {
+                print!("{} prov", taken);
+                if interval == 0 {
+                    print!(" vid räknaröverslag");
+                } else {
+                    print!(" var {}:e tick", interval);
+                }
+                println!(", {} bortkastade{}", dropped,
+                         if profile::running() { ", körs fortfarande" } else { "" });
+            },
+        }
+        if taken == 0 {
+            return;
+        }
+        for &(name, count) in profile::histogram().iter().take(PROFILE_LINES) {
+            println!("{:>6} {:>3}% {}", count, count * 100 / taken, Demangle(name));
+        }
+    }
+
+    /// Runs the program in module `name` and waits for it, or lists the
+    /// modules if no `name` is given
+    fn run_program(&self, name: Option<&str>) {
+        let name = match name {
+            Some(name) => name,
+            None => {
+                for module in loader::modules() {
+                    println!("{:<16} {} bytes", module.name,
+                             module.end - module.start);
+                }
+                return;
+            },
+        };
+
+        match loader::spawn(name) {
+            Ok(id) => {
+                tty::set_foreground(Some(id));
+                let code = sched::join(id);
+                tty::set_foreground(None);
+                if let Some(code) = code {
+                    if code != 0 {
+                        println!("{} exited with code {}", name, code);
+                    }
+                }
+            },
+            Err(error) => println!("{}: {}", name, error),
+        }
+    }
+
     /// Parses arguments in swedish
     fn parse_line_sv(&mut self, rd_line: &mut SplitWhitespace) {
         match rd_line.next() {
@@ -232,7 +494,7 @@ impl Shell {
             },
 
             Some("historik") =>
-                for line in &self.history {
+                for line in tty::history() {
                     println!("{}", line);
                 },
 
@@ -246,12 +508,30 @@ impl Shell {
                 self.set_lang(new_lang);
             },
 
+            Some("välj-tangentbord") => self.set_keymap(rd_line.next()),
+
             Some("välj-namn") => if let Some(new_name) = rd_line.next() {
                 self.user_name = String::from(new_name);
             },
 
             Some("rensa") => vga_buffer::clear_screen(),
 
+            Some("trådar") => self.print_threads(),
+
+            Some("kör") => self.run_program(rd_line.next()),
+
+            Some("kärnlogg") => self.print_log(),
+
+            Some("loggnivå") => self.set_log_level(rd_line),
+
+            Some("symbol") => self.print_symbol(rd_line.next()),
+
+            Some("felsök") => self.debug(),
+
+            Some("profilera") => self.profile(rd_line),
+
+            Some("perf") => self.perf(rd_line),
+
             Some("avsluta") =>
                 println!("Jag kan inte låta dig göra det, {}", self.user_name),
 
@@ -270,7 +550,7 @@ impl Shell {
             },
 
             Some("history") =>
-                for line in &self.history {
+                for line in tty::history() {
                     println!("{}", line);
                 },
 
@@ -292,6 +572,8 @@ impl Shell {
                 self.set_lang(new_lang);
             },
 
+            Some("set-keymap") => self.set_keymap(rd_line.next()),
+
             Some("set-host") => if let Some(new_host) = rd_line.next() {
                 self.host = String::from(new_host);
             } else {
@@ -300,6 +582,22 @@ impl Shell {
 
             Some("clear") => vga_buffer::clear_screen(),
 
+            Some("ps") => self.print_threads(),
+
+            Some("run") => self.run_program(rd_line.next()),
+
+            Some("dmesg") => self.print_log(),
+
+            Some("loglevel") => self.set_log_level(rd_line),
+
+            Some("sym") => self.print_symbol(rd_line.next()),
+
+            Some("gdb") => self.debug(),
+
+            Some("profile") => self.profile(rd_line),
+
+            Some("perf") => self.perf(rd_line),
+
             Some("set-name") => if let Some(new_name) = rd_line.next() {
                 self.user_name = String::from(new_name);
             },
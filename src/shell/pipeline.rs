@@ -0,0 +1,180 @@
+//! Pipelines, like `ps | grep shell | wc`.
+//!
+//! Every command in a pipeline runs in a thread of its own, with its
+//! output connected to the input of the next one through a pipe. The
+//! last command writes to the screen.
+//!
+//! Commands that can be used in pipelines:
+//!
+//! + `echo WORDS...` (`eko`): prints _WORDS_
+//! + `ps` (`trådar`): lists all threads
+//! + `dmesg` (`kärnlogg`): prints the kernel log
+//! + `cat`: copies its input
+//! + `grep WORD`: prints the input lines containing _WORD_
+//! + `wc`: counts input lines, words and bytes
+
+use alloc::boxed::Box;
+use collections::String;
+use collections::vec::Vec;
+use core::fmt::{self, Write};
+
+use log;
+use pipe::{self, PipeReader, PipeWriter};
+use sched;
+use vga_buffer;
+
+/// Where a command writes its output.
+enum Output {
+    Console,
+    Pipe(PipeWriter),
+}
+
+impl fmt::Write for Output {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        match *self {
+            Output::Console => vga_buffer::writer().write_str(s),
+            Output::Pipe(ref mut writer) => writer.write_str(s),
+        }
+    }
+}
+
+/// One command of a pipeline, handed to `run_stage`.
+struct Stage {
+    words: Vec<String>,
+    input: Option<PipeReader>,
+    output: Output,
+}
+
+/// Run the pipeline `line`, and wait for every command in it to finish.
+pub fn run(line: &str) {
+    let commands: Vec<&str> = line.split('|').map(|c| c.trim()).collect();
+    if commands.iter().any(|c| c.is_empty()) {
+        println!("Empty command in pipeline");
+        return;
+    }
+
+    let mut threads = Vec::new();
+    let mut input = None;
+
+    for (i, command) in commands.iter().enumerate() {
+        let (next_input, output) = if i + 1 == commands.len() {
+            (None, Output::Console)
+        } else {
+            let (reader, writer) = pipe::channel();
+            (Some(reader), Output::Pipe(writer))
+        };
+
+        let words: Vec<String> = command.split_whitespace()
+            .map(String::from).collect();
+        let name = words[0].clone();
+
+        let stage = Box::into_raw(Box::new(Stage {
+            words: words,
+            input: input.take(),
+            output: output,
+        })) as usize;
+
+        match sched::spawn(&name, run_stage, stage) {
+            Some(id) => threads.push(id),
+            None => {
+                // Dropping the stage closes its pipes, so its neighbours
+                // see end of file or a broken pipe.
+                unsafe { Box::from_raw(stage as *mut Stage) };
+                println!("Could not start {}", name);
+            },
+        }
+
+        input = next_input;
+    }
+
+    for id in threads {
+        sched::join(id);
+    }
+}
+
+/// Thread entry point for a pipeline command.
+fn run_stage(arg: usize) {
+    let stage = unsafe { Box::from_raw(arg as *mut Stage) };
+    let Stage { words, input, mut output } = *stage;
+    let args: Vec<&str> = words.iter().map(|w| &w[..]).collect();
+
+    // Errors mean the next command has quit, so just stop
+    let _ = match args[0] {
+        "echo" | "eko" => writeln!(output, "{}", args[1..].join(" ")),
+        "ps" | "trådar" => ps(&mut output),
+        "dmesg" | "kärnlogg" => dmesg(&mut output),
+        "cat" => for_each_line(&input, |line| writeln!(output, "{}", line)),
+        "grep" => match args.get(1) {
+            Some(word) => for_each_line(&input, |line| {
+                if line.contains(*word) {
+                    writeln!(output, "{}", line)
+                } else {
+                    Ok(())
+                }
+            }),
+            None => writeln!(Output::Console, "grep: No argument given"),
+        },
+        "wc" => wc(&input, &mut output),
+        command => writeln!(Output::Console, "Unrecognized command: {}", command),
+    };
+}
+
+/// List every thread, like the `ps` command.
+fn ps(output: &mut Output) -> fmt::Result {
+    for (id, name, state) in sched::list() {
+        try!(writeln!(output, "{:>4} {:<16} {:?}", id.0, name, state));
+    }
+    Ok(())
+}
+
+/// Print the kernel log, like the `dmesg` command.
+fn dmesg(output: &mut Output) -> fmt::Result {
+    for record in log::records() {
+        try!(writeln!(output, "{}", record));
+    }
+    Ok(())
+}
+
+/// Count lines, words and bytes of the input.
+fn wc(input: &Option<PipeReader>, output: &mut Output) -> fmt::Result {
+    let (mut lines, mut words, mut bytes) = (0, 0, 0);
+    try!(for_each_line(input, |line| {
+        lines += 1;
+        words += line.split_whitespace().count();
+        bytes += line.len() + 1;
+        Ok(())
+    }));
+    writeln!(output, "{:>6} {:>6} {:>6}", lines, words, bytes)
+}
+
+/// Call `f` with every line of `input`, until end of file.
+fn for_each_line<F>(input: &Option<PipeReader>, mut f: F) -> fmt::Result
+    where F: FnMut(&str) -> fmt::Result
+{
+    let reader = match *input {
+        Some(ref reader) => reader,
+        None => return Ok(()),
+    };
+
+    let mut line = Vec::new();
+    let mut buf = [0; 64];
+    loop {
+        let count = match reader.read(&mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(count) => count,
+        };
+        for &byte in &buf[..count] {
+            if byte == b'\n' {
+                try!(f(&String::from_utf8_lossy(&line)));
+                line.clear();
+            } else {
+                line.push(byte);
+            }
+        }
+    }
+
+    if !line.is_empty() {
+        try!(f(&String::from_utf8_lossy(&line)));
+    }
+    Ok(())
+}
@@ -0,0 +1,51 @@
+//! Condition variables.
+
+use irq;
+
+use super::{WaitQueue, MutexGuard};
+
+/// A condition variable, for waiting on a condition protected by a
+/// `Mutex`. As usual, always re-check the condition after waking up:
+///
+/// ```
+/// let mut ready = mutex.lock();
+/// while !*ready {
+///     ready = condvar.wait(ready);
+/// }
+/// ```
+pub struct Condvar {
+    waiters: WaitQueue,
+}
+
+impl Condvar {
+    /// Create a new condition variable.
+    pub const fn new() -> Condvar {
+        Condvar { waiters: WaitQueue::new() }
+    }
+
+    /// Release the lock held by `guard`, sleep until notified, and take
+    /// the lock again before returning.
+    pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let mutex = guard.mutex();
+
+        // Going on the wait queue and releasing the lock must happen
+        // without anyone else running in between, or we could miss a
+        // notification.
+        irq::without_interrupts(|| {
+            drop(guard);
+            unsafe { self.waiters.sleep() };
+        });
+
+        mutex.lock()
+    }
+
+    /// Wake up one waiting thread.
+    pub fn notify_one(&self) {
+        self.waiters.wake_one();
+    }
+
+    /// Wake up every waiting thread.
+    pub fn notify_all(&self) {
+        self.waiters.wake_all();
+    }
+}
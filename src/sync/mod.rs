@@ -0,0 +1,27 @@
+//! # Synchronisation
+//!
+//! Blocking synchronisation primitives for kernel threads. Where
+//! `spin::Mutex` burns CPU until the lock is free, everything in here
+//! puts the waiting thread to sleep and lets the scheduler run someone
+//! else.
+//!
+//! + `WaitQueue`: the building block for everything else; a queue of
+//!   sleeping threads. `wait_any()` waits on several at once.
+//! + `Mutex`: a mutual exclusion lock.
+//! + `Semaphore`: a counting semaphore.
+//! + `Condvar`: a condition variable, used together with `Mutex`.
+//!
+//! Interrupt handlers must never sleep, but they may _wake_ threads:
+//! `WaitQueue::wake_one()`, `WaitQueue::wake_all()`,
+//! `Semaphore::signal()` and `Condvar::notify_*()` are all safe to call
+//! from interrupt context.
+
+mod wait_queue;
+mod mutex;
+mod semaphore;
+mod condvar;
+
+pub use self::wait_queue::{WaitQueue, wait_any};
+pub use self::mutex::{Mutex, MutexGuard};
+pub use self::semaphore::Semaphore;
+pub use self::condvar::Condvar;
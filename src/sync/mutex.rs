@@ -0,0 +1,102 @@
+//! A mutual exclusion lock which puts waiting threads to sleep.
+
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use super::WaitQueue;
+
+/// A sleeping mutex. Unlike `spin::Mutex`, a thread that finds the lock
+/// taken yields the CPU until the lock is released.
+///
+/// Never use this from an interrupt handler: they cannot sleep.
+pub struct Mutex<T> {
+    locked: AtomicBool,
+    waiters: WaitQueue,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for Mutex<T> {}
+unsafe impl<T: Send> Send for Mutex<T> {}
+
+/// Proof of holding a `Mutex`. The lock is released when the guard is
+/// dropped.
+pub struct MutexGuard<'a, T: 'a> {
+    mutex: &'a Mutex<T>,
+}
+
+impl<T> Mutex<T> {
+    /// Create a new, unlocked mutex protecting `data`.
+    pub const fn new(data: T) -> Mutex<T> {
+        Mutex {
+            locked: AtomicBool::new(false),
+            waiters: WaitQueue::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Take the lock, sleeping until it is available.
+    pub fn lock(&self) -> MutexGuard<T> {
+        self.waiters.wait_until(|| self.acquire());
+        MutexGuard { mutex: self }
+    }
+
+    /// Like `lock()`, but give up at tick `deadline`.
+    pub fn lock_until(&self, deadline: usize) -> Option<MutexGuard<T>> {
+        if self.waiters.wait_until_timeout(|| self.acquire(), deadline) {
+            Some(MutexGuard { mutex: self })
+        } else {
+            // A release may have picked us to wake up just as we gave
+            // up: pass the wake-up on, or the next waiter could miss it.
+            self.waiters.wake_one();
+            None
+        }
+    }
+
+    /// Take the lock if it is free, without blocking.
+    pub fn try_lock(&self) -> Option<MutexGuard<T>> {
+        if self.acquire() {
+            Some(MutexGuard { mutex: self })
+        } else {
+            None
+        }
+    }
+
+    /// Try to flip the lock from free to taken.
+    fn acquire(&self) -> bool {
+        !self.locked.compare_and_swap(false, true, Ordering::Acquire)
+    }
+
+    /// Release the lock and wake up the next waiter.
+    fn release(&self) {
+        self.locked.store(false, Ordering::Release);
+        self.waiters.wake_one();
+    }
+}
+
+impl<'a, T> MutexGuard<'a, T> {
+    /// The mutex this guard is holding. Used by `Condvar`.
+    pub fn mutex(&self) -> &'a Mutex<T> {
+        self.mutex
+    }
+}
+
+impl<'a, T> Deref for MutexGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<'a, T> DerefMut for MutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<'a, T> Drop for MutexGuard<'a, T> {
+    fn drop(&mut self) {
+        self.mutex.release();
+    }
+}
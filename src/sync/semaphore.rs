@@ -0,0 +1,60 @@
+//! A counting semaphore.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use super::WaitQueue;
+
+/// A counting semaphore. `wait()` takes one unit, sleeping until one is
+/// available, and `signal()` gives one back.
+///
+/// `signal()` (but not `wait()`!) may be called from interrupt
+/// handlers, which makes this a good way to hand events from a driver
+/// to a thread.
+pub struct Semaphore {
+    count: AtomicUsize,
+    waiters: WaitQueue,
+}
+
+impl Semaphore {
+    /// Create a semaphore with `count` available units.
+    pub const fn new(count: usize) -> Semaphore {
+        Semaphore {
+            count: AtomicUsize::new(count),
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Take one unit, sleeping until one is available.
+    pub fn wait(&self) {
+        self.waiters.wait_until(|| self.try_wait());
+    }
+
+    /// Take one unit if available, without blocking. Returns `true` on
+    /// success.
+    pub fn try_wait(&self) -> bool {
+        let mut current = self.count.load(Ordering::Relaxed);
+        loop {
+            if current == 0 {
+                return false;
+            }
+
+            let previous = self.count.compare_and_swap(current, current - 1,
+                                                       Ordering::Acquire);
+            if previous == current {
+                return true;
+            }
+            current = previous;
+        }
+    }
+
+    /// Give back one unit, waking a waiting thread if there is one.
+    pub fn signal(&self) {
+        self.count.fetch_add(1, Ordering::Release);
+        self.waiters.wake_one();
+    }
+
+    /// The number of currently available units.
+    pub fn count(&self) -> usize {
+        self.count.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,155 @@
+//! Wait queues: lists of threads waiting for something to happen.
+
+use collections::vec_deque::VecDeque;
+use spin;
+
+use irq;
+use sched::{self, ThreadId};
+use timers;
+
+/// A queue of blocked threads. Threads put themselves to sleep on the
+/// queue, and are woken up in FIFO order by `wake_one()` or all at
+/// once by `wake_all()`.
+///
+/// Waking is safe from interrupt handlers: the internal lock is only
+/// ever taken with interrupts disabled.
+pub struct WaitQueue {
+    waiters: spin::Mutex<Option<VecDeque<ThreadId>>>,
+}
+
+impl WaitQueue {
+    /// Create an empty wait queue. Usable for statics.
+    pub const fn new() -> WaitQueue {
+        WaitQueue { waiters: spin::Mutex::new(None) }
+    }
+
+    /// Put the current thread on the queue, and block until woken.
+    ///
+    /// # Safety
+    /// Interrupts must be disabled, otherwise the wake-up could arrive
+    /// before the thread has gone to sleep and be lost.
+    pub unsafe fn sleep(&self) {
+        self.enqueue(sched::current());
+        sched::block();
+    }
+
+    /// Block the current thread until `condition` returns `true`. The
+    /// condition is checked with interrupts disabled, so a wake-up from
+    /// an interrupt handler can't slip in between the check and going
+    /// to sleep.
+    pub fn wait_until<F>(&self, mut condition: F)
+        where F: FnMut() -> bool
+    {
+        loop {
+            let done = irq::without_interrupts(|| {
+                if condition() {
+                    true
+                } else {
+                    unsafe { self.sleep() };
+                    false
+                }
+            });
+
+            if done {
+                return;
+            }
+        }
+    }
+
+    /// Like `wait_until`, but give up at tick `deadline`. Returns `false`
+    /// if it timed out.
+    pub fn wait_until_timeout<F>(&self, condition: F, deadline: usize) -> bool
+        where F: FnMut() -> bool
+    {
+        wait_any(&[self], condition, Some(deadline))
+    }
+
+    /// Wake up the thread that has waited the longest, if any. Returns
+    /// `true` if a thread was woken.
+    pub fn wake_one(&self) -> bool {
+        let next = irq::without_interrupts(|| {
+            self.waiters.lock().as_mut().and_then(|w| w.pop_front())
+        });
+
+        match next {
+            Some(id) => {
+                sched::wake(id);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Wake up every waiting thread.
+    pub fn wake_all(&self) {
+        while self.wake_one() {}
+    }
+
+    /// Stop waiting for thread `id`, for example after a timeout.
+    pub fn remove(&self, id: ThreadId) {
+        irq::without_interrupts(|| {
+            if let Some(ref mut waiters) = *self.waiters.lock() {
+                waiters.retain(|&w| w != id);
+            }
+        });
+    }
+
+    /// Add the thread `id` to the back of the queue.
+    fn enqueue(&self, id: ThreadId) {
+        irq::without_interrupts(|| {
+            let mut waiters = self.waiters.lock();
+            if waiters.is_none() {
+                *waiters = Some(VecDeque::new());
+            }
+            waiters.as_mut().unwrap().push_back(id);
+        });
+    }
+}
+
+/// Block the current thread until `condition` returns `true`, sleeping
+/// on all of `queues` at once: a wake-up on any of them makes it check
+/// again. Gives up at tick `deadline`, if there is one, and returns
+/// `false` in that case.
+///
+/// Threads waiting on several queues may be the one picked by
+/// `wake_one()` and not care, so only use this with queues that are
+/// woken with `wake_all()`.
+pub fn wait_any<F>(queues: &[&WaitQueue], mut condition: F,
+                   deadline: Option<usize>) -> bool
+    where F: FnMut() -> bool
+{
+    loop {
+        let done = irq::without_interrupts(|| {
+            if condition() {
+                return Some(true);
+            }
+            if let Some(deadline) = deadline {
+                if timers::get_ticks() >= deadline {
+                    return Some(false);
+                }
+            }
+
+            let current = sched::current();
+            for queue in queues {
+                queue.enqueue(current);
+            }
+
+            unsafe {
+                match deadline {
+                    Some(deadline) => sched::block_until(deadline),
+                    None => sched::block(),
+                }
+            }
+
+            // Only one of the queues (if any) woke us up
+            for queue in queues {
+                queue.remove(current);
+            }
+            None
+        });
+
+        if let Some(result) = done {
+            return result;
+        }
+    }
+}
@@ -0,0 +1,276 @@
+//! # System calls
+//!
+//! User programs enter the kernel with the `SYSCALL` instruction, which
+//! lands in `syscall_entry` (see `syscall.asm`). The entry stub switches
+//! to the kernel stack of the running thread (taken from the TSS) and
+//! calls `dispatch()`, which looks up the handler in a table much like
+//! the interrupt dispatch table in `irq`.
+//!
+//! # Calling convention
+//! System call number in `rax`, up to three arguments in `rdi`, `rsi`
+//! and `rdx`. The result comes back in `rax`; negative values are
+//! errors (see the `E_*` constants). `rcx` and `r11` are clobbered.
+//!
+//! | Number | Call                        | Returns            |
+//! |--------|-----------------------------|--------------------|
+//! | 0      | `write(fd, buf, len)`       | bytes written      |
+//! | 1      | `read(fd, buf, len)`        | bytes read         |
+//! | 2      | `exit(code)`                | does not return    |
+//! | 3      | `sleep(ms)`                 | 0                  |
+//! | 4      | `port_create(capacity)`     | port number        |
+//! | 5      | `port_send(port, msg)`      | 0                  |
+//! | 6      | `port_recv(port, msg, ms)`  | 0                  |
+//! | 7      | `port_destroy(port)`        | 0                  |
+//! | 8      | `tty_mode(raw)`             | 0                  |
+//!
+//! Port messages (see `ipc::port`) are four machine words, passed by
+//! pointer. `port_recv` waits at most `ms` milliseconds for a message;
+//! 0 means don't wait, and `!0` wait forever.
+//!
+//! Standard input is the console TTY (see `tty`). A program interrupted
+//! with Ctrl-C exits with `EXIT_INTERRUPTED` when its current system
+//! call is done, or at the next timer tick if it is running.
+
+use core::{mem, slice};
+
+use arch::x86_64::gdt;
+use ipc::{self, IpcError, Message, PortId};
+use memory;
+use msr;
+use sched;
+use tty::{self, Mode, ReadError};
+use vga_buffer;
+
+/// Write to a file descriptor
+pub const SYS_WRITE: usize = 0;
+/// Read from a file descriptor
+pub const SYS_READ: usize = 1;
+/// Terminate the calling thread
+pub const SYS_EXIT: usize = 2;
+/// Sleep for a number of milliseconds
+pub const SYS_SLEEP: usize = 3;
+/// Create a message port
+pub const SYS_PORT_CREATE: usize = 4;
+/// Send a message to a port
+pub const SYS_PORT_SEND: usize = 5;
+/// Receive a message from a port
+pub const SYS_PORT_RECV: usize = 6;
+/// Destroy a port
+pub const SYS_PORT_DESTROY: usize = 7;
+/// Switch the console between line at a time and raw input
+pub const SYS_TTY_MODE: usize = 8;
+
+/// Number of entries in the system call table.
+const NUM_SYSCALLS: usize = 9;
+
+/// No such system call
+pub const E_NOSYS: isize = -1;
+/// Bad address
+pub const E_FAULT: isize = -2;
+/// Bad file descriptor
+pub const E_BADF: isize = -3;
+/// No such port
+pub const E_NOENT: isize = -4;
+/// Try again: no message, or no room for one
+pub const E_AGAIN: isize = -5;
+/// Invalid argument
+pub const E_INVAL: isize = -6;
+/// Interrupted by Ctrl-C
+pub const E_INTR: isize = -7;
+
+/// Exit code of a program stopped by Ctrl-C, as in Unix shells
+pub const EXIT_INTERRUPTED: usize = 130;
+
+/// `port_recv` timeout meaning "wait forever"
+const WAIT_FOREVER: usize = !0;
+
+/// Standard input: the console TTY
+const FD_STDIN: usize = 0;
+/// Standard output: the console
+const FD_STDOUT: usize = 1;
+/// Standard error: also the console
+const FD_STDERR: usize = 2;
+
+/// RFLAGS bits to clear on `SYSCALL`: trap, interrupt enable and
+/// direction.
+const SYSCALL_RFLAGS_MASK: u32 = 0x100 | 0x200 | 0x400;
+
+/// A system call handler takes three arguments and returns a result.
+type SyscallFn = fn(usize, usize, usize) -> isize;
+
+/// The system call table, indexed by system call number.
+static SYSCALL_TABLE: [SyscallFn; NUM_SYSCALLS] = [
+    sys_write,
+    sys_read,
+    sys_exit,
+    sys_sleep,
+    sys_port_create,
+    sys_port_send,
+    sys_port_recv,
+    sys_port_destroy,
+    sys_tty_mode,
+];
+
+extern {
+    fn syscall_entry();
+    fn jump_to_user_mode(entry: usize, stack_pointer: usize) -> !;
+}
+
+/// Enable the `SYSCALL` instruction and point it at our entry stub.
+/// Requires the GDT from `gdt::init()`.
+pub fn init() {
+    unsafe {
+        let efer = msr::read_msr(msr::EFER);
+        msr::write_msr(msr::EFER, (efer >> 32) as u32,
+                       (efer | msr::EFER_SCE) as u32);
+
+        // Kernel CS/SS are taken from bits 32-47, the user ones are
+        // computed from bits 48-63 (+16 for CS, +8 for SS).
+        let sysret_base = (gdt::KERNEL_DATA as u32) << 16;
+        msr::write_msr(msr::STAR, sysret_base | gdt::KERNEL_CODE as u32, 0);
+
+        let entry = syscall_entry as u64;
+        msr::write_msr(msr::LSTAR, (entry >> 32) as u32, entry as u32);
+
+        msr::write_msr(msr::SFMASK, 0, SYSCALL_RFLAGS_MASK);
+    }
+}
+
+/// Leave the kernel and continue executing user code at `entry`, with
+/// the stack pointer set to `stack_pointer`. Both must be mapped user
+/// accessible in the current address space.
+///
+/// Interrupts and system calls will come back in on the kernel stack of
+/// the calling thread, starting from its top.
+pub unsafe fn enter_user_mode(entry: usize, stack_pointer: usize) -> ! {
+    jump_to_user_mode(entry, stack_pointer)
+}
+
+/// Run system call `num` with the given arguments. Called from the entry
+/// stub, with interrupts enabled.
+pub fn dispatch(num: usize, arg1: usize, arg2: usize, arg3: usize) -> isize {
+    let result = if num < NUM_SYSCALLS {
+        SYSCALL_TABLE[num](arg1, arg2, arg3)
+    } else {
+        E_NOSYS
+    };
+
+    // Don't go back to a program stopped with Ctrl-C
+    if sched::interrupted() {
+        sched::exit(EXIT_INTERRUPTED);
+    }
+    result
+}
+
+/// `write(fd, buf, len)`: write `len` bytes from `buf` to the console.
+fn sys_write(fd: usize, buf: usize, len: usize) -> isize {
+    if fd != FD_STDOUT && fd != FD_STDERR {
+        return E_BADF;
+    }
+    if !memory::user_range_accessible(buf, len, false) {
+        return E_FAULT;
+    }
+
+    let bytes = unsafe { slice::from_raw_parts(buf as *const u8, len) };
+    let mut writer = vga_buffer::writer();
+    for &byte in bytes {
+        writer.write_byte(byte);
+    }
+
+    len as isize
+}
+
+/// `read(fd, buf, len)`: read at most `len` bytes of console input into
+/// `buf`, sleeping until there is at least one. Returns 0 at end of
+/// file.
+fn sys_read(fd: usize, buf: usize, len: usize) -> isize {
+    if fd != FD_STDIN {
+        return E_BADF;
+    }
+    if !memory::user_range_accessible(buf, len, true) {
+        return E_FAULT;
+    }
+
+    let bytes = unsafe { slice::from_raw_parts_mut(buf as *mut u8, len) };
+    match tty::read(bytes) {
+        Ok(count) => count as isize,
+        Err(ReadError::Interrupted) => E_INTR,
+        Err(ReadError::EndOfFile) => 0,
+    }
+}
+
+/// `exit(code)`: terminate the calling thread.
+fn sys_exit(code: usize, _: usize, _: usize) -> isize {
+    sched::exit(code)
+}
+
+/// `sleep(ms)`: sleep for (at least) `ms` milliseconds.
+fn sys_sleep(ms: usize, _: usize, _: usize) -> isize {
+    sched::sleep(ms);
+    0
+}
+
+/// The system call error for a failed port operation.
+fn ipc_error(error: IpcError) -> isize {
+    match error {
+        IpcError::Disconnected => E_NOENT,
+        IpcError::Full | IpcError::Empty | IpcError::Timeout => E_AGAIN,
+    }
+}
+
+/// `port_create(capacity)`: create a port holding at most `capacity`
+/// messages.
+fn sys_port_create(capacity: usize, _: usize, _: usize) -> isize {
+    match ipc::port::create(capacity) {
+        Some(PortId(id)) => id as isize,
+        None => E_INVAL,
+    }
+}
+
+/// `port_send(port, msg)`: send the message at `msg` to `port`, waiting
+/// for room if needed.
+fn sys_port_send(port: usize, msg: usize, _: usize) -> isize {
+    if !memory::user_range_accessible(msg, mem::size_of::<Message>(), false) {
+        return E_FAULT;
+    }
+
+    let message = unsafe { *(msg as *const Message) };
+    match ipc::port::send(PortId(port), message) {
+        Ok(()) => 0,
+        Err(error) => ipc_error(error),
+    }
+}
+
+/// `port_recv(port, msg, ms)`: receive a message from `port` into `msg`,
+/// waiting at most `ms` milliseconds for one.
+fn sys_port_recv(port: usize, msg: usize, ms: usize) -> isize {
+    if !memory::user_range_accessible(msg, mem::size_of::<Message>(), true) {
+        return E_FAULT;
+    }
+
+    let timeout = if ms == WAIT_FOREVER { None } else { Some(ms) };
+    match ipc::port::recv(PortId(port), timeout) {
+        Ok(message) => {
+            unsafe { *(msg as *mut Message) = message };
+            0
+        },
+        Err(error) => ipc_error(error),
+    }
+}
+
+/// `port_destroy(port)`: destroy `port`.
+fn sys_port_destroy(port: usize, _: usize, _: usize) -> isize {
+    if ipc::port::destroy(PortId(port)) { 0 } else { E_NOENT }
+}
+
+/// `tty_mode(raw)`: switch the console to raw input if `raw` is 1, or
+/// back to line at a time if it is 0. Goes back to line at a time by
+/// itself when the program exits.
+fn sys_tty_mode(raw: usize, _: usize, _: usize) -> isize {
+    tty::set_mode(match raw {
+        0 => Mode::Canonical,
+        1 => Mode::Raw,
+        _ => return E_INVAL,
+    });
+    0
+}
@@ -13,6 +13,11 @@ mod pit;
 mod apict;
 
 use io::{send_LAPIC_EOI};
+use irq;
+use memory;
+use profile;
+use sched;
+use syscall::EXIT_INTERRUPTED;
 
 /// A tick counter
 static mut TICK_COUNTER : usize = 0;
@@ -43,7 +48,6 @@ pub fn busy_sleep(ms : usize) {
 /// Function to call when the timer times out.
 /// Argument is ignored.
 unsafe fn handle_timeout(_iv : usize) {
-    //println!("Timer reset! Now at {}", TICK_COUNTER);
 
     unsafe {
 
@@ -51,8 +55,24 @@ unsafe fn handle_timeout(_iv : usize) {
         TICK_COUNTER += 1;
     }
 
+    profile::timer_tick(get_ticks());
+
+    // Look now: other interrupts will have come by the time `tick()`
+    // returns.
+    let from_user = irq::interrupted().rip >= memory::USER_SPACE_START;
+
     // Send the End-of-Interrupt (EOI) signal to LAPIC:
     send_LAPIC_EOI();
+
+    // Let the scheduler preempt the running thread. This must come
+    // after the EOI, as we might not return here for a while.
+    sched::tick();
+
+    // Don't go back to a program stopped with Ctrl-C, even if it
+    // never makes a system call
+    if from_user && sched::interrupted() {
+        sched::exit(EXIT_INTERRUPTED);
+    }
 }
 
 /// Get the global tick count since the timer was started.
@@ -0,0 +1,201 @@
+//! The line being edited in canonical mode.
+//!
+//! Every change to the line is echoed as the text that makes the screen
+//! match it, given that the screen cursor is where the line's cursor
+//! is. In that text, `BACK` moves the screen cursor one step left
+//! without erasing anything.
+
+use collections::String;
+use collections::vec::Vec;
+
+/// Moves the cursor one step left, in echoed text.
+pub const BACK: char = '\x08';
+
+/// A line of input and the cursor position in it.
+pub struct Line {
+    chars: Vec<char>,
+    /// Index in `chars` of the character after the cursor
+    cursor: usize,
+}
+
+impl Line {
+    pub fn new() -> Line {
+        Line { chars: Vec::new(), cursor: 0 }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chars.is_empty()
+    }
+
+    /// The whole line.
+    pub fn text(&self) -> String {
+        self.chars.iter().cloned().collect()
+    }
+
+    /// Forget the line, without echoing anything.
+    pub fn clear(&mut self) {
+        self.chars.clear();
+        self.cursor = 0;
+    }
+
+    /// Insert `c` at the cursor.
+    pub fn insert(&mut self, c: char, echo: &mut FnMut(char)) {
+        self.chars.insert(self.cursor, c);
+        self.cursor += 1;
+        let from = self.cursor - 1;
+        self.redraw_tail(from, 0, echo);
+    }
+
+    /// Delete the character before the cursor.
+    pub fn backspace(&mut self, echo: &mut FnMut(char)) {
+        if self.cursor > 0 {
+            let cursor = self.cursor;
+            self.remove(cursor - 1, cursor, echo);
+        }
+    }
+
+    /// Delete the character under the cursor.
+    pub fn delete(&mut self, echo: &mut FnMut(char)) {
+        if self.cursor < self.chars.len() {
+            let cursor = self.cursor;
+            self.remove(cursor, cursor + 1, echo);
+        }
+    }
+
+    /// Delete the word before the cursor, and the spaces after it.
+    pub fn delete_word(&mut self, echo: &mut FnMut(char)) {
+        let mut start = self.cursor;
+        while start > 0 && self.chars[start - 1] == ' ' {
+            start -= 1;
+        }
+        while start > 0 && self.chars[start - 1] != ' ' {
+            start -= 1;
+        }
+        let cursor = self.cursor;
+        self.remove(start, cursor, echo);
+    }
+
+    /// Delete everything before the cursor.
+    pub fn kill(&mut self, echo: &mut FnMut(char)) {
+        let cursor = self.cursor;
+        self.remove(0, cursor, echo);
+    }
+
+    /// Replace the whole line with `text`, leaving the cursor at the end.
+    pub fn replace(&mut self, text: &str, echo: &mut FnMut(char)) {
+        self.end(echo);
+        let len = self.chars.len();
+        self.remove(0, len, echo);
+        for c in text.chars() {
+            self.insert(c, echo);
+        }
+    }
+
+    pub fn left(&mut self, echo: &mut FnMut(char)) {
+        if self.cursor > 0 {
+            self.cursor -= 1;
+            echo(BACK);
+        }
+    }
+
+    /// Move right, by echoing the character we move past.
+    pub fn right(&mut self, echo: &mut FnMut(char)) {
+        if self.cursor < self.chars.len() {
+            echo(self.chars[self.cursor]);
+            self.cursor += 1;
+        }
+    }
+
+    pub fn home(&mut self, echo: &mut FnMut(char)) {
+        while self.cursor > 0 {
+            self.left(echo);
+        }
+    }
+
+    pub fn end(&mut self, echo: &mut FnMut(char)) {
+        while self.cursor < self.chars.len() {
+            self.right(echo);
+        }
+    }
+
+    /// Remove the characters from `start` up to `end`, where the cursor
+    /// is at `end`, leaving it at `start`.
+    fn remove(&mut self, start: usize, end: usize, echo: &mut FnMut(char)) {
+        if start == end {
+            return;
+        }
+        for _ in start..end {
+            echo(BACK);
+        }
+        self.chars.drain(start..end);
+        self.cursor = start;
+        self.redraw_tail(start, end - start, echo);
+    }
+
+    /// Echo the characters from index `from`, where the screen cursor
+    /// is, to the end of the line, blank out the `erase` characters after
+    /// them which are no longer part of the line, and move back to the
+    /// cursor.
+    fn redraw_tail(&mut self, from: usize, erase: usize, echo: &mut FnMut(char)) {
+        for &c in &self.chars[from..] {
+            echo(c);
+        }
+        for _ in 0..erase {
+            echo(' ');
+        }
+        for _ in 0..(self.chars.len() - self.cursor + erase) {
+            echo(BACK);
+        }
+    }
+}
+
+
+#[cfg(test)]
+fn edit<F>(line: &mut Line, action: F) -> String where F: FnOnce(&mut Line, &mut FnMut(char)) {
+    let mut echoed = String::new();
+    action(line, &mut |c| echoed.push(c));
+    echoed
+}
+
+#[test]
+/// Typing in the middle of the line redraws the rest of it
+fn line_insert_middle() {
+    let mut line = Line::new();
+    edit(&mut line, |l, e| { l.insert('a', e); l.insert('c', e); l.left(e) });
+    assert_eq!(edit(&mut line, |l, e| l.insert('b', e)), "bc\x08");
+    assert_eq!(line.text(), "abc");
+}
+
+#[test]
+/// Backspace in the middle of the line blanks out the last character
+fn line_backspace_middle() {
+    let mut line = Line::new();
+    edit(&mut line, |l, e| { l.replace("abc", e); l.left(e) });
+    assert_eq!(edit(&mut line, |l, e| l.backspace(e)), "\x08c \x08\x08");
+    assert_eq!(line.text(), "ac");
+}
+
+#[test]
+/// Ctrl-W takes the last word and the spaces after it, Ctrl-U the rest
+fn line_delete_word_and_kill() {
+    let mut line = Line::new();
+    edit(&mut line, |l, e| l.replace("echo hello  ", e));
+    edit(&mut line, |l, e| l.delete_word(e));
+    assert_eq!(line.text(), "echo ");
+    edit(&mut line, |l, e| l.kill(e));
+    assert!(line.is_empty());
+}
+
+#[test]
+/// Ctrl-U and history recall on an empty line echo nothing
+fn line_empty() {
+    let mut line = Line::new();
+    assert_eq!(edit(&mut line, |l, e| l.kill(e)), "");
+    assert_eq!(edit(&mut line, |l, e| l.delete_word(e)), "");
+    assert_eq!(edit(&mut line, |l, e| l.replace("ps", e)), "ps");
+    assert_eq!(line.text(), "ps");
+
+    edit(&mut line, |l, e| l.home(e));
+    assert_eq!(edit(&mut line, |l, e| l.kill(e)), "");
+    assert_eq!(line.text(), "ps");
+}
@@ -0,0 +1,521 @@
+//! # Terminal
+//!
+//! The console TTYs: input from the keyboard and the serial port, output
+//! to the VGA screen. Every virtual console (see `vga_buffer`) has a
+//! TTY of its own, used by the threads on that console, and what is
+//! typed goes to the one on the screen. Alt+F1 to Alt+F6 switch between
+//! them.
+//!
+//! `tty_thread` runs the _line discipline_ on everything typed, whether
+//! anyone is reading or not, so Ctrl-C works on a program that never
+//! reads.
+//!
+//! In _canonical_ mode (the default) input is handed out a line at a
+//! time, with editing and echo:
+//!
+//! + Left, Right, Home and End move the cursor within the line
+//! + Backspace and Delete erase a character
+//! + Ctrl-W erases the word before the cursor, Ctrl-U everything before
+//!   the cursor
+//! + Up and Down browse the lines entered at the shell
+//! + Ctrl-C throws away the line and interrupts the foreground task
+//! + Ctrl-D on an empty line is end of file
+//!
+//! In _raw_ mode, for full-screen programs, everything is handed out
+//! as typed, without echo. Special keys come as the escape sequences of
+//! a VT100 terminal (`\x1b[A` for Up, and so on), Ctrl-C as `\x03`.
+//!
+//! Shift+PageUp and Shift+PageDown page through the console scrollback.
+//!
+//! The foreground task (see `set_foreground()`) is interrupted with
+//! `sched::interrupt()`, and terminated on its way back to user mode,
+//! from a system call or the timer interrupt. When there is no
+//! foreground task, whoever reads sees `ReadError::Interrupted` instead.
+
+use collections::String;
+use collections::vec::Vec;
+use collections::vec_deque::VecDeque;
+use core::fmt::Write;
+use spin;
+
+use input::{self, Event};
+use io::kbd::{KeyCode, ALT, SHIFT};
+use irq;
+use sched::{self, ThreadId};
+use sync::WaitQueue;
+use vga_buffer::{self, NUM_CONSOLES};
+
+mod line;
+
+use self::line::{Line, BACK};
+
+/// Number of lines kept for Up and Down.
+const HISTORY_SIZE: usize = 64;
+
+const CTRL_C: char = '\x03';
+const CTRL_D: char = '\x04';
+const CTRL_U: char = '\x15';
+const CTRL_W: char = '\x17';
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    /// Line at a time, with editing and echo
+    Canonical,
+    /// Byte at a time, as typed
+    Raw,
+}
+
+/// Why a read came back without input.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReadError {
+    /// Ctrl-C was pressed
+    Interrupted,
+    /// Ctrl-D was pressed on an empty line
+    EndOfFile,
+}
+
+/// Something typed, from either the keyboard or the serial port.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Input {
+    Char(char),
+    Key(KeyCode),
+}
+
+struct Tty {
+    mode: Mode,
+    /// The line being edited, in canonical mode
+    line: Line,
+    /// Input ready to be read: finished lines in canonical mode,
+    /// everything in raw mode
+    ready: VecDeque<char>,
+    /// Ctrl-D was pressed, and no reader has seen it yet
+    eof: bool,
+    /// Ctrl-C was pressed without a foreground task, and no reader has
+    /// seen it yet
+    interrupted: bool,
+    foreground: Option<ThreadId>,
+    /// Lines entered while there was no foreground task, oldest first
+    history: VecDeque<String>,
+    /// Index in `history` of the line shown; `history.len()` when
+    /// editing a new line
+    history_pos: usize,
+    /// The new line, kept while browsing the history
+    saved: String,
+}
+
+/// The TTY of every virtual console. Only locked with interrupts
+/// disabled, as readers check them with interrupts disabled before
+/// going to sleep.
+static TTYS: [spin::Mutex<Option<Tty>>; NUM_CONSOLES] = [
+    spin::Mutex::new(None), spin::Mutex::new(None), spin::Mutex::new(None),
+    spin::Mutex::new(None), spin::Mutex::new(None), spin::Mutex::new(None),
+];
+
+/// Readers of each console sleep here until there is input for them.
+static READERS: [WaitQueue; NUM_CONSOLES] = [
+    WaitQueue::new(), WaitQueue::new(), WaitQueue::new(),
+    WaitQueue::new(), WaitQueue::new(), WaitQueue::new(),
+];
+
+/// Run `f` on the TTY of console `console`. Interrupts must be
+/// disabled.
+fn locked<F, T>(console: usize, f: F) -> T where F: FnOnce(&mut Tty) -> T {
+    let mut tty = TTYS[console].lock();
+    if tty.is_none() {
+        *tty = Some(Tty::new());
+    }
+    f(tty.as_mut().unwrap())
+}
+
+/// Run `f` on the TTY of console `console`, with interrupts disabled.
+fn with_tty<F, T>(console: usize, f: F) -> T where F: FnOnce(&mut Tty) -> T {
+    irq::without_interrupts(|| locked(console, f))
+}
+
+impl Tty {
+    fn new() -> Tty {
+        Tty {
+            mode: Mode::Canonical,
+            line: Line::new(),
+            ready: VecDeque::new(),
+            eof: false,
+            interrupted: false,
+            foreground: None,
+            history: VecDeque::new(),
+            history_pos: 0,
+            saved: String::new(),
+        }
+    }
+
+    /// Run the line discipline on `input`, echoing with `echo`.
+    fn handle(&mut self, input: Input, echo: &mut FnMut(char)) {
+        match self.mode {
+            Mode::Raw => match input {
+                Input::Char(c) => self.ready.push_back(c),
+                Input::Key(key) => if let Some(sequence) = escape_sequence(key) {
+                    self.ready.extend(sequence.chars());
+                },
+            },
+            Mode::Canonical => self.edit(input, echo),
+        }
+    }
+
+    /// Canonical mode: edit the line, and hand it out on Enter.
+    fn edit(&mut self, input: Input, echo: &mut FnMut(char)) {
+        match input {
+            Input::Char('\n') => {
+                self.line.end(echo);
+                echo('\n');
+                let text = self.line.text();
+                self.line.clear();
+
+                if self.foreground.is_none() && !text.is_empty()
+                    && self.history.back() != Some(&text) {
+                    if self.history.len() == HISTORY_SIZE {
+                        self.history.pop_front();
+                    }
+                    self.history.push_back(text.clone());
+                }
+                self.history_pos = self.history.len();
+
+                self.ready.extend(text.chars());
+                self.ready.push_back('\n');
+            },
+            Input::Char(CTRL_C) => self.interrupt(echo),
+            Input::Char(CTRL_D) => if self.line.is_empty() {
+                self.eof = true;
+            },
+            Input::Char(CTRL_U) => self.line.kill(echo),
+            Input::Char(CTRL_W) => self.line.delete_word(echo),
+            Input::Char(c) if c >= ' ' && c != '\x7f' => self.line.insert(c, echo),
+            Input::Char(_) => {},
+
+            Input::Key(KeyCode::Backspace) => self.line.backspace(echo),
+            Input::Key(KeyCode::Delete) => self.line.delete(echo),
+            Input::Key(KeyCode::Left) => self.line.left(echo),
+            Input::Key(KeyCode::Right) => self.line.right(echo),
+            Input::Key(KeyCode::Home) => self.line.home(echo),
+            Input::Key(KeyCode::End) => self.line.end(echo),
+            Input::Key(KeyCode::Up) => if self.history_pos > 0 {
+                if self.history_pos == self.history.len() {
+                    self.saved = self.line.text();
+                }
+                self.history_pos -= 1;
+                self.line.replace(&self.history[self.history_pos], echo);
+            },
+            Input::Key(KeyCode::Down) => if self.history_pos < self.history.len() {
+                self.history_pos += 1;
+                let text = if self.history_pos == self.history.len() {
+                    self.saved.clone()
+                } else {
+                    self.history[self.history_pos].clone()
+                };
+                self.line.replace(&text, echo);
+            },
+            Input::Key(_) => {},
+        }
+    }
+
+    /// Ctrl-C: throw away what hasn't been read, and interrupt the
+    /// foreground task, or the next read if there is none.
+    fn interrupt(&mut self, echo: &mut FnMut(char)) {
+        self.line.end(echo);
+        for c in "^C\n".chars() {
+            echo(c);
+        }
+        self.line.clear();
+        self.ready.clear();
+        self.history_pos = self.history.len();
+
+        match self.foreground {
+            Some(id) => sched::interrupt(id),
+            None => self.interrupted = true,
+        }
+    }
+
+    /// Any reason for a reader to stop waiting.
+    fn readable(&self) -> bool {
+        !self.ready.is_empty() || self.eof || self.interrupted || sched::interrupted()
+    }
+
+    /// The reason a reader got nothing, if it is one.
+    fn take_error(&mut self) -> Option<ReadError> {
+        if sched::interrupted() {
+            Some(ReadError::Interrupted)
+        } else if self.interrupted {
+            self.interrupted = false;
+            Some(ReadError::Interrupted)
+        } else if self.ready.is_empty() && self.eof {
+            self.eof = false;
+            Some(ReadError::EndOfFile)
+        } else {
+            None
+        }
+    }
+}
+
+/// The VT100 escape sequence a special key sends, in raw mode.
+fn escape_sequence(key: KeyCode) -> Option<&'static str> {
+    Some(match key {
+        KeyCode::Up => "\x1b[A",
+        KeyCode::Down => "\x1b[B",
+        KeyCode::Right => "\x1b[C",
+        KeyCode::Left => "\x1b[D",
+        KeyCode::Home => "\x1b[H",
+        KeyCode::End => "\x1b[F",
+        KeyCode::Insert => "\x1b[2~",
+        KeyCode::Delete => "\x1b[3~",
+        KeyCode::PageUp => "\x1b[5~",
+        KeyCode::PageDown => "\x1b[6~",
+        KeyCode::Backspace => "\x7f",
+        KeyCode::Escape => "\x1b",
+        _ => return None,
+    })
+}
+
+/// What a serial terminal is in the middle of sending.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum SerialState {
+    Normal,
+    /// After ESC
+    Escape,
+    /// After ESC [ or ESC O, with the number so far
+    Sequence(u8),
+}
+
+/// Turns what a VT100-like terminal on the serial port sends into
+/// `Input`s, for canonical mode.
+struct SerialDecoder {
+    state: SerialState,
+}
+
+impl SerialDecoder {
+    fn new() -> SerialDecoder {
+        SerialDecoder { state: SerialState::Normal }
+    }
+
+    fn feed(&mut self, byte: u8) -> Option<Input> {
+        match (self.state, byte) {
+            (SerialState::Normal, 0x1B) => self.state = SerialState::Escape,
+            (SerialState::Normal, b'\r') => return Some(Input::Char('\n')),
+            (SerialState::Normal, 0x7F) | (SerialState::Normal, 0x08) =>
+                return Some(Input::Key(KeyCode::Backspace)),
+            (SerialState::Normal, byte) => return Some(Input::Char(byte as char)),
+
+            (SerialState::Escape, b'[') | (SerialState::Escape, b'O') =>
+                self.state = SerialState::Sequence(0),
+            (SerialState::Escape, _) => self.state = SerialState::Normal,
+
+            (SerialState::Sequence(n), b'0'...b'9') =>
+                self.state = SerialState::Sequence(n.saturating_mul(10)
+                                                   .saturating_add(byte - b'0')),
+            (SerialState::Sequence(n), _) => {
+                self.state = SerialState::Normal;
+                let key = match (n, byte) {
+                    (_, b'A') => KeyCode::Up,
+                    (_, b'B') => KeyCode::Down,
+                    (_, b'C') => KeyCode::Right,
+                    (_, b'D') => KeyCode::Left,
+                    (_, b'H') | (1, b'~') | (7, b'~') => KeyCode::Home,
+                    (_, b'F') | (4, b'~') | (8, b'~') => KeyCode::End,
+                    (2, b'~') => KeyCode::Insert,
+                    (3, b'~') => KeyCode::Delete,
+                    (5, b'~') => KeyCode::PageUp,
+                    (6, b'~') => KeyCode::PageDown,
+                    _ => return None,
+                };
+                return Some(Input::Key(key));
+            },
+        }
+        None
+    }
+}
+
+/// Show echoed text on console `console`.
+fn echo(console: usize, text: &str) {
+    let mut writer = vga_buffer::console(console);
+    for c in text.chars() {
+        if c == BACK {
+            writer.move_left();
+        } else {
+            let _ = writer.write_char(c);
+        }
+    }
+}
+
+/// Thread entry point: run the line discipline on everything typed.
+pub fn tty_thread(_arg: usize) {
+    let input = input::subscribe(input::KEY | input::CHAR | input::SERIAL);
+    let mut serial = SerialDecoder::new();
+
+    loop {
+        let event = input.read().event;
+        // Typing goes to the console on the screen
+        let console = vga_buffer::active();
+
+        let typed = match event {
+            // Alt+F1 to Alt+F6 switch consoles
+            Event::Key(key) if key.pressed && key.modifiers.contains(ALT)
+                && function_key(key.code).is_some() => {
+                vga_buffer::switch_to(function_key(key.code).unwrap());
+                continue;
+            },
+            // Shift+PageUp/PageDown look through the scrollback, in
+            // either mode
+            Event::Key(key) if key.pressed && key.modifiers.contains(SHIFT)
+                && (key.code == KeyCode::PageUp || key.code == KeyCode::PageDown) => {
+                if key.code == KeyCode::PageUp {
+                    vga_buffer::page_up();
+                } else {
+                    vga_buffer::page_down();
+                }
+                continue;
+            },
+            Event::Key(key) if key.pressed => Input::Key(key.key()),
+            Event::Char(c) => Input::Char(c),
+            Event::Serial(byte) => {
+                // Raw mode gets escape sequences as they are
+                if with_tty(console, |tty| tty.mode) == Mode::Raw {
+                    Input::Char(byte as char)
+                } else {
+                    match serial.feed(byte) {
+                        Some(typed) => typed,
+                        None => continue,
+                    }
+                }
+            },
+            _ => continue,
+        };
+
+        // Echo once the lock is released, as printing may have to wait
+        let mut echoed = String::new();
+        with_tty(console, |tty| tty.handle(typed, &mut |c| echoed.push(c)));
+        echo(console, &echoed);
+
+        READERS[console].wake_all();
+    }
+}
+
+/// The console number for function key `code`: 0 for F1, and so on.
+fn function_key(code: KeyCode) -> Option<usize> {
+    use io::kbd::KeyCode::*;
+
+    [F1, F2, F3, F4, F5, F6].iter().position(|&key| key == code)
+}
+
+/// Wait until there is something for a reader, and take it with `f`.
+fn read_with<F, T>(f: F) -> Result<T, ReadError> where F: FnOnce(&mut Tty) -> T {
+    let mut result = None;
+    let mut f = Some(f);
+    let console = sched::console();
+
+    READERS[console].wait_until(|| locked(console, |tty| {
+        if !tty.readable() {
+            return false;
+        }
+        result = Some(match tty.take_error() {
+            Some(error) => Err(error),
+            None => Ok((f.take().unwrap())(tty)),
+        });
+        true
+    }));
+
+    result.unwrap()
+}
+
+/// Read a line, without the newline. Waits for a whole line, even in
+/// raw mode.
+pub fn read_line() -> Result<String, ReadError> {
+    let mut line = String::new();
+    loop {
+        let done = try!(read_with(|tty| {
+            while let Some(c) = tty.ready.pop_front() {
+                if c == '\n' {
+                    return true;
+                }
+                line.push(c);
+            }
+            false
+        }));
+        if done {
+            return Ok(line);
+        }
+    }
+}
+
+/// Read what is available into `buf`, at least one byte, one byte per
+/// character. Characters that don't fit in a byte are skipped. Returns
+/// 0 at end of file.
+pub fn read(buf: &mut [u8]) -> Result<usize, ReadError> {
+    if buf.is_empty() {
+        return Ok(0);
+    }
+
+    loop {
+        let result = read_with(|tty| {
+            let mut count = 0;
+            while count < buf.len() {
+                match tty.ready.pop_front() {
+                    Some(c) if (c as u32) < 0x100 => {
+                        buf[count] = c as u8;
+                        count += 1;
+                    },
+                    Some(_) => {},
+                    None => break,
+                }
+            }
+            count
+        });
+
+        match result {
+            Ok(0) => {},
+            Err(ReadError::EndOfFile) => return Ok(0),
+            result => return result,
+        }
+    }
+}
+
+/// Switch between canonical and raw mode. Anything typed but not yet
+/// read is kept, except a half-edited line.
+pub fn set_mode(mode: Mode) {
+    with_tty(sched::console(), |tty| {
+        tty.mode = mode;
+        tty.line.clear();
+    });
+}
+
+/// Make `task` the one interrupted by Ctrl-C, or nobody. Also goes back
+/// to canonical mode and throws away unread input, as that belonged to
+/// the previous foreground task.
+pub fn set_foreground(task: Option<ThreadId>) {
+    with_tty(sched::console(), |tty| {
+        tty.foreground = task;
+        tty.mode = Mode::Canonical;
+        tty.ready.clear();
+        tty.eof = false;
+        tty.interrupted = false;
+    });
+}
+
+/// The lines entered while there was no foreground task, oldest first.
+pub fn history() -> Vec<String> {
+    with_tty(sched::console(), |tty| tty.history.iter().cloned().collect())
+}
+
+
+#[test]
+/// Arrow keys and Home/End from a serial terminal
+fn serial_escape_sequences() {
+    let mut decoder = SerialDecoder::new();
+    let mut decoded = [None; 3];
+    let mut count = 0;
+    for &byte in b"\x1b[Ax\x1b[4~" {
+        if let Some(input) = decoder.feed(byte) {
+            decoded[count] = Some(input);
+            count += 1;
+        }
+    }
+    assert_eq!(decoded, [Some(Input::Key(KeyCode::Up)),
+                         Some(Input::Char('x')),
+                         Some(Input::Key(KeyCode::End))]);
+}
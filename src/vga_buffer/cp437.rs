@@ -0,0 +1,75 @@
+//! Code page 437, the character set of the VGA text mode font.
+//!
+//! Besides ASCII it has accented Latin letters, box drawing, some Greek
+//! and some maths. The bytes below 0x20 have pictures too (smileys,
+//! arrows, card suits), but only the ones that aren't also control
+//! characters can be written.
+
+/// The character for every byte of the font.
+const CP437: [char; 256] = [
+    // 0x00: shows as a blank
+    ' ',  '☺', '☻', '♥', '♦', '♣', '♠', '•', '◘', '○', '◙', '♂', '♀', '♪', '♫', '☼',
+    '►', '◄', '↕', '‼', '¶', '§', '▬', '↨', '↑', '↓', '→', '←', '∟', '↔', '▲', '▼',
+    ' ',  '!', '"', '#', '$', '%', '&', '\'', '(', ')', '*', '+', ',', '-', '.', '/',
+    '0', '1', '2', '3', '4', '5', '6', '7', '8', '9', ':', ';', '<', '=', '>', '?',
+    '@', 'A', 'B', 'C', 'D', 'E', 'F', 'G', 'H', 'I', 'J', 'K', 'L', 'M', 'N', 'O',
+    'P', 'Q', 'R', 'S', 'T', 'U', 'V', 'W', 'X', 'Y', 'Z', '[', '\\', ']', '^', '_',
+    '`', 'a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'i', 'j', 'k', 'l', 'm', 'n', 'o',
+    'p', 'q', 'r', 's', 't', 'u', 'v', 'w', 'x', 'y', 'z', '{', '|', '}', '~', '⌂',
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{A0}',
+];
+
+/// Characters that look like one in the font, without being it.
+const ALIASES: [(char, u8); 5] = [
+    ('β', 0xE1), // ß
+    ('μ', 0xE6), // µ, the micro sign
+    ('∑', 0xE4), // Σ
+    ('∈', 0xEE), // ε
+    ('Ø', 0xED), // φ
+];
+
+/// Shown for characters the font doesn't have: ■
+pub const REPLACEMENT: u8 = 0xFE;
+
+/// The byte for `c`, if the font has it. ASCII control characters come
+/// out as themselves.
+pub fn from_char(c: char) -> Option<u8> {
+    if (c as u32) < 0x80 {
+        return Some(c as u8);
+    }
+
+    // The pictures for \n, \r and escape would be taken for them
+    for (byte, &glyph) in CP437.iter().enumerate().skip(1) {
+        if glyph == c && byte != 0x0A && byte != 0x0D && byte != 0x1B {
+            return Some(byte as u8);
+        }
+    }
+    ALIASES.iter().find(|&&(alias, _)| alias == c).map(|&(_, byte)| byte)
+}
+
+/// The character shown for `byte`.
+pub fn to_char(byte: u8) -> char {
+    CP437[byte as usize]
+}
+
+
+#[test]
+/// Every character in the font makes it there and back
+fn cp437_round_trip() {
+    for byte in 0x80..0x100 {
+        assert_eq!(from_char(to_char(byte as u8)), Some(byte as u8));
+    }
+    assert_eq!(from_char('å'), Some(0x86));
+    assert_eq!(from_char('╬'), Some(0xCE));
+    assert_eq!(from_char('♥'), Some(0x03));
+    assert_eq!(from_char('\n'), Some(b'\n'));
+    assert_eq!(from_char('←'), None);
+    assert_eq!(from_char('€'), None);
+}
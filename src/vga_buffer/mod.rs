@@ -0,0 +1,809 @@
+//! This module is an interface to the IBM standard VGA Buffer.
+//!
+//! Text is shown in the font's character set, code page 437 (see
+//! `cp437`); anything else comes out as a ■.
+//!
+//! The `Writer` understands the VT100/ANSI escape sequences most
+//! programs use (`CSI` is `ESC [`, _n_ and _m_ are numbers):
+//!
+//! + `CSI n m`: colours (30-37 and 90-97 foreground, 40-47 and 100-107
+//!   background, 1 bold, 0 back to normal)
+//! + `CSI n A`, `B`, `C`, `D`: cursor up, down, forward, back
+//! + `CSI n ; m H`: cursor to row _n_, column _m_, counting from 1
+//! + `CSI n G`: cursor to column _n_
+//! + `CSI n J`: erase the screen after (0), before (1) the cursor, or
+//!   all of it (2)
+//! + `CSI n K`: the same for the cursor's line
+//! + `CSI s` and `CSI u`, or `ESC 7` and `ESC 8`: save and restore the
+//!   cursor position
+//!
+//! Other sequences are swallowed.
+
+use alloc::boxed::Box;
+use collections::String;
+use collections::vec_deque::VecDeque;
+use core::cmp::min;
+use core::ptr::Unique;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::{Mutex, MutexGuard};
+use x86::io::outb;
+
+use sched;
+
+pub mod cp437;
+
+// Proportions of the buffers.
+const BUFFER_HEIGHT: usize = 25;
+const BUFFER_WIDTH: usize = 80;
+
+/// Number of virtual consoles, switched between with Alt+F1 and so on.
+pub const NUM_CONSOLES: usize = 6;
+
+// The CRT controller, which owns the hardware cursor: write a register
+// number to the index port, then its value to the data port.
+const CRTC_INDEX: u16 = 0x3D4;
+const CRTC_DATA: u16 = 0x3D5;
+/// Cursor position (in characters from the top left), high byte
+const CRTC_CURSOR_HIGH: u8 = 0x0E;
+/// Cursor position, low byte
+const CRTC_CURSOR_LOW: u8 = 0x0F;
+
+const ESCAPE: u8 = 0x1B;
+
+/// Parameters of an escape sequence we care about, at most
+const MAX_PARAMS: usize = 4;
+
+/// An enum represented as an 8-bit integer mapping the various VGA BIOS
+/// colours.
+#[allow(dead_code)]
+#[repr(u8)]
+pub enum Color {
+    Black      = 0,
+    Blue       = 1,
+    Green      = 2,
+    Cyan       = 3,
+    Red        = 4,
+    Magenta    = 5,
+    Brown      = 6,
+    LightGray  = 7,
+    DarkGray   = 8,
+    LightBlue  = 9,
+    LightGreen = 10,
+    LightCyan  = 11,
+    LightRed   = 12,
+    Pink       = 13,
+    Yellow     = 14,
+    White      = 15,
+}
+
+const DEFAULT_FOREGROUND: Color = Color::LightBlue;
+const DEFAULT_BACKGROUND: Color = Color::White;
+
+/// The VGA colours for the eight ANSI colours: black, red, green,
+/// yellow, blue, magenta, cyan and white. Add 8 for the bright ones.
+const ANSI_COLORS: [u8; 8] = [
+    Color::Black as u8, Color::Red as u8, Color::Green as u8, Color::Brown as u8,
+    Color::Blue as u8, Color::Magenta as u8, Color::Cyan as u8, Color::LightGray as u8,
+];
+
+/// Funny object: A `ColorCode` is just an 8-bit int.
+#[derive(Clone, Copy)]
+struct ColorCode(u8);
+
+/// ...but notice how we are only implementing this library function for
+/// `ColorCodes`, not u8 in general!
+impl ColorCode {
+    const fn new(foreground: Color, background: Color) -> ColorCode {
+        ColorCode((background as u8) << 4 | (foreground as u8))
+    }
+}
+
+/// Every character has a colour and an ascii character.
+/// The character is a byte of code page 437; see `cp437`.
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct ScreenChar {
+    ascii_character: u8,
+    color_code: ColorCode,
+}
+
+type Line = [ScreenChar; BUFFER_WIDTH];
+
+struct Buffer {
+    chars: [Line; BUFFER_HEIGHT],
+}
+
+/// Lines which have scrolled off the top of the screen, for looking
+/// back at. Lives on the heap, so it can only be set up after memory.
+struct Scrollback {
+    /// Oldest first
+    lines: VecDeque<Line>,
+    /// Most lines to keep
+    depth: usize,
+    /// How many lines back the view is; 0 when showing the live screen
+    offset: usize,
+    /// What the live screen looked like, while showing older lines
+    live: Box<Buffer>,
+}
+
+/// Something the byte stream asks the writer to do.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Action {
+    /// Show a character, or carry out a control character
+    Print(u8),
+    /// `ESC` followed by this byte
+    Escape(u8),
+    /// `CSI`, up to `MAX_PARAMS` numbers (0 if left out), and the
+    /// final byte saying what to do. `private` sequences (`CSI ? ...`)
+    /// belong to other terminals
+    Csi { params: [usize; MAX_PARAMS], count: usize, private: bool, command: u8 },
+}
+
+/// Where in an escape sequence the byte stream is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    Ground,
+    /// After `ESC`
+    Escape,
+    /// After `CSI`, with what has been seen so far
+    Csi { params: [usize; MAX_PARAMS], count: usize, private: bool },
+}
+
+/// Splits a byte stream into characters and escape sequences.
+struct Parser {
+    state: State,
+}
+
+impl Parser {
+    const fn new() -> Parser {
+        Parser { state: State::Ground }
+    }
+
+    fn feed(&mut self, byte: u8) -> Option<Action> {
+        match self.state {
+            State::Ground => if byte == ESCAPE {
+                self.state = State::Escape;
+            } else {
+                return Some(Action::Print(byte));
+            },
+
+            State::Escape => if byte == b'[' {
+                self.state = State::Csi { params: [0; MAX_PARAMS], count: 1, private: false };
+            } else {
+                self.state = State::Ground;
+                return Some(Action::Escape(byte));
+            },
+
+            State::Csi { mut params, mut count, mut private } => {
+                match byte {
+                    b'0'...b'9' => if count <= MAX_PARAMS {
+                        let param = &mut params[count - 1];
+                        *param = param.saturating_mul(10).saturating_add((byte - b'0') as usize);
+                    },
+                    b';' => count += 1,
+                    b'<'...b'?' => private = true,
+                    // Intermediate bytes: nothing we understand uses them
+                    0x20...0x2F => {},
+                    0x40...0x7E => {
+                        self.state = State::Ground;
+                        return Some(Action::Csi {
+                            params: params,
+                            count: min(count, MAX_PARAMS),
+                            private: private,
+                            command: byte,
+                        });
+                    },
+                    // Garbage: give up on the sequence
+                    _ => {
+                        self.state = State::Ground;
+                        return None;
+                    },
+                }
+                self.state = State::Csi { params: params, count: count, private: private };
+            },
+        }
+        None
+    }
+}
+
+/// Parameter `index` of an escape sequence, or `default` if it was left
+/// out or 0.
+fn param(params: &[usize], index: usize, default: usize) -> usize {
+    match params.get(index) {
+        Some(&value) if value > 0 => value,
+        _ => default,
+    }
+}
+
+pub struct Writer {
+    row_position: usize,
+    /// Column of the next character. May be `BUFFER_WIDTH`, when the
+    /// next character goes on a new line
+    column_position: usize,
+    color_code: ColorCode,
+    /// The colours set with escape sequences, as VGA colours
+    foreground: u8,
+    background: u8,
+    /// Bold text is shown in the bright version of its colour
+    bold: bool,
+    /// Position stored by `ESC 7` or `CSI s`
+    saved_position: (usize, usize),
+    parser: Parser,
+    /// The screen
+    buffer: Unique<Buffer>,
+    /// Where the console is drawn while it isn't the one on the screen
+    shadow: Option<Box<Buffer>>,
+    /// True if this is the console on the screen
+    visible: bool,
+    /// The cell (row, column) showing the mouse pointer, if any
+    pointer: Option<(usize, usize)>,
+    scrollback: Option<Scrollback>,
+}
+
+impl Writer {
+
+    /// Write a given byte to the screen.
+    /// Warning! Will only work with single bytes!
+    pub fn write_byte(&mut self, byte: u8) {
+        let action = match self.parser.feed(byte) {
+            Some(action) => action,
+            None => return,
+        };
+
+        self.snap_back();
+        self.toggle_pointer();
+        match action {
+            Action::Print(b'\n') => self.new_line(),
+            Action::Print(b'\r') => self.column_position = 0,
+            Action::Print(byte) => {
+                if self.column_position >= BUFFER_WIDTH {
+                    self.new_line();
+                }
+
+                let row = self.row_position;
+                let col = self.column_position;
+
+                self.buffer().chars[row][col] = ScreenChar {
+                    ascii_character: byte,
+                    color_code: self.color_code,
+                };
+
+                self.column_position += 1;
+            },
+            Action::Escape(b'7') => self.saved_position = self.position(),
+            Action::Escape(b'8') => {
+                let (row, col) = self.saved_position;
+                self.row_position = row;
+                self.column_position = col;
+            },
+            Action::Escape(_) => {},
+            Action::Csi { private: true, .. } => {},
+            Action::Csi { params, count, command, .. } =>
+                self.csi(&params[..count], command),
+        }
+        self.toggle_pointer();
+        self.update_cursor();
+    }
+
+    /// Carry out the escape sequence `CSI params command`.
+    fn csi(&mut self, params: &[usize], command: u8) {
+        let (row, col) = self.position();
+        let n = param(params, 0, 1);
+        let here = row * BUFFER_WIDTH + col;
+        let line_start = row * BUFFER_WIDTH;
+
+        match command {
+            b'A' => self.row_position = row.saturating_sub(n),
+            b'B' => self.row_position = min(row + n, BUFFER_HEIGHT - 1),
+            b'C' => self.column_position = min(col + n, BUFFER_WIDTH - 1),
+            b'D' => self.column_position = col.saturating_sub(n),
+            b'G' => self.column_position = min(n, BUFFER_WIDTH) - 1,
+            b'H' | b'f' => {
+                self.row_position = min(n, BUFFER_HEIGHT) - 1;
+                self.column_position = min(param(params, 1, 1), BUFFER_WIDTH) - 1;
+            },
+            b'J' => match param(params, 0, 0) {
+                0 => self.erase(here, BUFFER_WIDTH * BUFFER_HEIGHT),
+                1 => self.erase(0, here + 1),
+                _ => self.erase(0, BUFFER_WIDTH * BUFFER_HEIGHT),
+            },
+            b'K' => match param(params, 0, 0) {
+                0 => self.erase(here, line_start + BUFFER_WIDTH),
+                1 => self.erase(line_start, here + 1),
+                _ => self.erase(line_start, line_start + BUFFER_WIDTH),
+            },
+            b'm' => self.select_graphic_rendition(params),
+            b's' => self.saved_position = (row, col),
+            b'u' => {
+                let (row, col) = self.saved_position;
+                self.row_position = row;
+                self.column_position = col;
+            },
+            _ => {},
+        }
+    }
+
+    /// `CSI ... m`: change the colours of what is written next.
+    fn select_graphic_rendition(&mut self, params: &[usize]) {
+        // `CSI m` is the same as `CSI 0 m`
+        let reset = [0];
+        let params = if params.is_empty() { &reset[..] } else { params };
+
+        for &param in params {
+            match param {
+                0 => {
+                    self.foreground = DEFAULT_FOREGROUND as u8;
+                    self.background = DEFAULT_BACKGROUND as u8;
+                    self.bold = false;
+                },
+                1 => self.bold = true,
+                22 => self.bold = false,
+                30...37 => self.foreground = ANSI_COLORS[param - 30],
+                39 => self.foreground = DEFAULT_FOREGROUND as u8,
+                40...47 => self.background = ANSI_COLORS[param - 40],
+                49 => self.background = DEFAULT_BACKGROUND as u8,
+                90...97 => self.foreground = ANSI_COLORS[param - 90] + 8,
+                100...107 => self.background = ANSI_COLORS[param - 100] + 8,
+                _ => {},
+            }
+        }
+
+        let foreground = if self.bold { self.foreground | 8 } else { self.foreground };
+        self.color_code = ColorCode(self.background << 4 | foreground);
+    }
+
+    /// Blank the cells from `start` up to `end`, counted from the top
+    /// left corner row by row.
+    fn erase(&mut self, start: usize, end: usize) {
+        let blank = self.blank();
+        for cell in start..min(end, BUFFER_WIDTH * BUFFER_HEIGHT) {
+            self.buffer().chars[cell / BUFFER_WIDTH][cell % BUFFER_WIDTH] = blank;
+        }
+    }
+
+    /// Where the next character goes, as (row, column).
+    pub fn position(&self) -> (usize, usize) {
+        (self.row_position, min(self.column_position, BUFFER_WIDTH - 1))
+    }
+
+    /// Move to `row` and `col`, or as close as the screen allows.
+    pub fn set_position(&mut self, row: usize, col: usize) {
+        self.snap_back();
+        self.row_position = min(row, BUFFER_HEIGHT - 1);
+        self.column_position = min(col, BUFFER_WIDTH - 1);
+        self.update_cursor();
+    }
+
+    /// Blank the whole line the cursor is on, and move to its start.
+    pub fn clear_line(&mut self) {
+        self.column_position = 0;
+        self.clear_to_end();
+    }
+
+    /// Blank the line from the cursor to its end.
+    pub fn clear_to_end(&mut self) {
+        self.snap_back();
+        self.toggle_pointer();
+        let start = self.row_position * BUFFER_WIDTH + self.column_position;
+        let end = (self.row_position + 1) * BUFFER_WIDTH;
+        self.erase(start, end);
+        self.toggle_pointer();
+        self.update_cursor();
+    }
+
+    /// Blank the whole screen, and move to the top left corner.
+    pub fn clear_screen(&mut self) {
+        self.snap_back();
+        for row in 0..BUFFER_HEIGHT {
+            self.clear_row(row);
+        }
+        // The pointer's cell was blanked with the rest
+        self.toggle_pointer();
+        self.row_position = 0;
+        self.column_position = 0;
+        self.update_cursor();
+    }
+
+    /// Keep up to `depth` lines scrolled off the top of the screen, to
+    /// look back at with `scroll_view()`. Needs the heap.
+    pub fn enable_scrollback(&mut self, depth: usize) {
+        self.snap_back();
+        self.scrollback = Some(Scrollback {
+            lines: VecDeque::new(),
+            depth: depth,
+            offset: 0,
+            live: Box::new(Buffer {
+                chars: [[self.blank(); BUFFER_WIDTH]; BUFFER_HEIGHT],
+            }),
+        });
+    }
+
+    /// Move the view `lines` lines back in the scrollback, or forward
+    /// if negative. Output moves it back to the live screen.
+    pub fn scroll_view(&mut self, lines: isize) {
+        let (old, new) = match self.scrollback {
+            Some(ref scrollback) => {
+                let old = scrollback.offset as isize;
+                let new = min(old + lines, scrollback.lines.len() as isize);
+                (old as usize, if new < 0 { 0 } else { new as usize })
+            },
+            None => return,
+        };
+        if new == old {
+            return;
+        }
+
+        self.toggle_pointer();
+        {
+            let buffer = screen(self.visible, &mut self.shadow, &mut self.buffer);
+            let scrollback = self.scrollback.as_mut().unwrap();
+            if old == 0 {
+                scrollback.live.chars = buffer.chars;
+            }
+            scrollback.offset = new;
+
+            // Everything there is, scrollback then live screen, and the
+            // window of it that should show
+            let total = scrollback.lines.len() + BUFFER_HEIGHT;
+            let top = total - BUFFER_HEIGHT - new;
+            for row in 0..BUFFER_HEIGHT {
+                let index = top + row;
+                buffer.chars[row] = if index < scrollback.lines.len() {
+                    scrollback.lines[index]
+                } else {
+                    scrollback.live.chars[index - scrollback.lines.len()]
+                };
+            }
+        }
+        self.toggle_pointer();
+        self.update_cursor();
+    }
+
+    /// Show the live screen again, if looking at the scrollback.
+    fn snap_back(&mut self) {
+        let offset = self.scrollback.as_ref().map_or(0, |s| s.offset);
+        if offset > 0 {
+            self.scroll_view(-(offset as isize));
+        }
+    }
+
+    /// Stop drawing on the screen, and keep drawing in the background.
+    /// Returns the mouse pointer, for the console shown instead.
+    fn hide(&mut self) -> Option<(usize, usize)> {
+        self.snap_back();
+        self.toggle_pointer();
+        let chars = self.buffer().chars;
+        match self.shadow {
+            Some(ref mut shadow) => shadow.chars = chars,
+            None => self.shadow = Some(Box::new(Buffer { chars: chars })),
+        }
+        self.visible = false;
+        self.pointer.take()
+    }
+
+    /// Draw on the screen again, starting with what was drawn in the
+    /// background, with the mouse `pointer` from the console hidden.
+    fn show(&mut self, pointer: Option<(usize, usize)>) {
+        if let Some(ref shadow) = self.shadow {
+            unsafe { self.buffer.get_mut().chars = shadow.chars };
+        }
+        self.visible = true;
+        self.pointer = pointer;
+        self.toggle_pointer();
+        self.update_cursor();
+    }
+
+    /// Move the blinking hardware cursor to where the next character
+    /// goes, or off the screen while looking at the scrollback.
+    fn update_cursor(&self) {
+        if !self.visible {
+            return;
+        }
+        let (row, col) = self.position();
+        let mut offset = (row * BUFFER_WIDTH + col) as u16;
+        if self.scrollback.as_ref().map_or(false, |s| s.offset > 0) {
+            offset = (BUFFER_WIDTH * BUFFER_HEIGHT) as u16;
+        }
+        unsafe {
+            outb(CRTC_INDEX, CRTC_CURSOR_HIGH);
+            outb(CRTC_DATA, (offset >> 8) as u8);
+            outb(CRTC_INDEX, CRTC_CURSOR_LOW);
+            outb(CRTC_DATA, offset as u8);
+        }
+    }
+
+    /// Show the mouse pointer at `cell` (row, column) instead, or hide
+    /// it.
+    pub fn set_pointer(&mut self, cell: Option<(usize, usize)>) {
+        self.toggle_pointer();
+        self.pointer = cell.map(|(row, col)| {
+            (min(row, BUFFER_HEIGHT - 1), min(col, BUFFER_WIDTH - 1))
+        });
+        self.toggle_pointer();
+    }
+
+    /// Show or hide the pointer, by swapping the colours of its cell.
+    fn toggle_pointer(&mut self) {
+        if let Some((row, col)) = self.pointer {
+            let color = &mut self.buffer().chars[row][col].color_code;
+            *color = ColorCode(color.0 << 4 | color.0 >> 4);
+        }
+    }
+
+    /// Write an entire string to screen, possibly clipping it if it
+    /// turns out to be too long. Characters missing from the font show
+    /// as `cp437::REPLACEMENT`.
+    pub fn write_str(&mut self, s: &str) {
+        for c in s.chars() {
+            self.write_byte(cp437::from_char(c).unwrap_or(cp437::REPLACEMENT))
+        }
+    }
+
+    /// The text on `row` of the live screen, without trailing blanks.
+    pub fn row_text(&self, row: usize) -> String {
+        let buffer = match (&self.scrollback, &self.shadow) {
+            (&Some(ref scrollback), _) if scrollback.offset > 0 => &*scrollback.live,
+            (_, &Some(ref shadow)) if !self.visible => &**shadow,
+            _ => unsafe { self.buffer.get() },
+        };
+        let mut text: String = buffer.chars[min(row, BUFFER_HEIGHT - 1)].iter()
+            .map(|cell| cp437::to_char(cell.ascii_character))
+            .collect();
+        let len = text.trim_right().len();
+        text.truncate(len);
+        text
+    }
+
+
+    /// Moves one step left, to the end of the line above if at the start
+    /// of a line. Does nothing in the top left corner.
+    pub fn move_left(&mut self) {
+        self.snap_back();
+        if self.column_position > 0 {
+            self.column_position -= 1;
+        } else if self.row_position > 0 {
+            self.row_position -= 1;
+            self.column_position = BUFFER_WIDTH - 1;
+        }
+        self.update_cursor();
+    }
+
+    /// Internal helper function. Get a mutable reference to the
+    /// buffer. That `unsafe` block is sort of worrying.
+    fn buffer(&mut self) -> &mut Buffer {
+        screen(self.visible, &mut self.shadow, &mut self.buffer)
+    }
+
+    /// Print a simple newline, scrolling if on the bottom row.
+    fn new_line(&mut self) {
+        if self.row_position < BUFFER_HEIGHT - 1 {
+            self.row_position += 1;
+        } else {
+            let top = self.buffer().chars[0];
+            if let Some(ref mut scrollback) = self.scrollback {
+                if scrollback.depth > 0 {
+                    if scrollback.lines.len() == scrollback.depth {
+                        scrollback.lines.pop_front();
+                    }
+                    scrollback.lines.push_back(top);
+                }
+            }
+
+            for row in 0..(BUFFER_HEIGHT-1) {
+                let buffer = self.buffer();
+                buffer.chars[row] = buffer.chars[row + 1]
+            }
+            self.clear_row(BUFFER_HEIGHT-1);
+        }
+        self.column_position = 0;
+    }
+
+    /// Write an entire row of spaces.
+    fn clear_row(&mut self, row: usize) {
+        let blank = self.blank();
+        self.buffer().chars[row] = [blank; BUFFER_WIDTH];
+    }
+
+    /// An empty cell in the current colours.
+    fn blank(&self) -> ScreenChar {
+        ScreenChar {
+            ascii_character: b' ',
+            color_code: self.color_code,
+        }
+    }
+}
+
+/// Make our Writer implement the Writer trait. It just writes a given
+/// set of characters and returns Ok(()).
+impl ::core::fmt::Write for Writer {
+    fn write_str(&mut self, s: &str) -> ::core::fmt::Result {
+        Writer::write_str(self, s);
+        Ok(())
+    }
+}
+
+/// Where a `Writer` draws: the screen itself if `visible`, otherwise
+/// its `shadow` (or the screen, if it has none yet).
+fn screen<'a>(visible: bool, shadow: &'a mut Option<Box<Buffer>>,
+              vga: &'a mut Unique<Buffer>) -> &'a mut Buffer {
+    match *shadow {
+        Some(ref mut shadow) if !visible => &mut **shadow,
+        _ => unsafe { vga.get_mut() },
+    }
+}
+
+/// A writer for a console, all set up but for its shadow buffer.
+macro_rules! console {
+    ($visible:expr) => (Writer {
+        row_position: 0,
+        column_position: 0,
+        color_code: ColorCode::new(DEFAULT_FOREGROUND, DEFAULT_BACKGROUND),
+        foreground: DEFAULT_FOREGROUND as u8,
+        background: DEFAULT_BACKGROUND as u8,
+        bold: false,
+        saved_position: (0, 0),
+        parser: Parser::new(),
+        buffer: unsafe { Unique::new(0xb8000 as *mut _) },
+        shadow: None,
+        visible: $visible,
+        pointer: None,
+        scrollback: None,
+    })
+}
+
+/// This looks kind of scary, but I think what it amounts to is a
+/// spin-locked global static internal writer object for every console,
+/// meaning that anyone calling any of the writer macros below will
+/// automatically use the writer of *their* console (see `writer()`),
+/// which is universally spin-locked for mutual exclusion.
+static WRITERS: [Mutex<Writer>; NUM_CONSOLES] = [
+    Mutex::new(console!(true)), Mutex::new(console!(false)),
+    Mutex::new(console!(false)), Mutex::new(console!(false)),
+    Mutex::new(console!(false)), Mutex::new(console!(false)),
+];
+
+/// The console on the screen.
+static ACTIVE: AtomicUsize = AtomicUsize::new(0);
+
+/// Implement the formatted println macro.
+macro_rules! println {
+    ($fmt:expr) => (print!(concat!($fmt, "\n")));
+    ($fmt:expr, $($arg:tt)*) => (print!(concat!($fmt, "\n"), $($arg)*));
+}
+
+macro_rules! print {
+    ($($arg:tt)*) => ({
+            use core::fmt::Write;
+            $crate::vga_buffer::writer().write_fmt(format_args!($($arg)*)).unwrap();
+    });
+}
+
+/// The writer of console number `n`.
+pub fn console(n: usize) -> MutexGuard<'static, Writer> {
+    WRITERS[n].lock()
+}
+
+/// The writer of the running thread's console.
+pub fn writer() -> MutexGuard<'static, Writer> {
+    console(sched::console())
+}
+
+/// A writer of its own straight onto the screen, for when the consoles
+/// can't be trusted: a panic may have struck with one of them locked.
+///
+/// # Safety
+/// Nothing else may be drawing on the screen while it is in use.
+pub unsafe fn panic_writer() -> Writer {
+    console!(true)
+}
+
+/// The console on the screen.
+pub fn active() -> usize {
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+/// Set up the consoles in the background, each keeping `depth` lines
+/// of scrollback. Needs the heap.
+pub fn init_consoles(depth: usize) {
+    for writer in WRITERS.iter() {
+        let mut writer = writer.lock();
+        if !writer.visible && writer.shadow.is_none() {
+            let blank = writer.blank();
+            writer.shadow = Some(Box::new(Buffer {
+                chars: [[blank; BUFFER_WIDTH]; BUFFER_HEIGHT],
+            }));
+        }
+        writer.enable_scrollback(depth);
+    }
+}
+
+/// Put console `n` on the screen.
+pub fn switch_to(n: usize) {
+    let old = active();
+    if n == old || n >= NUM_CONSOLES {
+        return;
+    }
+    let pointer = WRITERS[old].lock().hide();
+    WRITERS[n].lock().show(pointer);
+    ACTIVE.store(n, Ordering::Relaxed);
+}
+
+/// Helper function: clear the screen, and start over in the top left
+/// corner.
+pub fn clear_screen() {
+    writer().clear_screen();
+}
+
+/// Helper function: Moves the cursor one step left
+pub fn step_left() {
+    writer().move_left();
+}
+
+/// Helper function: the cursor position, as (row, column)
+pub fn position() -> (usize, usize) {
+    writer().position()
+}
+
+/// Helper function: move the cursor to `row` and `col`
+pub fn set_position(row: usize, col: usize) {
+    writer().set_position(row, col);
+}
+
+/// Helper function: blank the cursor's line, and go to its start
+pub fn clear_line() {
+    writer().clear_line();
+}
+
+/// Helper function: blank from the cursor to the end of its line
+pub fn clear_to_end() {
+    writer().clear_to_end();
+}
+
+/// Helper function: the text on `row` of the screen
+pub fn row_text(row: usize) -> String {
+    writer().row_text(row)
+}
+
+/// Helper function: look a screenful further back in the scrollback of
+/// the console on the screen
+pub fn page_up() {
+    console(active()).scroll_view(BUFFER_HEIGHT as isize);
+}
+
+/// Helper function: look a screenful further forward in the scrollback
+/// of the console on the screen
+pub fn page_down() {
+    console(active()).scroll_view(-(BUFFER_HEIGHT as isize));
+}
+
+/// Helper function: the size of the screen, in columns and rows
+pub fn size() -> (usize, usize) {
+    (BUFFER_WIDTH, BUFFER_HEIGHT)
+}
+
+/// Helper function: show the mouse pointer at `cell` (row, column), or
+/// hide it
+pub fn set_pointer(cell: Option<(usize, usize)>) {
+    console(active()).set_pointer(cell);
+}
+
+#[test]
+/// Escape sequences are picked out of the text, with their parameters
+fn parse_escape_sequences() {
+    let mut parser = Parser::new();
+    let mut actions = [None; 4];
+    let mut count = 0;
+    for &byte in b"a\x1b[1;31m\x1b7\x1b[?25l" {
+        if let Some(action) = parser.feed(byte) {
+            actions[count] = Some(action);
+            count += 1;
+        }
+    }
+    assert_eq!(actions, [
+        Some(Action::Print(b'a')),
+        Some(Action::Csi { params: [1, 31, 0, 0], count: 2, private: false, command: b'm' }),
+        Some(Action::Escape(b'7')),
+        Some(Action::Csi { params: [25, 0, 0, 0], count: 1, private: true, command: b'l' }),
+    ]);
+}
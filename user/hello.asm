@@ -0,0 +1,27 @@
+;;; A tiny user program: says hello through the write system call and
+;;; exits. See src/syscall/mod.rs for the calling convention.
+
+%define SYS_WRITE 0
+%define SYS_EXIT 2
+%define STDOUT 1
+
+global _start
+
+section .text
+bits 64
+
+_start:
+        mov rax, SYS_WRITE
+        mov rdi, STDOUT
+        lea rsi, [rel message]
+        mov rdx, message_len
+        syscall
+
+        mov rax, SYS_EXIT
+        mov rdi, 0
+        syscall
+
+section .rodata
+message:
+        db "Hello from user mode!", 10
+message_len equ $ - message